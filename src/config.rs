@@ -1,10 +1,22 @@
 use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 use arc_swap::ArcSwap;
 use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::errors::ConfigError;
+
+#[cfg(feature = "watch")]
+use crate::errors::WatchConfigError;
 
 pub type GlobalString = Lazy<ArcSwap<String>>;
 pub type GlobalStaticStr = Lazy<ArcSwap<&'static str>>;
+pub type GlobalSecret = Lazy<ArcSwap<Secret<String>>>;
 
 pub fn debug_mode() -> bool {
     env::args().nth(1) == Some("debug".into())
@@ -17,3 +29,1161 @@ pub fn env_var_with_default(name: &str, default: &str) -> ArcSwap<String> {
     };
     ArcSwap::from_pointee(val)
 }
+
+/// return env var `name` as a bool, `1/0/true/false/yes/no/on/off` in any case,
+/// if it is unset return `default`, if it is invalid log a warning and return `default`
+pub fn env_bool(name: &str, default: bool) -> bool {
+    let Ok(val) = env::var(name) else {
+        return default;
+    };
+    match val.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => true,
+        "0" | "false" | "no" | "off" => false,
+        _ => {
+            log::warn!(
+                "invalid bool {:?} in env var {}, using default {}",
+                val,
+                name,
+                default
+            );
+            default
+        }
+    }
+}
+
+/// return env var `name` as a duration like `30s`, `5m` or `1h 30m`, see [`humantime`],
+/// if it is unset return `default`, if it is invalid log a warning and return `default`
+pub fn env_duration(name: &str, default: Duration) -> Duration {
+    let Ok(val) = env::var(name) else {
+        return default;
+    };
+    match humantime::parse_duration(val.trim()) {
+        Ok(duration) => duration,
+        Err(e) => {
+            log::warn!(
+                "invalid duration {:?} in env var {}: {}, using default {:?}",
+                val,
+                name,
+                e,
+                default
+            );
+            default
+        }
+    }
+}
+
+/// return env var `name` parsed as `T` like `8080` or `2.5`, if it is unset return `default`,
+/// if it is invalid log a warning and return `default`
+pub fn env_parse_or<T>(name: &str, default: T) -> T
+where
+    T: FromStr + fmt::Debug,
+    T::Err: fmt::Display,
+{
+    let Ok(val) = env::var(name) else {
+        return default;
+    };
+    match val.trim().parse() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            log::warn!(
+                "invalid value {:?} in env var {}: {}, using default {:?}",
+                val,
+                name,
+                e,
+                default
+            );
+            default
+        }
+    }
+}
+
+/// Wrapper of a sensitive value like a password, `Debug` and `Display` print `****` so it
+/// does not leak into logs, use [`Secret::expose`] to get the real value.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "****")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "****")
+    }
+}
+
+/// [`env_var_with_default`] for sensitive values.
+pub fn secret_env_var_with_default(name: &str, default: &str) -> ArcSwap<Secret<String>> {
+    let val = match env::var(name) {
+        Ok(s) => s,
+        Err(_) => default.to_string(),
+    };
+    ArcSwap::from_pointee(Secret(val))
+}
+
+/// What [`expand_env_with`] does with a reference to an unset env var.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingVar {
+    /// replace it with an empty string, like a shell does
+    #[default]
+    Empty,
+    /// leave the reference as is
+    Keep,
+}
+
+/// Replace `${VAR}` and `$VAR` references in `input` with env var values, unset vars become
+/// empty. `$$` is a literal `$`, and braces may nest: `${DB_${APP_ENV}}`.
+///
+/// ```rust,ignore
+/// let data_dir = expand_env("${HOME}/data/$APP_ENV");
+/// ```
+pub fn expand_env(input: &str) -> String {
+    expand_env_with(input, MissingVar::Empty)
+}
+
+/// [`expand_env`] with the handling of unset env vars chosen by `missing`.
+pub fn expand_env_with(input: &str, missing: MissingVar) -> String {
+    let mut expanded = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            match matching_brace(braced) {
+                Some(end) => {
+                    let name = expand_env_with(&braced[..end], missing);
+                    push_env_var(&mut expanded, &name, &rest[pos..pos + end + 3], missing);
+                    rest = &braced[end + 1..];
+                }
+                // unterminated `${`, keep the rest as is
+                None => {
+                    expanded.push_str(&rest[pos..]);
+                    rest = "";
+                }
+            }
+        } else {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            if len == 0 {
+                expanded.push('$');
+            } else {
+                push_env_var(
+                    &mut expanded,
+                    &after[..len],
+                    &rest[pos..pos + len + 1],
+                    missing,
+                );
+            }
+            rest = &after[len..];
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+fn matching_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn push_env_var(expanded: &mut String, name: &str, reference: &str, missing: MissingVar) {
+    match (env::var(name), missing) {
+        (Ok(val), _) => expanded.push_str(&val),
+        (Err(_), MissingVar::Empty) => {}
+        (Err(_), MissingVar::Keep) => expanded.push_str(reference),
+    }
+}
+
+/// Build a typed config from layered sources, later layers win:
+///
+/// 1. the `default` value given to [`ConfigBuilder::new`]
+/// 2. the JSON files added with [`ConfigBuilder::file`], in the order they were added
+/// 3. env vars starting with the [`ConfigBuilder::env_prefix`]
+///
+/// Objects are merged key by key, any other value is replaced as a whole. An env var
+/// `APP_DB__HOST` overrides the field `db.host` with the prefix `APP`, names are matched
+/// lowercased. Its value is parsed as JSON unless the overridden value is a string, so
+/// `APP_DB__PORT=5433` sets a number and `APP_DB__PASSWORD=123` keeps a string. A field
+/// without a value, like a `None` option, gets the env value as a string, and the values of
+/// such fields are parsed as JSON only if the config does not deserialize with strings.
+///
+/// ```rust,ignore
+/// let config: AppConfig = ConfigBuilder::new(AppConfig::default())
+///     .file("/etc/app/config.json")
+///     .env_prefix("APP")
+///     .build()?;
+/// ```
+pub struct ConfigBuilder<T> {
+    default: T,
+    files: Vec<(PathBuf, bool)>,
+    env_prefix: Option<String>,
+}
+
+impl<T> ConfigBuilder<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(default: T) -> Self {
+        ConfigBuilder {
+            default,
+            files: Vec::new(),
+            env_prefix: None,
+        }
+    }
+
+    /// Layer the JSON file at `path`, [`ConfigBuilder::build`] fails if it does not exist.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push((path.into(), true));
+        self
+    }
+
+    /// [`ConfigBuilder::file`] skipped if the file does not exist.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn optional_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push((path.into(), false));
+        self
+    }
+
+    /// Layer the env vars named `{prefix}_{FIELD}`, nested fields separated by `__`.
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn build(self) -> Result<T, ConfigError> {
+        let mut merged = serde_json::to_value(&self.default)
+            .map_err(|e| ConfigError::Load(format!("serialize default config failed: {}", e)))?;
+        for (path, required) in &self.files {
+            let content = match std::fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(ConfigError::Load(format!(
+                        "read config file {:?} failed: {}",
+                        path, e
+                    )))
+                }
+            };
+            let layer = serde_json::from_str(&content).map_err(|e| {
+                ConfigError::Load(format!("parse config file {:?} failed: {}", path, e))
+            })?;
+            merge_json(&mut merged, layer);
+        }
+        let mut vars = Vec::new();
+        if let Some(prefix) = &self.env_prefix {
+            let prefix = format!("{}_", prefix);
+            vars = env::vars()
+                .filter_map(|(k, v)| k.strip_prefix(&prefix).map(|k| (k.to_lowercase(), v)))
+                .collect();
+            vars.sort();
+        }
+        let with_env = |parse_nulls: bool| {
+            let mut config = merged.clone();
+            for (key, val) in &vars {
+                let path: Vec<_> = key.split("__").collect();
+                override_json(&mut config, &path, val.clone(), parse_nulls);
+            }
+            config
+        };
+        serde_json::from_value(with_env(false))
+            .or_else(|e| serde_json::from_value(with_env(true)).map_err(|_| e))
+            .map_err(|e| ConfigError::Load(format!("invalid config: {}", e)))
+    }
+}
+
+/// return the JSON config file at `path` encrypted at rest with
+/// [`crate::crypto::encrypt_by_key`], decrypted with `key` and deserialized, if the file can
+/// not be read, decrypted or parsed return [`ConfigError::Load`]
+///
+/// ```rust,ignore
+/// let config: AppConfig = load_encrypted("/etc/app/config.json.enc", &key)?;
+/// ```
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+pub fn load_encrypted<T: DeserializeOwned>(
+    path: impl AsRef<std::path::Path>,
+    key: &str,
+) -> Result<T, ConfigError> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| ConfigError::Load(format!("read config file {:?} failed: {}", path, e)))?;
+    let json = crate::crypto::decrypt_by_key_with_error(content.trim().to_string(), key)
+        .map_err(|e| ConfigError::Load(format!("decrypt config file {:?} failed: {}", path, e)))?;
+    serde_json::from_str(&json)
+        .map_err(|e| ConfigError::Load(format!("parse config file {:?} failed: {}", path, e)))
+}
+
+/// [`load_encrypted`] with the key read from env var `env_var`
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+pub fn load_encrypted_from_env<T: DeserializeOwned>(
+    path: impl AsRef<std::path::Path>,
+    env_var: &str,
+) -> Result<T, ConfigError> {
+    let key = crate::crypto::key_from_env(env_var).map_err(|e| ConfigError::Load(e.to_string()))?;
+    load_encrypted(path, &key)
+}
+
+/// Prefix of the values encrypted by [`encrypt_env_file`].
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+pub const ENCRYPTED_ENV_PREFIX: &str = "ENC:";
+
+/// Write the `.env` file `src` to `dst` with every `KEY=value` value encrypted with `key` as
+/// `KEY=ENC:...`, comments, blank lines, `export` prefixes and key names are kept so the file
+/// can be committed and diffed. A value is encrypted as written, quotes included, and the same
+/// value always gives the same ciphertext, so only changed values show up in a diff. A quoted
+/// value may span lines. Values already encrypted with `key` are kept, if a plaintext value
+/// starts with [`ENCRYPTED_ENV_PREFIX`] or error, return Err.
+///
+/// ```rust,ignore
+/// encrypt_env_file(".env", ".env.enc", &key_from_env("ENV_KEY")?)?;
+/// decrypt_env_file(".env.enc", ".env", &key_from_env("ENV_KEY")?)?;
+/// ```
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+pub fn encrypt_env_file(
+    src: impl AsRef<std::path::Path>,
+    dst: impl AsRef<std::path::Path>,
+    key: &str,
+) -> Result<(), ConfigError> {
+    map_env_file(src.as_ref(), dst.as_ref(), |name, value| {
+        if let Some(encrypted) = value.strip_prefix(ENCRYPTED_ENV_PREFIX) {
+            // would be decrypted as is otherwise
+            crate::crypto::decrypt_by_key_with_error(encrypted.to_string(), key).map_err(|_| {
+                ConfigError::Invalid {
+                    key: name.to_string(),
+                    details: format!(
+                        "value starts with {} but is not encrypted with the key",
+                        ENCRYPTED_ENV_PREFIX
+                    ),
+                }
+            })?;
+            return Ok(value.to_string());
+        }
+        let encrypted = crate::crypto::encrypt_by_key(value.to_string(), key);
+        Ok(format!("{}{}", ENCRYPTED_ENV_PREFIX, encrypted))
+    })
+}
+
+/// Write the `.env` file `src` encrypted by [`encrypt_env_file`] to `dst` with its values
+/// decrypted with `key`, if a value does not decrypt return [`ConfigError::Invalid`] with its
+/// key name.
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+pub fn decrypt_env_file(
+    src: impl AsRef<std::path::Path>,
+    dst: impl AsRef<std::path::Path>,
+    key: &str,
+) -> Result<(), ConfigError> {
+    map_env_file(src.as_ref(), dst.as_ref(), |name, value| {
+        let Some(encrypted) = value.strip_prefix(ENCRYPTED_ENV_PREFIX) else {
+            return Ok(value.to_string());
+        };
+        crate::crypto::decrypt_by_key_with_error(encrypted.to_string(), key).map_err(|e| {
+            ConfigError::Invalid {
+                key: name.to_string(),
+                details: format!("decrypt value failed: {}", e),
+            }
+        })
+    })
+}
+
+/// Write `src` to `dst` with the non-empty value of every `KEY=value` line replaced by
+/// `map(KEY, value)`, the other lines and the line endings are kept as is. A quoted value
+/// continues on the next lines until its closing quote, if it is not closed return
+/// [`ConfigError::Invalid`].
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+fn map_env_file<F>(
+    src: &std::path::Path,
+    dst: &std::path::Path,
+    mut map: F,
+) -> Result<(), ConfigError>
+where
+    F: FnMut(&str, &str) -> Result<String, ConfigError>,
+{
+    let content = std::fs::read_to_string(src)
+        .map_err(|e| ConfigError::Load(format!("read env file {:?} failed: {}", src, e)))?;
+    let mut mapped = String::with_capacity(content.len());
+    let mut lines = content.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        let body = line.trim_end_matches(['\r', '\n']);
+        let mut ending = &line[body.len()..];
+        match body.split_once('=') {
+            Some((name, value)) if !body.trim_start().starts_with('#') && !value.is_empty() => {
+                let key = name.trim();
+                let key = key.strip_prefix("export ").unwrap_or(key).trim();
+                let mut value = value.to_string();
+                while !quote_closed(&value) {
+                    let Some(next) = lines.next() else {
+                        return Err(ConfigError::Invalid {
+                            key: key.to_string(),
+                            details: "unterminated quoted value".to_string(),
+                        });
+                    };
+                    let next_body = next.trim_end_matches(['\r', '\n']);
+                    value.push_str(ending);
+                    value.push_str(next_body);
+                    ending = &next[next_body.len()..];
+                }
+                mapped.push_str(name);
+                mapped.push('=');
+                mapped.push_str(&map(key, &value)?);
+            }
+            _ => mapped.push_str(body),
+        }
+        mapped.push_str(ending);
+    }
+    std::fs::write(dst, mapped)
+        .map_err(|e| ConfigError::Load(format!("write env file {:?} failed: {}", dst, e)))
+}
+
+/// return false if `value` opens a quote it does not close, a `\` escapes the next character in
+/// double quotes
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+fn quote_closed(value: &str) -> bool {
+    let mut chars = value.trim_start().chars();
+    let quote = match chars.next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => return true,
+    };
+    let mut escaped = false;
+    for c in chars {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote == '"' => escaped = true,
+            c if c == quote => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Collect every invalid setting before failing, so operators fix them all in one go instead of
+/// one per restart.
+///
+/// ```rust,ignore
+/// let mut v = ConfigValidator::new();
+/// let db_url = v.require_env("DB_URL");
+/// let port: Option<u16> = v.env_parse("PORT");
+/// let level: Option<tracing::Level> = v.env_parse("LOG_LEVEL");
+/// v.finish()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct ConfigValidator {
+    errors: Vec<ConfigError>,
+}
+
+impl ConfigValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the value of `key` is invalid.
+    pub fn error(&mut self, key: &str, details: impl Into<String>) {
+        self.errors.push(ConfigError::Invalid {
+            key: key.to_string(),
+            details: details.into(),
+        });
+    }
+
+    /// Record `details` for `key` unless `valid`, return `valid`.
+    pub fn check(&mut self, key: &str, valid: bool, details: impl Into<String>) -> bool {
+        if !valid {
+            self.error(key, details);
+        }
+        valid
+    }
+
+    /// return env var `name`, if it is missing or empty, record an error and return None
+    pub fn require_env(&mut self, name: &str) -> Option<String> {
+        match env::var(name) {
+            Ok(val) if !val.is_empty() => Some(val),
+            _ => {
+                self.error(name, "missing");
+                None
+            }
+        }
+    }
+
+    /// return env var `name` parsed as `T`, if it is missing or invalid, record an error and
+    /// return None
+    pub fn env_parse<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let val = self.require_env(name)?;
+        match val.trim().parse() {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.error(name, format!("invalid value {:?}: {}", val, e));
+                None
+            }
+        }
+    }
+
+    /// return env var `name` as a duration like [`env_duration`], if it is missing or invalid,
+    /// record an error and return None
+    pub fn env_duration(&mut self, name: &str) -> Option<Duration> {
+        let val = self.require_env(name)?;
+        match humantime::parse_duration(val.trim()) {
+            Ok(duration) => Some(duration),
+            Err(e) => {
+                self.error(name, format!("invalid duration {:?}: {}", val, e));
+                None
+            }
+        }
+    }
+
+    /// return the errors recorded so far
+    pub fn errors(&self) -> &[ConfigError] {
+        &self.errors
+    }
+
+    /// return Ok if nothing was recorded, else all the errors in [`ConfigError::Multiple`]
+    pub fn finish(self) -> Result<(), ConfigError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Multiple(self.errors))
+        }
+    }
+}
+
+fn merge_json(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (k, v) in layer {
+                match base.get_mut(&k) {
+                    Some(existing) => merge_json(existing, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// set the value at `path` in `base` to `val`, parsed as JSON unless the value it replaces is
+/// a string, or is missing and not `parse_nulls`
+fn override_json(base: &mut Value, path: &[&str], val: String, parse_nulls: bool) {
+    let Some((key, rest)) = path.split_first() else {
+        *base = match base {
+            Value::String(_) => Value::String(val),
+            Value::Null if !parse_nulls => Value::String(val),
+            _ => serde_json::from_str(&val).unwrap_or(Value::String(val)),
+        };
+        return;
+    };
+    if !base.is_object() {
+        *base = Value::Object(Map::new());
+    }
+    if let Value::Object(map) = base {
+        override_json(
+            map.entry(key.to_string()).or_insert(Value::Null),
+            rest,
+            val,
+            parse_nulls,
+        );
+    }
+}
+
+/// Keeps watching a config file, see [`watch_config`]. Watching stops when it is dropped.
+#[cfg(feature = "watch")]
+pub struct ConfigWatcher {
+    _watcher: notify::RecommendedWatcher,
+}
+
+/// How long a config file has to stay unchanged before it is reloaded, so editors writing a
+/// file in several steps trigger only one reload.
+#[cfg(feature = "watch")]
+pub const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Call `on_change` with the new content of the file at `path` each time it changes on disk.
+/// Keep the returned watcher alive as long as reloading is wanted.
+///
+/// ```rust,ignore
+/// static LOG_LEVEL: GlobalString = Lazy::new(|| ArcSwap::from_pointee("info".to_string()));
+/// let _watcher = watch_config("/etc/app/log_level", |new| {
+///     LOG_LEVEL.store(Arc::new(new.trim().to_string()))
+/// })?;
+/// ```
+#[cfg(feature = "watch")]
+pub fn watch_config<F>(
+    path: impl AsRef<std::path::Path>,
+    on_change: F,
+) -> Result<ConfigWatcher, WatchConfigError>
+where
+    F: Fn(String) + Send + 'static,
+{
+    use notify::{RecursiveMode, Watcher};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+
+    let path = std::env::current_dir()
+        .map(|cwd| cwd.join(path.as_ref()))
+        .map_err(|e| WatchConfigError {
+            details: format!("invalid config path {:?}: {}", path.as_ref(), e),
+        })?;
+    // watch the parent directory, editors often replace the file instead of writing into it
+    let dir = match path.parent() {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            return Err(WatchConfigError {
+                details: format!("config path {:?} has no parent directory", path),
+            })
+        }
+    };
+    let (tx, rx) = channel::<notify::Result<notify::Event>>();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| WatchConfigError {
+        details: format!("create config watcher failed: {}", e),
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(|e| WatchConfigError {
+            details: format!("watch config directory {:?} failed: {}", dir, e),
+        })?;
+
+    std::thread::spawn(move || {
+        // the loop ends when the watcher is dropped and the channel disconnected
+        while let Ok(event) = rx.recv() {
+            match event {
+                Ok(event) if !event.kind.is_access() && event.paths.contains(&path) => {}
+                _ => continue,
+            }
+            loop {
+                match rx.recv_timeout(WATCH_DEBOUNCE) {
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(content) => on_change(content),
+                Err(e) => log::warn!("reload config {:?} failed: {}", path, e),
+            }
+        }
+    });
+    Ok(ConfigWatcher { _watcher: watcher })
+}
+
+/// [`watch_config`] storing the value `parse`d from the new content into `target`, a content
+/// failing to parse is logged and the previous value kept.
+#[cfg(feature = "watch")]
+pub fn watch_config_into<T, E, F>(
+    path: impl AsRef<std::path::Path>,
+    target: std::sync::Arc<ArcSwap<T>>,
+    parse: F,
+) -> Result<ConfigWatcher, WatchConfigError>
+where
+    T: Send + Sync + 'static,
+    E: fmt::Display,
+    F: Fn(&str) -> Result<T, E> + Send + 'static,
+{
+    watch_config(path, move |content| match parse(&content) {
+        Ok(value) => target.store(std::sync::Arc::new(value)),
+        Err(e) => log::warn!(
+            "parse reloaded config failed, keep the previous value: {}",
+            e
+        ),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::time::Duration;
+
+    use crate::config::{
+        env_bool, env_duration, env_parse_or, expand_env, expand_env_with,
+        secret_env_var_with_default, ConfigValidator, MissingVar, Secret,
+    };
+
+    #[test]
+    fn secret_test() {
+        let secret = Secret::new("hunter2".to_string());
+        assert_eq!(format!("{:?}", secret), "****");
+        assert_eq!(format!("{}", secret), "****");
+        assert_eq!(secret.expose(), "hunter2");
+
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct DbConfig {
+            user: String,
+            password: Secret<String>,
+        }
+        let config = DbConfig {
+            user: "foo".to_string(),
+            password: secret,
+        };
+        let printed = format!("{:?}", config);
+        assert!(printed.contains("****"));
+        assert!(!printed.contains("hunter2"));
+
+        env::set_var("BUSYLIB_SECRET_PASSWORD", "s3cret");
+        let password = secret_env_var_with_default("BUSYLIB_SECRET_PASSWORD", "");
+        assert_eq!(password.load().expose(), "s3cret");
+        assert_eq!(format!("{:?}", password.load()), "****");
+    }
+
+    #[test]
+    fn env_bool_test() {
+        let name = "BUSYLIB_ENV_BOOL";
+        for (val, expected) in [
+            ("1", true),
+            ("true", true),
+            ("TRUE", true),
+            ("Yes", true),
+            ("0", false),
+            ("false", false),
+            ("False", false),
+            ("NO", false),
+            ("on", true),
+            ("OFF", false),
+        ] {
+            env::set_var(name, val);
+            assert_eq!(env_bool(name, !expected), expected, "{}", val);
+        }
+
+        env::set_var(name, "maybe");
+        assert!(env_bool(name, true));
+        assert!(!env_bool(name, false));
+
+        env::remove_var(name);
+        assert!(env_bool(name, true));
+    }
+
+    #[test]
+    fn env_duration_test() {
+        let name = "BUSYLIB_ENV_DURATION";
+        let default = Duration::from_secs(7);
+        for (val, expected) in [
+            ("30s", Duration::from_secs(30)),
+            ("5m", Duration::from_secs(300)),
+            ("1h", Duration::from_secs(3600)),
+            ("1h 30m", Duration::from_secs(5400)),
+            ("250ms", Duration::from_millis(250)),
+        ] {
+            env::set_var(name, val);
+            assert_eq!(env_duration(name, default), expected, "{}", val);
+        }
+
+        for val in ["30", "soon", ""] {
+            env::set_var(name, val);
+            assert_eq!(env_duration(name, default), default, "{}", val);
+        }
+
+        env::remove_var(name);
+        assert_eq!(env_duration(name, default), default);
+    }
+
+    #[test]
+    fn env_parse_or_test() {
+        let name = "BUSYLIB_ENV_PARSE_OR";
+        env::set_var(name, " 8080 ");
+        assert_eq!(env_parse_or(name, 80u16), 8080);
+        env::set_var(name, "2.5");
+        assert_eq!(env_parse_or(name, 10.0), 2.5);
+        for val in ["http", "-1", "70000", ""] {
+            env::set_var(name, val);
+            assert_eq!(env_parse_or(name, 80u16), 80, "{}", val);
+        }
+        env::remove_var(name);
+        assert_eq!(env_parse_or(name, 80u16), 80);
+    }
+
+    #[test]
+    fn config_validator_test() {
+        use crate::errors::ConfigError;
+
+        env::remove_var("BUSYLIB_VALIDATE_DB_URL");
+        env::set_var("BUSYLIB_VALIDATE_PORT", "http");
+        env::set_var("BUSYLIB_VALIDATE_LOG_LEVEL", "loud");
+        env::set_var("BUSYLIB_VALIDATE_WORKERS", "8");
+        env::set_var("BUSYLIB_VALIDATE_TIMEOUT", "30s");
+
+        let mut v = ConfigValidator::new();
+        assert_eq!(v.require_env("BUSYLIB_VALIDATE_DB_URL"), None);
+        assert_eq!(v.env_parse::<u16>("BUSYLIB_VALIDATE_PORT"), None);
+        assert_eq!(
+            v.env_parse::<tracing::Level>("BUSYLIB_VALIDATE_LOG_LEVEL"),
+            None
+        );
+        let workers: Option<u32> = v.env_parse("BUSYLIB_VALIDATE_WORKERS");
+        assert_eq!(workers, Some(8));
+        assert_eq!(
+            v.env_duration("BUSYLIB_VALIDATE_TIMEOUT"),
+            Some(Duration::from_secs(30))
+        );
+        assert!(!v.check("workers", workers.unwrap() > 8, "must be more than 8"));
+        assert_eq!(v.errors().len(), 4);
+
+        let err = v.finish().unwrap_err();
+        let ConfigError::Multiple(errors) = &err else {
+            panic!("expected ConfigError::Multiple, got {:?}", err);
+        };
+        let keys: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                ConfigError::Invalid { key, .. } => key.as_str(),
+                _ => panic!("expected ConfigError::Invalid, got {:?}", e),
+            })
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "BUSYLIB_VALIDATE_DB_URL",
+                "BUSYLIB_VALIDATE_PORT",
+                "BUSYLIB_VALIDATE_LOG_LEVEL",
+                "workers"
+            ]
+        );
+        let msg = err.to_string();
+        assert!(msg.starts_with("4 config errors: BUSYLIB_VALIDATE_DB_URL: missing; "));
+        assert!(msg.contains("BUSYLIB_VALIDATE_PORT: invalid value \"http\""));
+
+        assert!(ConfigValidator::new().finish().is_ok());
+    }
+
+    #[test]
+    fn expand_env_test() {
+        env::set_var("BUSYLIB_EXPAND_HOME", "/home/foo");
+        env::set_var("BUSYLIB_EXPAND_ENV", "prod");
+        env::set_var("BUSYLIB_EXPAND_DB_prod", "db.prod");
+        env::remove_var("BUSYLIB_EXPAND_MISSING");
+
+        assert_eq!(
+            expand_env("${BUSYLIB_EXPAND_HOME}/data/$BUSYLIB_EXPAND_ENV.log"),
+            "/home/foo/data/prod.log"
+        );
+        // nested braces
+        assert_eq!(
+            expand_env("${BUSYLIB_EXPAND_DB_${BUSYLIB_EXPAND_ENV}}:5432"),
+            "db.prod:5432"
+        );
+        assert_eq!(expand_env("{${BUSYLIB_EXPAND_ENV}}"), "{prod}");
+
+        // missing vars
+        assert_eq!(
+            expand_env("a${BUSYLIB_EXPAND_MISSING}b$BUSYLIB_EXPAND_MISSING"),
+            "ab"
+        );
+        assert_eq!(
+            expand_env_with(
+                "a${BUSYLIB_EXPAND_MISSING}b$BUSYLIB_EXPAND_MISSING",
+                MissingVar::Keep
+            ),
+            "a${BUSYLIB_EXPAND_MISSING}b$BUSYLIB_EXPAND_MISSING"
+        );
+
+        // escaped and lone dollars
+        assert_eq!(
+            expand_env("cost: $$5, $BUSYLIB_EXPAND_ENV$$"),
+            "cost: $5, prod$"
+        );
+        assert_eq!(expand_env("$$BUSYLIB_EXPAND_ENV"), "$BUSYLIB_EXPAND_ENV");
+        assert_eq!(expand_env("a $ b $"), "a $ b $");
+        assert_eq!(expand_env("${BUSYLIB_EXPAND_ENV"), "${BUSYLIB_EXPAND_ENV");
+    }
+
+    #[test]
+    fn config_builder_test() {
+        use serde::{Deserialize, Serialize};
+
+        use crate::config::ConfigBuilder;
+
+        #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+        struct Db {
+            host: String,
+            port: u16,
+            password: String,
+        }
+
+        #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+        struct AppConfig {
+            name: String,
+            workers: u32,
+            db: Db,
+        }
+
+        let default = || AppConfig {
+            name: "default".to_string(),
+            workers: 1,
+            db: Db {
+                host: "localhost".to_string(),
+                port: 5432,
+                password: String::new(),
+            },
+        };
+        let dir = env::temp_dir().join(format!("busylib_config_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"name": "file", "workers": 4, "db": {"host": "db.file"}}"#,
+        )
+        .unwrap();
+
+        env::set_var("BUSYLIB_CFG_WORKERS", "8");
+        env::set_var("BUSYLIB_CFG_DB__PASSWORD", "123");
+        let config = ConfigBuilder::new(default())
+            .file(&path)
+            .optional_file(dir.join("missing.json"))
+            .env_prefix("BUSYLIB_CFG")
+            .build()
+            .unwrap();
+        assert_eq!(
+            config,
+            AppConfig {
+                // file overrides default
+                name: "file".to_string(),
+                // env overrides file
+                workers: 8,
+                db: Db {
+                    host: "db.file".to_string(),
+                    // default kept by the partial db object of the file
+                    port: 5432,
+                    password: "123".to_string(),
+                },
+            }
+        );
+
+        assert!(ConfigBuilder::new(default())
+            .file(dir.join("missing.json"))
+            .build()
+            .is_err());
+        env::set_var("BUSYLIB_CFG_BAD_WORKERS", "many");
+        let err = ConfigBuilder::new(default())
+            .env_prefix("BUSYLIB_CFG_BAD")
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("invalid config"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+        struct OptionalConfig {
+            token: Option<String>,
+            flag: Option<String>,
+            retries: Option<u32>,
+        }
+
+        env::set_var("BUSYLIB_CFG_OPT_TOKEN", "123");
+        env::set_var("BUSYLIB_CFG_OPT_FLAG", "true");
+        let config = ConfigBuilder::new(OptionalConfig::default())
+            .env_prefix("BUSYLIB_CFG_OPT")
+            .build()
+            .unwrap();
+        assert_eq!(config.token.as_deref(), Some("123"));
+        assert_eq!(config.flag.as_deref(), Some("true"));
+        assert_eq!(config.retries, None);
+        // parsed when the strings do not deserialize
+        env::set_var("BUSYLIB_CFG_OPT_NUM_RETRIES", "3");
+        let config = ConfigBuilder::new(OptionalConfig::default())
+            .env_prefix("BUSYLIB_CFG_OPT_NUM")
+            .build()
+            .unwrap();
+        assert_eq!(config.retries, Some(3));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn load_encrypted_test() {
+        use crate::config::{load_encrypted, load_encrypted_from_env};
+        use crate::errors::ConfigError;
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct AppConfig {
+            name: String,
+            workers: u32,
+        }
+
+        let dir = env::temp_dir().join(format!("busylib_encrypted_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json.enc");
+        let encrypted = crate::crypto::encrypt_by_key(
+            r#"{"name": "secret", "workers": 4}"#.to_string(),
+            "config key",
+        );
+        std::fs::write(&path, format!("{}\n", encrypted)).unwrap();
+
+        let expected = AppConfig {
+            name: "secret".to_string(),
+            workers: 4,
+        };
+        let config: AppConfig = load_encrypted(&path, "config key").unwrap();
+        assert_eq!(config, expected);
+        env::set_var("BUSYLIB_CONFIG_KEY", "config key");
+        let config: AppConfig = load_encrypted_from_env(&path, "BUSYLIB_CONFIG_KEY").unwrap();
+        assert_eq!(config, expected);
+
+        let err = load_encrypted::<AppConfig>(&path, "wrong key").unwrap_err();
+        assert!(matches!(err, ConfigError::Load(_)));
+        assert!(
+            err.to_string().starts_with("decrypt config file"),
+            "{}",
+            err
+        );
+        assert!(load_encrypted::<AppConfig>(dir.join("missing"), "config key").is_err());
+        assert!(load_encrypted_from_env::<AppConfig>(&path, "BUSYLIB_CONFIG_KEY_UNSET").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn env_file_test() {
+        use crate::config::{decrypt_env_file, encrypt_env_file};
+        use crate::errors::ConfigError;
+
+        let dir = env::temp_dir().join(format!("busylib_env_file_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let env_path = dir.join(".env");
+        let enc_path = dir.join(".env.enc");
+        let dec_path = dir.join(".env.dec");
+        let env = "# database\n\
+                   DB_HOST=localhost\n\
+                   export DB_PASSWORD=\"p@ss=word # not a comment\"\n\
+                   \n\
+                   API_TOKEN='abc123'\r\n\
+                   EMPTY=\n\
+                   \x20 # indented comment\n\
+                   NOT_AN_ASSIGNMENT\n\
+                   LAST=no newline";
+        std::fs::write(&env_path, env).unwrap();
+
+        encrypt_env_file(&env_path, &enc_path, "env key").unwrap();
+        let encrypted = std::fs::read_to_string(&enc_path).unwrap();
+        let lines: Vec<_> = encrypted.split('\n').collect();
+        assert_eq!(lines.len(), env.split('\n').count());
+        assert_eq!(lines[0], "# database");
+        assert!(lines[1].starts_with("DB_HOST=ENC:"));
+        assert!(lines[2].starts_with("export DB_PASSWORD=ENC:"));
+        assert_eq!(lines[3], "");
+        assert!(lines[4].starts_with("API_TOKEN=ENC:") && lines[4].ends_with('\r'));
+        assert_eq!(
+            &lines[5..8],
+            ["EMPTY=", "  # indented comment", "NOT_AN_ASSIGNMENT"]
+        );
+        assert!(!encrypted.contains("p@ss") && !encrypted.contains("abc123"));
+
+        // same values, same ciphertexts
+        encrypt_env_file(&env_path, &dec_path, "env key").unwrap();
+        assert_eq!(std::fs::read_to_string(&dec_path).unwrap(), encrypted);
+        // already encrypted values are kept
+        encrypt_env_file(&enc_path, &dec_path, "env key").unwrap();
+        assert_eq!(std::fs::read_to_string(&dec_path).unwrap(), encrypted);
+
+        decrypt_env_file(&enc_path, &dec_path, "env key").unwrap();
+        assert_eq!(std::fs::read_to_string(&dec_path).unwrap(), env);
+
+        let err = decrypt_env_file(&enc_path, &dec_path, "wrong key").unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::Invalid { key, .. } if key == "DB_HOST"),
+            "{}",
+            err
+        );
+        assert!(encrypt_env_file(dir.join("missing"), &dec_path, "env key").is_err());
+
+        // quoted values spanning lines are encrypted whole
+        let env = "CERT=\"-----BEGIN-----\nMII=abc\\\"\n-----END-----\"\nNEXT=1\n";
+        std::fs::write(&env_path, env).unwrap();
+        encrypt_env_file(&env_path, &enc_path, "env key").unwrap();
+        let encrypted = std::fs::read_to_string(&enc_path).unwrap();
+        assert_eq!(encrypted.lines().count(), 2);
+        assert!(!encrypted.contains("MII") && !encrypted.contains("END"));
+        decrypt_env_file(&enc_path, &dec_path, "env key").unwrap();
+        assert_eq!(std::fs::read_to_string(&dec_path).unwrap(), env);
+
+        std::fs::write(&env_path, "CERT='-----BEGIN-----\nMII=abc\n").unwrap();
+        let err = encrypt_env_file(&env_path, &enc_path, "env key").unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::Invalid { key, .. } if key == "CERT"),
+            "{}",
+            err
+        );
+
+        // a plaintext value that looks encrypted is not left in clear text
+        std::fs::write(&env_path, "PASSWORD=ENC:hunter2\n").unwrap();
+        let err = encrypt_env_file(&env_path, &enc_path, "env key").unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::Invalid { key, .. } if key == "PASSWORD"),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watch_config_test() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        use arc_swap::ArcSwap;
+
+        use crate::config::{watch_config, watch_config_into};
+
+        let dir = env::temp_dir().join(format!("busylib_watch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.conf");
+        std::fs::write(&path, "1").unwrap();
+
+        let reloads = Arc::new(Mutex::new(Vec::new()));
+        let received = reloads.clone();
+        let _watcher = watch_config(&path, move |new| received.lock().unwrap().push(new)).unwrap();
+        let level = Arc::new(ArcSwap::from_pointee(1u32));
+        let _level_watcher =
+            watch_config_into(&path, level.clone(), |s| s.trim().parse::<u32>()).unwrap();
+
+        // rapid successive writes are debounced into one reload
+        std::thread::sleep(Duration::from_millis(100));
+        std::fs::write(&path, "2").unwrap();
+        std::fs::write(&path, "3").unwrap();
+        for _ in 0..50 {
+            if !reloads.lock().unwrap().is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(*reloads.lock().unwrap(), vec!["3".to_string()]);
+        assert_eq!(**level.load(), 3);
+
+        // invalid content keeps the previous value
+        std::fs::write(&path, "not a number").unwrap();
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(**level.load(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
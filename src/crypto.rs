@@ -1,7 +1,33 @@
-use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+#[cfg(feature = "aead")]
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use std::borrow::Cow;
+use std::io::Write;
+#[cfg(feature = "hash")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::errors::DecryptError;
-use crate::prelude::EnhancedUnwrap;
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    read::DecoderReader,
+    Engine,
+};
+#[cfg(feature = "hash")]
+use hmac::{Hmac, Mac};
+use magic_crypt::{new_magic_crypt, MagicCrypt256, MagicCryptError, MagicCryptTrait};
+#[cfg(feature = "crypto-serde")]
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "hash")]
+use sha2::Sha512;
+#[cfg(any(feature = "aead", feature = "hash"))]
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+#[cfg(feature = "hash")]
+use crate::errors::TokenError;
+use crate::errors::{DecryptError, DecryptErrorKind, EncryptError, KeyError};
+use crate::prelude::{EnhancedExpect, EnhancedUnwrap};
 
 /// return encrypted string in base64
 pub fn encrypt_by_key(value: String, key: &str) -> String {
@@ -12,13 +38,13 @@ pub fn encrypt_by_key(value: String, key: &str) -> String {
 /// return decrypted string from base64
 pub fn decrypt_by_key(value: String, key: &str) -> String {
     let mc = new_magic_crypt!(key, 256);
-    mc.decrypt_base64_to_string(value).unwp()
+    mc.decrypt_base64_to_string(pad_base64(&value)).unwp()
 }
 
 /// return decrypted string from base64, if error, return default
 pub fn decrypt_by_key_with_default(value: String, key: &str, default: &str) -> String {
     let mc = new_magic_crypt!(key, 256);
-    let decrypted_result = mc.decrypt_base64_to_string(value);
+    let decrypted_result = mc.decrypt_base64_to_string(pad_base64(&value));
     match decrypted_result {
         Ok(decrypted_result) => decrypted_result,
         Err(_) => default.to_string(),
@@ -28,17 +54,1034 @@ pub fn decrypt_by_key_with_default(value: String, key: &str, default: &str) -> S
 /// return decrypted result from base64, if error, return Err
 pub fn decrypt_by_key_with_error(value: String, key: &str) -> Result<String, DecryptError> {
     let mc = new_magic_crypt!(key, 256);
-    let decrypted_result = mc.decrypt_base64_to_string(value);
+    let value = pad_base64(&value);
+    let decrypted_result = mc.decrypt_base64_to_string(&value);
     match decrypted_result {
         Ok(decrypted_result) => Ok(decrypted_result),
-        Err(e) => Err(DecryptError {
-            details: format!("{}", e),
+        Err(e) => Err(decrypt_error(e, base64_decoded_len(&value))),
+    }
+}
+
+/// return base64 `value` decrypted with the first of `keys` that works, for legacy ciphertexts
+/// without the key id of [`Keyring`]. If error, return Err: [`DecryptErrorKind::InvalidBase64`]
+/// or [`DecryptErrorKind::InvalidPadding`] when `value` is not a ciphertext at all, else
+/// [`DecryptErrorKind::WrongKeyOrCorrupt`] when no key works.
+///
+/// Every key is tried even after a match so the time taken does not tell which key matched,
+/// the cost is one decryption per key. A wrong key passes the padding check once in a few
+/// hundred tries and then usually fails the UTF-8 check, when it does not the garbage
+/// plaintext of an earlier key is returned, so keep the list short and put the likeliest key
+/// first.
+pub fn decrypt_try_keys(value: &str, keys: &[&str]) -> Result<String, DecryptError> {
+    let decoded_len = base64_decode(value)?.len();
+    if decoded_len == 0 || decoded_len % 16 != 0 {
+        return Err(DecryptError::new(
+            DecryptErrorKind::InvalidPadding,
+            format!(
+                "{} bytes is not a whole number of cipher blocks",
+                decoded_len
+            ),
+        ));
+    }
+    let mut decrypted = None;
+    for key in keys {
+        if let Ok(plaintext) = decrypt_by_key_with_error(value.to_string(), key) {
+            decrypted.get_or_insert(plaintext);
+        }
+    }
+    decrypted.ok_or_else(|| {
+        DecryptError::new(
+            DecryptErrorKind::WrongKeyOrCorrupt,
+            format!("none of the {} keys decrypts the value", keys.len()),
+        )
+    })
+}
+
+/// decrypt base64 `value` into `writer` block by block without holding the whole plaintext,
+/// if error, return Err. When the key is wrong the failure is only detected on the last block,
+/// so part of the garbage plaintext may already be written.
+///
+/// ```rust,ignore
+/// let mut file = File::create("report.csv")?;
+/// decrypt_by_key_to_writer(&encrypted, key, &mut file)?;
+/// ```
+pub fn decrypt_by_key_to_writer(
+    value: &str,
+    key: &str,
+    writer: &mut impl Write,
+) -> Result<(), DecryptError> {
+    let mc = new_magic_crypt!(key, 256);
+    let value = pad_base64(value);
+    let mut reader = DecoderReader::new(value.as_bytes(), &STANDARD);
+    mc.decrypt_reader_to_writer(&mut reader, writer)
+        .map_err(|e| decrypt_error(e, base64_decoded_len(&value)))
+}
+
+/// The serialization of [`encrypt_value_as`], the binary ones give much smaller ciphertexts
+/// for high-volume field encryption. A value must be decrypted with the format it was
+/// encrypted with.
+#[cfg(feature = "crypto-serde")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SerdeFormat {
+    /// readable once decrypted, the format of [`encrypt_value`]
+    #[default]
+    Json,
+    /// bincode 1, the most compact, field names are not stored so the type must not change
+    #[cfg(feature = "crypto-bincode")]
+    Bincode,
+    /// MessagePack with the field names, tolerates added optional fields like JSON
+    #[cfg(feature = "crypto-msgpack")]
+    MessagePack,
+}
+
+#[cfg(feature = "crypto-serde")]
+impl SerdeFormat {
+    fn serialize<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            SerdeFormat::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "crypto-bincode")]
+            SerdeFormat::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+            #[cfg(feature = "crypto-msgpack")]
+            SerdeFormat::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn deserialize<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            SerdeFormat::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            #[cfg(feature = "crypto-bincode")]
+            SerdeFormat::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+            #[cfg(feature = "crypto-msgpack")]
+            SerdeFormat::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// return `value` serialized to JSON and encrypted like [`encrypt_by_key`], if `value` can not
+/// be serialized, return Err
+#[cfg(feature = "crypto-serde")]
+pub fn encrypt_value<T: Serialize + ?Sized>(value: &T, key: &str) -> Result<String, EncryptError> {
+    encrypt_value_as(value, key, SerdeFormat::Json)
+}
+
+/// return `value` serialized in `format` and encrypted like [`encrypt_by_key`], if `value` can
+/// not be serialized in `format`, return Err
+///
+/// ```rust,ignore
+/// let encrypted = encrypt_value_as(&card, key, SerdeFormat::Bincode)?;
+/// let card: Card = decrypt_value_as(&encrypted, key, SerdeFormat::Bincode)?;
+/// ```
+#[cfg(feature = "crypto-serde")]
+pub fn encrypt_value_as<T: Serialize + ?Sized>(
+    value: &T,
+    key: &str,
+    format: SerdeFormat,
+) -> Result<String, EncryptError> {
+    let bytes = format
+        .serialize(value)
+        .map_err(|e| EncryptError::new(format!("serialize value to encrypt failed: {}", e)))?;
+    let mc = new_magic_crypt!(key, 256);
+    Ok(mc.encrypt_bytes_to_base64(&bytes))
+}
+
+/// return the value encrypted by [`encrypt_value`], if error, return Err
+#[cfg(feature = "crypto-serde")]
+pub fn decrypt_value<T: DeserializeOwned>(value: &str, key: &str) -> Result<T, DecryptError> {
+    decrypt_value_as(value, key, SerdeFormat::Json)
+}
+
+/// return the value encrypted by [`encrypt_value_as`] in `format`, if error, return Err
+#[cfg(feature = "crypto-serde")]
+pub fn decrypt_value_as<T: DeserializeOwned>(
+    value: &str,
+    key: &str,
+    format: SerdeFormat,
+) -> Result<T, DecryptError> {
+    let mc = new_magic_crypt!(key, 256);
+    let value = pad_base64(value);
+    let bytes = mc
+        .decrypt_base64_to_bytes(&*value)
+        .map_err(|e| decrypt_error(e, base64_decoded_len(&value)))?;
+    format.deserialize(&bytes).map_err(|e| {
+        DecryptError::new(
+            DecryptErrorKind::Other,
+            format!("deserialize decrypted value failed: {}", e),
+        )
+    })
+}
+
+/// return standard base64 `value` with the `=` padding stripped by some encoders, like the
+/// JavaScript and Go unpadded ones, added back. Padded or malformed `value` is left as is.
+fn pad_base64(value: &str) -> Cow<'_, str> {
+    match value.len() % 4 {
+        2 | 3 if !value.ends_with('=') => {
+            Cow::Owned(format!("{}{}", value, "=".repeat(4 - value.len() % 4)))
+        }
+        _ => Cow::Borrowed(value),
+    }
+}
+
+/// length of the decoded `value`, exact for valid base64
+fn base64_decoded_len(value: &str) -> usize {
+    value.trim_end_matches('=').len() * 3 / 4
+}
+
+fn decrypt_error(e: MagicCryptError, decoded_len: usize) -> DecryptError {
+    let kind = match &e {
+        MagicCryptError::Base64Error(_) => DecryptErrorKind::InvalidBase64,
+        MagicCryptError::IOError(io)
+            if io
+                .get_ref()
+                .is_some_and(|inner| inner.is::<base64::DecodeError>()) =>
+        {
+            DecryptErrorKind::InvalidBase64
+        }
+        MagicCryptError::DecryptError(_) if decoded_len % 16 != 0 => {
+            DecryptErrorKind::InvalidPadding
+        }
+        MagicCryptError::DecryptError(_) | MagicCryptError::StringError(_) => {
+            DecryptErrorKind::WrongKeyOrCorrupt
+        }
+        _ => DecryptErrorKind::Other,
+    };
+    DecryptError::new(kind, format!("{}", e))
+}
+
+/// Encryption backend of [`encrypt_with`] and [`decrypt_with`], implement it to encrypt with a
+/// KMS or an HSM instead of a local key. [`MagicCryptEncryptor`] is the local AES-256 backend of
+/// [`encrypt_by_key`].
+///
+/// ```rust,ignore
+/// struct KmsEncryptor { client: KmsClient, key_id: String }
+///
+/// impl Encryptor for KmsEncryptor {
+///     fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptError> {
+///         self.client.encrypt(&self.key_id, plaintext).map_err(|e| EncryptError::new(e.to_string()))
+///     }
+///
+///     fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError> {
+///         self.client
+///             .decrypt(&self.key_id, ciphertext)
+///             .map_err(|e| DecryptError::new(DecryptErrorKind::Other, e.to_string()))
+///     }
+/// }
+///
+/// let token = encrypt_with(&kms, "secret")?;
+/// ```
+pub trait Encryptor {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptError>;
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError>;
+}
+
+/// AES-256 [`Encryptor`] of [`encrypt_by_key`]/[`decrypt_by_key`], its ciphertexts are
+/// interchangeable with theirs.
+#[derive(Clone, Debug)]
+pub struct MagicCryptEncryptor {
+    mc: MagicCrypt256,
+}
+
+impl MagicCryptEncryptor {
+    pub fn new(key: &str) -> Self {
+        MagicCryptEncryptor {
+            mc: new_magic_crypt!(key, 256),
+        }
+    }
+}
+
+impl Encryptor for MagicCryptEncryptor {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptError> {
+        Ok(self.mc.encrypt_bytes_to_bytes(plaintext))
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError> {
+        self.mc
+            .decrypt_bytes_to_bytes(ciphertext)
+            .map_err(|e| decrypt_error(e, ciphertext.len()))
+    }
+}
+
+/// return `value` encrypted by `encryptor` in base64, if error, return Err
+pub fn encrypt_with(
+    encryptor: &(impl Encryptor + ?Sized),
+    value: &str,
+) -> Result<String, EncryptError> {
+    Ok(base64_encode(&encryptor.encrypt(value.as_bytes())?))
+}
+
+/// return base64 `value` decrypted by `encryptor`, if error, return Err
+pub fn decrypt_with(
+    encryptor: &(impl Encryptor + ?Sized),
+    value: &str,
+) -> Result<String, DecryptError> {
+    let plaintext = encryptor.decrypt(&base64_decode(value)?)?;
+    String::from_utf8(plaintext)
+        .map_err(|e| DecryptError::new(DecryptErrorKind::WrongKeyOrCorrupt, format!("{}", e)))
+}
+
+/// Ordered encryption keys for rotation: [`Keyring::encrypt`] always uses the primary key, the
+/// last one added, and [`Keyring::decrypt`] picks the key by the id embedded in the ciphertext,
+/// so the values encrypted with the older keys still decrypt. The ciphertexts are
+/// `{id}:{base64}`, so a key id containing a `:` is rejected with Err.
+///
+/// ```rust,ignore
+/// let keyring = Keyring::new("2023", old_key)?.with_primary("2024", new_key)?;
+/// let token = keyring.encrypt("secret")?; // "2024:..."
+/// ```
+pub struct Keyring {
+    keys: Vec<(String, Box<dyn Encryptor + Send + Sync>)>,
+}
+
+impl Keyring {
+    /// keyring with the [`MagicCryptEncryptor`] of `key` as primary key, if `id` contains a `:`,
+    /// return Err
+    pub fn new(id: impl Into<String>, key: &str) -> Result<Self, KeyError> {
+        Self::with_encryptor(id, MagicCryptEncryptor::new(key))
+    }
+
+    /// keyring with `encryptor` as primary key, if `id` contains a `:`, return Err
+    pub fn with_encryptor(
+        id: impl Into<String>,
+        encryptor: impl Encryptor + Send + Sync + 'static,
+    ) -> Result<Self, KeyError> {
+        Keyring { keys: Vec::new() }.with_primary_encryptor(id, encryptor)
+    }
+
+    /// Add the [`MagicCryptEncryptor`] of `key` as the new primary key, the current one is kept
+    /// for decryption. If `id` contains a `:`, return Err.
+    pub fn with_primary(self, id: impl Into<String>, key: &str) -> Result<Self, KeyError> {
+        self.with_primary_encryptor(id, MagicCryptEncryptor::new(key))
+    }
+
+    /// [`Keyring::with_primary`] with any [`Encryptor`].
+    pub fn with_primary_encryptor(
+        mut self,
+        id: impl Into<String>,
+        encryptor: impl Encryptor + Send + Sync + 'static,
+    ) -> Result<Self, KeyError> {
+        let id = id.into();
+        if id.contains(':') {
+            return Err(KeyError {
+                details: format!("key id {:?} contains ':'", id),
+            });
+        }
+        self.keys.push((id, Box::new(encryptor)));
+        Ok(self)
+    }
+
+    /// return the id of the key used by [`Keyring::encrypt`]
+    pub fn primary_id(&self) -> &str {
+        &self.keys.last().ex("keyring has a primary key").0
+    }
+
+    /// return `value` encrypted with the primary key as `{id}:{base64}`, if error, return Err
+    pub fn encrypt(&self, value: &str) -> Result<String, EncryptError> {
+        let (id, encryptor) = self.keys.last().ex("keyring has a primary key");
+        Ok(format!(
+            "{}:{}",
+            id,
+            encrypt_with(encryptor.as_ref(), value)?
+        ))
+    }
+
+    /// return `value` of [`Keyring::encrypt`] decrypted with the key of its id, if error, return
+    /// Err, of kind [`DecryptErrorKind::WrongKeyOrCorrupt`] for an id not in the keyring
+    pub fn decrypt(&self, value: &str) -> Result<String, DecryptError> {
+        let (id, ciphertext) = value.split_once(':').ok_or_else(|| {
+            DecryptError::new(DecryptErrorKind::Other, "missing key id in ciphertext")
+        })?;
+        let (_, encryptor) = self
+            .keys
+            .iter()
+            .rev()
+            .find(|(key_id, _)| key_id == id)
+            .ok_or_else(|| {
+                DecryptError::new(
+                    DecryptErrorKind::WrongKeyOrCorrupt,
+                    format!("unknown key id {:?}", id),
+                )
+            })?;
+        decrypt_with(encryptor.as_ref(), ciphertext)
+    }
+}
+
+/// [`encrypt_by_key`] on the blocking thread pool, so large inputs do not stall the async runtime
+#[cfg(feature = "async")]
+pub async fn encrypt_by_key_async(value: String, key: &str) -> String {
+    let key = key.to_string();
+    tokio::task::spawn_blocking(move || encrypt_by_key(value, &key))
+        .await
+        .ex("encrypt task failed")
+}
+
+/// [`decrypt_by_key_with_error`] on the blocking thread pool, so large inputs do not stall the
+/// async runtime
+#[cfg(feature = "async")]
+pub async fn decrypt_by_key_async(value: String, key: &str) -> Result<String, DecryptError> {
+    let key = key.to_string();
+    tokio::task::spawn_blocking(move || decrypt_by_key_with_error(value, &key))
+        .await
+        .ex("decrypt task failed")
+}
+
+/// Strength of an encryption key, see [`validate_key`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyBits {
+    Bits128,
+    Bits192,
+    /// the strength of [`encrypt_by_key`]
+    #[default]
+    Bits256,
+}
+
+impl KeyBits {
+    pub fn bits(&self) -> usize {
+        match self {
+            KeyBits::Bits128 => 128,
+            KeyBits::Bits192 => 192,
+            KeyBits::Bits256 => 256,
+        }
+    }
+}
+
+/// return Err if `key` is empty or shorter than `bits` / 8 bytes, so a weak key is caught when
+/// the config is loaded rather than silently stretched by [`encrypt_by_key`], which accepts any
+/// string. Only the length is checked, a long key can still be guessable.
+///
+/// ```rust,ignore
+/// let key = key_from_env("APP_KEY")?;
+/// validate_key(&key, KeyBits::Bits256)?;
+/// ```
+pub fn validate_key(key: &str, bits: KeyBits) -> Result<(), KeyError> {
+    let min_len = bits.bits() / 8;
+    if key.is_empty() {
+        return Err(KeyError {
+            details: "encryption key is empty".to_string(),
+        });
+    }
+    if key.len() < min_len {
+        return Err(KeyError {
+            details: format!(
+                "encryption key of {} bytes is too short for {} bits, at least {} bytes expected",
+                key.len(),
+                bits.bits(),
+                min_len
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// return key read from env var `env_var`, if it is missing or empty, return Err
+pub fn key_from_env(env_var: &str) -> Result<String, KeyError> {
+    match std::env::var(env_var) {
+        Ok(key) if !key.is_empty() => Ok(key),
+        Ok(_) => Err(KeyError {
+            details: format!("encryption key env var {} is empty", env_var),
+        }),
+        Err(e) => Err(KeyError {
+            details: format!("encryption key env var {} is not available: {}", env_var, e),
         }),
     }
 }
 
+/// return key read from file `path` without the trailing newline, if the file can not be read
+/// or is empty, return Err
+#[cfg(not(target_arch = "wasm32"))]
+pub fn key_from_file(path: impl AsRef<std::path::Path>) -> Result<String, KeyError> {
+    let path = path.as_ref();
+    let key = std::fs::read_to_string(path).map_err(|e| KeyError {
+        details: format!("read encryption key file {:?} failed: {}", path, e),
+    })?;
+    let key = key.trim_end_matches(['\r', '\n']);
+    if key.is_empty() {
+        return Err(KeyError {
+            details: format!("encryption key file {:?} is empty", path),
+        });
+    }
+    Ok(key.to_string())
+}
+
+/// [`encrypt_by_key`] with the key read from env var `env_var`
+pub fn encrypt_by_key_from_env(value: String, env_var: &str) -> Result<String, KeyError> {
+    Ok(encrypt_by_key(value, &key_from_env(env_var)?))
+}
+
+/// [`decrypt_by_key_with_error`] with the key read from env var `env_var`
+pub fn decrypt_by_key_from_env(value: String, env_var: &str) -> Result<String, DecryptError> {
+    decrypt_by_key_with_error(value, &key_from_env(env_var)?)
+}
+
+/// [`encrypt_by_key`] with the key read from file `path`
+#[cfg(not(target_arch = "wasm32"))]
+pub fn encrypt_by_key_from_file(
+    value: String,
+    path: impl AsRef<std::path::Path>,
+) -> Result<String, KeyError> {
+    Ok(encrypt_by_key(value, &key_from_file(path)?))
+}
+
+/// [`decrypt_by_key_with_error`] with the key read from file `path`
+#[cfg(not(target_arch = "wasm32"))]
+pub fn decrypt_by_key_from_file(
+    value: String,
+    path: impl AsRef<std::path::Path>,
+) -> Result<String, DecryptError> {
+    decrypt_by_key_with_error(value, &key_from_file(path)?)
+}
+
+/// return base64 string of `value` in the standard alphabet with padding
+pub fn base64_encode(value: &[u8]) -> String {
+    STANDARD.encode(value)
+}
+
+/// return decoded bytes of standard base64 `value`, padded or not, if error, return Err
+pub fn base64_decode(value: &str) -> Result<Vec<u8>, DecryptError> {
+    STANDARD
+        .decode(&*pad_base64(value))
+        .map_err(|e| DecryptError::new(DecryptErrorKind::InvalidBase64, format!("{}", e)))
+}
+
+/// return base64 string of `value` in the URL-safe alphabet without padding
+pub fn base64_url_encode(value: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(value)
+}
+
+/// return decoded bytes of URL-safe unpadded base64 `value`, if error, return Err
+pub fn base64_url_decode(value: &str) -> Result<Vec<u8>, DecryptError> {
+    URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|e| DecryptError::new(DecryptErrorKind::InvalidBase64, format!("{}", e)))
+}
+
+#[cfg(feature = "hash")]
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// return SHA-256 digest of `value`
+#[cfg(feature = "hash")]
+pub fn sha256(value: &[u8]) -> [u8; 32] {
+    Sha256::digest(value).into()
+}
+
+/// return SHA-256 digest of `value` in lowercase hex
+#[cfg(feature = "hash")]
+pub fn sha256_hex(value: &[u8]) -> String {
+    to_hex(&sha256(value))
+}
+
+/// return SHA-512 digest of `value`
+#[cfg(feature = "hash")]
+pub fn sha512(value: &[u8]) -> [u8; 64] {
+    Sha512::digest(value).into()
+}
+
+/// return SHA-512 digest of `value` in lowercase hex
+#[cfg(feature = "hash")]
+pub fn sha512_hex(value: &[u8]) -> String {
+    to_hex(&sha512(value))
+}
+
+/// Streaming SHA-256 for large inputs, it implements [`std::io::Write`] so a file can be
+/// hashed with [`std::io::copy`].
+///
+/// ```rust,ignore
+/// let mut hasher = Sha256Hasher::new();
+/// std::io::copy(&mut File::open("upload.bin")?, &mut hasher)?;
+/// let fingerprint = hasher.finalize_hex();
+/// ```
+#[cfg(feature = "hash")]
+#[derive(Clone, Debug, Default)]
+pub struct Sha256Hasher {
+    inner: Sha256,
+}
+
+#[cfg(feature = "hash")]
+impl Sha256Hasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, value: &[u8]) {
+        self.inner.update(value);
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        self.inner.finalize().into()
+    }
+
+    pub fn finalize_hex(self) -> String {
+        to_hex(&self.finalize())
+    }
+}
+
+#[cfg(feature = "hash")]
+impl std::io::Write for Sha256Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// return HMAC-SHA256 of `value` keyed by `key`
+#[cfg(feature = "hash")]
+pub fn hmac_sha256(key: &[u8], value: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).ex("hmac accepts keys of any length");
+    mac.update(value);
+    mac.finalize().into_bytes().into()
+}
+
+/// return HMAC-SHA256 of `value` keyed by `key` in lowercase hex
+#[cfg(feature = "hash")]
+pub fn hmac_sha256_hex(key: &[u8], value: &[u8]) -> String {
+    to_hex(&hmac_sha256(key, value))
+}
+
+/// Issue a URL-safe token carrying `payload` that [`verify_token`] accepts for `ttl`, for
+/// password-reset or email-verification links. The payload is signed, not encrypted, anyone
+/// holding the token can read it.
+///
+/// The token is `base64url(expiry || payload).base64url(hmac)` with the expiry as big-endian
+/// unix seconds. If the expiry is out of the range of [`SystemTime`], return Err.
+#[cfg(feature = "hash")]
+pub fn issue_token(payload: &str, key: &str, ttl: Duration) -> Result<String, EncryptError> {
+    issue_token_with_clock(payload, key, ttl, SystemTime::now)
+}
+
+/// [`issue_token`] with the current time from `clock` instead of the system clock, to test
+/// expiry deterministically with [`verify_token_with_clock`].
+#[cfg(feature = "hash")]
+pub fn issue_token_with_clock(
+    payload: &str,
+    key: &str,
+    ttl: Duration,
+    clock: fn() -> SystemTime,
+) -> Result<String, EncryptError> {
+    let expiry = clock()
+        .checked_add(ttl)
+        .and_then(|expiry| expiry.duration_since(UNIX_EPOCH).ok())
+        .ok_or_else(|| EncryptError::new(format!("token expiry out of range with ttl {:?}", ttl)))?
+        .as_secs();
+    let mut body = expiry.to_be_bytes().to_vec();
+    body.extend_from_slice(payload.as_bytes());
+    let body = base64_url_encode(&body);
+    let signature = base64_url_encode(&hmac_sha256(key.as_bytes(), body.as_bytes()));
+    Ok(format!("{}.{}", body, signature))
+}
+
+/// return the payload of a `token` issued by [`issue_token`] with the same `key`,
+/// if it is malformed, tampered with or expired, return Err
+#[cfg(feature = "hash")]
+pub fn verify_token(token: &str, key: &str) -> Result<String, TokenError> {
+    verify_token_with_clock(token, key, SystemTime::now)
+}
+
+/// [`verify_token`] with the current time from `clock` instead of the system clock,
+/// a `clock` before the Unix epoch treats every token as expired.
+#[cfg(feature = "hash")]
+pub fn verify_token_with_clock(
+    token: &str,
+    key: &str,
+    clock: fn() -> SystemTime,
+) -> Result<String, TokenError> {
+    let (body, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+    let signature = base64_url_decode(signature).map_err(|_| TokenError::Malformed)?;
+    let expected = hmac_sha256(key.as_bytes(), body.as_bytes());
+    if !constant_time_eq_bytes(&signature, &expected) {
+        return Err(TokenError::InvalidSignature);
+    }
+    let body = base64_url_decode(body).map_err(|_| TokenError::Malformed)?;
+    if body.len() < 8 {
+        return Err(TokenError::Malformed);
+    }
+    let (expiry, payload) = body.split_at(8);
+    let expiry = u64::from_be_bytes(expiry.try_into().unwp());
+    let now = clock()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| TokenError::Expired)?
+        .as_secs();
+    if now >= expiry {
+        return Err(TokenError::Expired);
+    }
+    String::from_utf8(payload.to_vec()).map_err(|_| TokenError::Malformed)
+}
+
+/// compare two secrets like API tokens or HMAC digests in constant time,
+/// only the length may leak
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    constant_time_eq_bytes(a.as_bytes(), b.as_bytes())
+}
+
+/// bytes variant of [`constant_time_eq`]
+pub fn constant_time_eq_bytes(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(feature = "aead")]
+const AEAD_NONCE_LEN: usize = 12;
+
+#[cfg(feature = "aead")]
+fn aead_cipher(key: &str) -> Aes256Gcm {
+    let key = Sha256::digest(key.as_bytes());
+    Aes256Gcm::new(&key)
+}
+
+/// return AES-256-GCM encrypted string in base64, `aad` is authenticated but not encrypted,
+/// the same `aad` is required to decrypt, e.g. a tenant id binding the ciphertext to its record
+#[cfg(feature = "aead")]
+pub fn encrypt_aead(plaintext: &str, key: &str, aad: &str) -> String {
+    let cipher = aead_cipher(key);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: plaintext.as_bytes(),
+        aad: aad.as_bytes(),
+    };
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend(cipher.encrypt(&nonce, payload).unwp());
+    base64_encode(&encrypted)
+}
+
+/// return decrypted string of [`encrypt_aead`], if the key or `aad` does not match or the
+/// ciphertext was tampered with, return Err
+#[cfg(feature = "aead")]
+pub fn decrypt_aead(ciphertext: &str, key: &str, aad: &str) -> Result<String, DecryptError> {
+    let encrypted = base64_decode(ciphertext)?;
+    if encrypted.len() < AEAD_NONCE_LEN {
+        return Err(DecryptError::new(
+            DecryptErrorKind::Other,
+            "ciphertext is too short",
+        ));
+    }
+    let (nonce, msg) = encrypted.split_at(AEAD_NONCE_LEN);
+    let payload = Payload {
+        msg,
+        aad: aad.as_bytes(),
+    };
+    let decrypted = aead_cipher(key)
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| {
+            DecryptError::new(
+                DecryptErrorKind::WrongKeyOrCorrupt,
+                "authentication failed, wrong key or aad, or tampered ciphertext",
+            )
+        })?;
+    String::from_utf8(decrypted)
+        .map_err(|e| DecryptError::new(DecryptErrorKind::Other, format!("{}", e)))
+}
+
+#[cfg(feature = "aead")]
+const CHUNKED_MAGIC: &[u8; 4] = b"BLC1";
+/// magic, chunk size and nonce prefix
+#[cfg(feature = "aead")]
+const CHUNKED_HEADER_LEN: usize = 4 + 4 + 8;
+#[cfg(feature = "aead")]
+const AEAD_TAG_LEN: usize = 16;
+
+/// Default chunk size of [`encrypt_aead_chunked`], 64 KiB.
+#[cfg(feature = "aead")]
+pub const DEFAULT_AEAD_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// Largest chunk size of [`encrypt_aead_chunked`], 16 MiB, a bigger one in a header read by
+/// [`decrypt_aead_chunked`] is rejected before allocating the chunk buffer.
+#[cfg(feature = "aead")]
+pub const MAX_AEAD_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// read until `buf` is full or the end of `reader`, return the bytes read
+#[cfg(feature = "aead")]
+fn read_full(reader: &mut impl std::io::Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// nonce and aad of chunk `index`: the nonce is the random prefix of the header and the index,
+/// the aad binds the header, the index and whether it is the final chunk so chunks can not be
+/// reordered, dropped or moved to another file
+#[cfg(feature = "aead")]
+fn chunk_nonce_aad(header: &[u8], index: u32, last: bool) -> ([u8; AEAD_NONCE_LEN], Vec<u8>) {
+    let mut nonce = [0; AEAD_NONCE_LEN];
+    nonce[..8].copy_from_slice(&header[8..CHUNKED_HEADER_LEN]);
+    nonce[8..].copy_from_slice(&index.to_be_bytes());
+    let mut aad = header.to_vec();
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(last as u8);
+    (nonce, aad)
+}
+
+/// Encrypt `reader` into `writer` with AES-256-GCM in chunks of `chunk_size` bytes, for large
+/// files that need integrity protection with bounded memory, see [`decrypt_aead_chunked`] and
+/// [`decrypt_aead_chunk`] for random access.
+///
+/// The output is a header (magic, chunk size, random nonce prefix) followed by the encrypted
+/// chunks, each with its own tag and a nonce derived from the prefix and the chunk index. The
+/// last chunk is always shorter than `chunk_size`, possibly empty, so a truncated file is
+/// detected. At most 2^32 chunks can be written, `chunk_size` is at most
+/// [`MAX_AEAD_CHUNK_SIZE`].
+///
+/// ```rust,ignore
+/// let mut input = BufReader::new(File::open("backup.tar")?);
+/// let mut output = BufWriter::new(File::create("backup.tar.enc")?);
+/// encrypt_aead_chunked(&mut input, &mut output, key, DEFAULT_AEAD_CHUNK_SIZE)?;
+/// ```
+#[cfg(feature = "aead")]
+pub fn encrypt_aead_chunked(
+    reader: &mut impl std::io::Read,
+    writer: &mut impl Write,
+    key: &str,
+    chunk_size: u32,
+) -> Result<(), EncryptError> {
+    if chunk_size == 0 {
+        return Err(EncryptError::new("chunk size must be positive"));
+    }
+    if chunk_size > MAX_AEAD_CHUNK_SIZE {
+        return Err(EncryptError::new(format!(
+            "chunk size must be at most {}",
+            MAX_AEAD_CHUNK_SIZE
+        )));
+    }
+    let io_error = |e: std::io::Error| EncryptError::new(format!("{}", e));
+    let cipher = aead_cipher(key);
+    let mut header = CHUNKED_MAGIC.to_vec();
+    header.extend_from_slice(&chunk_size.to_be_bytes());
+    header.extend_from_slice(&Aes256Gcm::generate_nonce(&mut OsRng)[..8]);
+    writer.write_all(&header).map_err(io_error)?;
+
+    let mut chunk = vec![0; chunk_size as usize];
+    for index in 0..=u32::MAX {
+        let read = read_full(reader, &mut chunk).map_err(io_error)?;
+        let last = read < chunk.len();
+        let (nonce, aad) = chunk_nonce_aad(&header, index, last);
+        let payload = Payload {
+            msg: &chunk[..read],
+            aad: &aad,
+        };
+        let encrypted = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|e| EncryptError::new(format!("{}", e)))?;
+        writer.write_all(&encrypted).map_err(io_error)?;
+        if last {
+            return writer.flush().map_err(io_error);
+        }
+    }
+    Err(EncryptError::new("too many chunks"))
+}
+
+/// read and check the header of [`encrypt_aead_chunked`], return it with the chunk size
+#[cfg(feature = "aead")]
+fn read_chunked_header(
+    reader: &mut impl std::io::Read,
+) -> Result<([u8; CHUNKED_HEADER_LEN], u32), DecryptError> {
+    let mut header = [0; CHUNKED_HEADER_LEN];
+    let read = read_full(reader, &mut header)
+        .map_err(|e| DecryptError::new(DecryptErrorKind::Other, format!("{}", e)))?;
+    if read < CHUNKED_HEADER_LEN || &header[..4] != CHUNKED_MAGIC {
+        return Err(DecryptError::new(
+            DecryptErrorKind::Other,
+            "not a chunked ciphertext",
+        ));
+    }
+    let chunk_size = u32::from_be_bytes(header[4..8].try_into().unwp());
+    // the header is not authenticated before the first chunk is read
+    if chunk_size > MAX_AEAD_CHUNK_SIZE {
+        return Err(DecryptError::new(
+            DecryptErrorKind::WrongKeyOrCorrupt,
+            format!("chunk size {} in the header is too large", chunk_size),
+        ));
+    }
+    Ok((header, chunk_size))
+}
+
+/// decrypt chunk `index` read into `chunk`, `chunk` is the full encrypted chunk size unless the
+/// chunk is the last one
+#[cfg(feature = "aead")]
+fn decrypt_chunk(
+    cipher: &Aes256Gcm,
+    header: &[u8],
+    index: u32,
+    chunk: &[u8],
+    last: bool,
+) -> Result<Vec<u8>, DecryptError> {
+    let (nonce, aad) = chunk_nonce_aad(header, index, last);
+    let payload = Payload {
+        msg: chunk,
+        aad: &aad,
+    };
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|_| {
+            DecryptError::new(
+                DecryptErrorKind::WrongKeyOrCorrupt,
+                format!(
+                    "authentication of chunk {} failed, wrong key or tampered ciphertext",
+                    index
+                ),
+            )
+        })
+}
+
+/// Decrypt the output of [`encrypt_aead_chunked`] from `reader` into `writer` chunk by chunk,
+/// if the key does not match or a chunk was tampered with, reordered or dropped, return Err.
+/// The chunks before the failing one are already written to `writer`.
+#[cfg(feature = "aead")]
+pub fn decrypt_aead_chunked(
+    reader: &mut impl std::io::Read,
+    writer: &mut impl Write,
+    key: &str,
+) -> Result<(), DecryptError> {
+    let io_error = |e: std::io::Error| DecryptError::new(DecryptErrorKind::Other, format!("{}", e));
+    let (header, chunk_size) = read_chunked_header(reader)?;
+    let cipher = aead_cipher(key);
+    let mut chunk = vec![0; chunk_size as usize + AEAD_TAG_LEN];
+    for index in 0..=u32::MAX {
+        let read = read_full(reader, &mut chunk).map_err(io_error)?;
+        if read == 0 && index > 0 {
+            return Err(DecryptError::new(
+                DecryptErrorKind::WrongKeyOrCorrupt,
+                "truncated ciphertext, the last chunk is missing",
+            ));
+        }
+        let last = read < chunk.len();
+        let decrypted = decrypt_chunk(&cipher, &header, index, &chunk[..read], last)?;
+        writer.write_all(&decrypted).map_err(io_error)?;
+        if last {
+            return writer.flush().map_err(io_error);
+        }
+    }
+    Err(DecryptError::new(
+        DecryptErrorKind::Other,
+        "too many chunks",
+    ))
+}
+
+/// return chunk `index` of the output of [`encrypt_aead_chunked`] in `reader`, decrypted
+/// without reading the other chunks, if it does not exist, the key does not match or the
+/// chunk was tampered with, return Err
+#[cfg(feature = "aead")]
+pub fn decrypt_aead_chunk(
+    reader: &mut (impl std::io::Read + std::io::Seek),
+    key: &str,
+    index: u32,
+) -> Result<Vec<u8>, DecryptError> {
+    let io_error = |e: std::io::Error| DecryptError::new(DecryptErrorKind::Other, format!("{}", e));
+    reader.rewind().map_err(io_error)?;
+    let (header, chunk_size) = read_chunked_header(reader)?;
+    let encrypted_size = chunk_size as u64 + AEAD_TAG_LEN as u64;
+    reader
+        .seek(std::io::SeekFrom::Start(
+            CHUNKED_HEADER_LEN as u64 + index as u64 * encrypted_size,
+        ))
+        .map_err(io_error)?;
+    let mut chunk = vec![0; encrypted_size as usize];
+    let read = read_full(reader, &mut chunk).map_err(io_error)?;
+    if read == 0 {
+        return Err(DecryptError::new(
+            DecryptErrorKind::Other,
+            format!("chunk {} does not exist", index),
+        ));
+    }
+    decrypt_chunk(
+        &aead_cipher(key),
+        &header,
+        index,
+        &chunk[..read],
+        read < chunk.len(),
+    )
+}
+
 #[cfg(test)]
 mod test {
+    use crate::errors::DecryptErrorKind;
+
+    #[test]
+    fn decrypt_try_keys_test() {
+        use crate::crypto::{decrypt_try_keys, encrypt_by_key};
+
+        let encrypted = encrypt_by_key("https?".to_string(), "2021");
+        let keys = ["2023", "2022", "2021", "2020"];
+        assert_eq!(decrypt_try_keys(&encrypted, &keys).unwrap(), "https?");
+        assert_eq!(
+            decrypt_try_keys(&encrypted, &keys[..2]).unwrap_err().kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        assert_eq!(
+            decrypt_try_keys(&encrypted, &[]).unwrap_err().kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        assert_eq!(
+            decrypt_try_keys("not base64!", &keys).unwrap_err().kind(),
+            DecryptErrorKind::InvalidBase64
+        );
+        assert_eq!(
+            decrypt_try_keys(&crate::crypto::base64_encode(b"hello"), &keys)
+                .unwrap_err()
+                .kind(),
+            DecryptErrorKind::InvalidPadding
+        );
+    }
+
+    #[test]
+    fn keyring_test() {
+        use crate::crypto::Keyring;
+
+        let keyring = Keyring::new("2023", "old key").unwrap();
+        let old = keyring.encrypt("https?").unwrap();
+        assert!(old.starts_with("2023:"));
+        // the ciphertext is the one of encrypt_by_key after the id
+        assert_eq!(
+            crate::crypto::decrypt_by_key(old["2023:".len()..].to_string(), "old key"),
+            "https?"
+        );
+
+        let keyring = keyring.with_primary("2024", "new key").unwrap();
+        assert_eq!(keyring.primary_id(), "2024");
+        let new = keyring.encrypt("https?").unwrap();
+        assert!(new.starts_with("2024:"));
+        assert_eq!(keyring.decrypt(&old).unwrap(), "https?");
+        assert_eq!(keyring.decrypt(&new).unwrap(), "https?");
+
+        // a keyring without the old key rejects its ciphertexts
+        let rotated = Keyring::new("2024", "new key").unwrap();
+        assert_eq!(rotated.decrypt(&new).unwrap(), "https?");
+        assert_eq!(
+            rotated.decrypt(&old).unwrap_err().kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        assert_eq!(
+            rotated.decrypt("no id").unwrap_err().kind(),
+            DecryptErrorKind::Other
+        );
+        assert_eq!(
+            rotated
+                .decrypt(&format!("2024:{}", &old["2023:".len()..]))
+                .unwrap_err()
+                .kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+    }
+
+    #[test]
+    fn keyring_id_with_colon_test() {
+        use crate::crypto::Keyring;
+
+        assert!(Keyring::new("2024:01", "key").is_err());
+        assert!(Keyring::new("2024", "key")
+            .unwrap()
+            .with_primary("2025:01", "key")
+            .is_err());
+    }
+
     #[test]
     fn encrypt_test() {
         let msg = "https?";
@@ -49,6 +1092,67 @@ mod test {
         assert_eq!(msg, decrypted);
     }
 
+    #[test]
+    fn decrypt_error_kind_test() {
+        let decrypt = |value: &str, key: &str| {
+            crate::crypto::decrypt_by_key_with_error(value.to_string(), key)
+                .unwrap_err()
+                .kind()
+        };
+        assert_eq!(
+            decrypt("not base64 at all!", "foo"),
+            DecryptErrorKind::InvalidBase64
+        );
+        // valid base64 of 5 bytes, not a whole cipher block
+        assert_eq!(
+            decrypt(&crate::crypto::base64_encode(b"hello"), "foo"),
+            DecryptErrorKind::InvalidPadding
+        );
+        let encrypted = crate::crypto::encrypt_by_key("https?".to_string(), "foo");
+        assert_eq!(
+            decrypt(&encrypted, "bar"),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+    }
+
+    #[test]
+    fn decrypt_to_writer_test() {
+        let msg = "https?".repeat(2000);
+        let encrypted = crate::crypto::encrypt_by_key(msg.clone(), "foo");
+        let mut plaintext = Vec::new();
+        crate::crypto::decrypt_by_key_to_writer(&encrypted, "foo", &mut plaintext).unwrap();
+        assert_eq!(plaintext, msg.as_bytes());
+
+        let kind = |value: &str, key: &str| {
+            crate::crypto::decrypt_by_key_to_writer(value, key, &mut Vec::new())
+                .unwrap_err()
+                .kind()
+        };
+        assert_eq!(kind(&encrypted, "bar"), DecryptErrorKind::WrongKeyOrCorrupt);
+        assert_eq!(kind("not base64!", "foo"), DecryptErrorKind::InvalidBase64);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_encrypt_test() {
+        let msg = "https?".repeat(1000);
+        let encrypted = crate::crypto::encrypt_by_key_async(msg.clone(), "foo").await;
+        assert_eq!(crate::crypto::decrypt_by_key(encrypted.clone(), "foo"), msg);
+        assert_eq!(
+            crate::crypto::decrypt_by_key_async(encrypted.clone(), "foo")
+                .await
+                .unwrap(),
+            msg
+        );
+        assert_eq!(
+            crate::crypto::decrypt_by_key_async(encrypted, "bar")
+                .await
+                .unwrap_err()
+                .kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+    }
+
     #[test]
     fn decrypt_test() {
         let msg = "https?";
@@ -73,4 +1177,521 @@ mod test {
             panic!("Decrypt error: {:?}", err);
         }
     }
+
+    #[test]
+    fn decrypt_unpadded_test() {
+        let msg = "https?";
+        let key = "foo";
+        let encrypted = crate::crypto::encrypt_by_key(msg.to_string(), key);
+        assert!(encrypted.ends_with("=="));
+        let unpadded = encrypted.trim_end_matches('=').to_string();
+        assert_eq!(
+            crate::crypto::decrypt_by_key_with_error(unpadded.clone(), key).unwrap(),
+            msg
+        );
+        assert_eq!(crate::crypto::decrypt_by_key(unpadded.clone(), key), msg);
+        let mut decrypted = Vec::new();
+        crate::crypto::decrypt_by_key_to_writer(&unpadded, key, &mut decrypted).unwrap();
+        assert_eq!(decrypted, msg.as_bytes());
+        assert_eq!(
+            crate::crypto::base64_decode("+/8/dG9rZW4").unwrap(),
+            b"\xfb\xff?token"
+        );
+        assert_eq!(
+            crate::crypto::decrypt_by_key_with_error(unpadded[..unpadded.len() - 1].into(), key)
+                .unwrap_err()
+                .kind(),
+            crate::errors::DecryptErrorKind::InvalidBase64
+        );
+    }
+
+    #[test]
+    fn validate_key_test() {
+        use crate::crypto::{validate_key, KeyBits};
+
+        let err = validate_key("", KeyBits::Bits128).unwrap_err();
+        assert_eq!(err.to_string(), "encryption key is empty");
+        let err = validate_key("hunter2", KeyBits::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "encryption key of 7 bytes is too short for 256 bits, at least 32 bytes expected"
+        );
+        let key = "0123456789abcdef";
+        assert!(validate_key(key, KeyBits::Bits128).is_ok());
+        assert!(validate_key(key, KeyBits::Bits192).is_err());
+        assert!(validate_key(&key.repeat(2), KeyBits::Bits256).is_ok());
+    }
+
+    #[test]
+    fn key_from_env_test() {
+        let msg = "https?";
+        let env_var = "BUSYLIB_TEST_CRYPTO_KEY";
+        std::env::set_var(env_var, "foo");
+        let encrypted = crate::crypto::encrypt_by_key_from_env(msg.to_string(), env_var).unwrap();
+        assert_eq!(crate::crypto::decrypt_by_key(encrypted.clone(), "foo"), msg);
+        assert_eq!(
+            crate::crypto::decrypt_by_key_from_env(encrypted.clone(), env_var).unwrap(),
+            msg
+        );
+
+        let missing = "BUSYLIB_TEST_CRYPTO_KEY_MISSING";
+        std::env::remove_var(missing);
+        assert!(crate::crypto::encrypt_by_key_from_env(msg.to_string(), missing).is_err());
+        let err = crate::crypto::decrypt_by_key_from_env(encrypted, missing).unwrap_err();
+        assert!(err.to_string().contains(missing));
+    }
+
+    #[test]
+    fn key_from_file_test() {
+        let msg = "https?";
+        let path = std::env::temp_dir().join(format!("busylib_key_{}", std::process::id()));
+        std::fs::write(&path, "foo\n").unwrap();
+        let encrypted = crate::crypto::encrypt_by_key_from_file(msg.to_string(), &path).unwrap();
+        assert_eq!(crate::crypto::decrypt_by_key(encrypted.clone(), "foo"), msg);
+        assert_eq!(
+            crate::crypto::decrypt_by_key_from_file(encrypted.clone(), &path).unwrap(),
+            msg
+        );
+
+        std::fs::write(&path, "").unwrap();
+        assert!(crate::crypto::key_from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(crate::crypto::encrypt_by_key_from_file(msg.to_string(), &path).is_err());
+        assert!(crate::crypto::decrypt_by_key_from_file(encrypted, &path).is_err());
+    }
+
+    #[test]
+    fn base64_test() {
+        let value = b"\xfb\xff?token";
+        let encoded = crate::crypto::base64_encode(value);
+        assert_eq!(encoded, "+/8/dG9rZW4=");
+        assert_eq!(crate::crypto::base64_decode(&encoded).unwrap(), value);
+
+        let encoded = crate::crypto::base64_url_encode(value);
+        assert_eq!(encoded, "-_8_dG9rZW4");
+        assert_eq!(crate::crypto::base64_url_decode(&encoded).unwrap(), value);
+
+        assert!(crate::crypto::base64_decode("not base64!").is_err());
+        assert!(crate::crypto::base64_url_decode("+/8/").is_err());
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn hash_test() {
+        assert_eq!(
+            crate::crypto::sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            crate::crypto::sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            crate::crypto::sha512_hex(b"abc"),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(crate::crypto::sha256(b"abc").len(), 32);
+
+        let mut hasher = crate::crypto::Sha256Hasher::new();
+        std::io::copy(&mut &b"abc"[..], &mut hasher).unwrap();
+        hasher.update(b"");
+        assert_eq!(hasher.finalize_hex(), crate::crypto::sha256_hex(b"abc"));
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn hmac_test() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            crate::crypto::hmac_sha256_hex(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn token_test() {
+        use std::time::Duration;
+
+        use crate::crypto::{issue_token, verify_token};
+        use crate::errors::TokenError;
+
+        let key = "foo";
+        let token = issue_token("user:42", key, Duration::from_secs(60)).unwrap();
+        assert!(!token.contains(['+', '/', '=']));
+        assert_eq!(verify_token(&token, key).unwrap(), "user:42");
+
+        assert_eq!(
+            verify_token(&token, "bar").unwrap_err(),
+            TokenError::InvalidSignature
+        );
+        let (body, signature) = token.split_once('.').unwrap();
+        let forged = crate::crypto::base64_url_encode(b"\xff\xff\xff\xff\xff\xff\xff\xffuser:1");
+        assert_eq!(
+            verify_token(&format!("{}.{}", forged, signature), key).unwrap_err(),
+            TokenError::InvalidSignature
+        );
+        assert_eq!(
+            verify_token(&format!("{}x.{}", body, signature), key).unwrap_err(),
+            TokenError::InvalidSignature
+        );
+        assert_eq!(verify_token(body, key).unwrap_err(), TokenError::Malformed);
+        assert_eq!(
+            verify_token(&format!("{}.!", body), key).unwrap_err(),
+            TokenError::Malformed
+        );
+
+        let expired = issue_token("user:42", key, Duration::ZERO).unwrap();
+        assert_eq!(
+            verify_token(&expired, key).unwrap_err(),
+            TokenError::Expired
+        );
+        assert!(issue_token("user:42", key, Duration::MAX).is_err());
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn token_clock_test() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        use crate::crypto::{issue_token_with_clock, verify_token, verify_token_with_clock};
+        use crate::errors::TokenError;
+
+        static NOW: AtomicU64 = AtomicU64::new(1693583999);
+        fn clock() -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(NOW.load(Ordering::SeqCst))
+        }
+
+        let token =
+            issue_token_with_clock("user:42", "foo", Duration::from_secs(60), clock).unwrap();
+        assert_eq!(
+            verify_token_with_clock(&token, "foo", clock).unwrap(),
+            "user:42"
+        );
+        NOW.fetch_add(59, Ordering::SeqCst);
+        assert_eq!(
+            verify_token_with_clock(&token, "foo", clock).unwrap(),
+            "user:42"
+        );
+        NOW.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(
+            verify_token_with_clock(&token, "foo", clock).unwrap_err(),
+            TokenError::Expired
+        );
+        // issued in 2023, long expired for the system clock
+        assert_eq!(
+            verify_token(&token, "foo").unwrap_err(),
+            TokenError::Expired
+        );
+        fn before_epoch() -> SystemTime {
+            UNIX_EPOCH - Duration::from_secs(1)
+        }
+        assert_eq!(
+            verify_token_with_clock(&token, "foo", before_epoch).unwrap_err(),
+            TokenError::Expired
+        );
+    }
+
+    #[test]
+    fn encryptor_test() {
+        use crate::crypto::{decrypt_with, encrypt_with, Encryptor, MagicCryptEncryptor};
+        use crate::errors::{DecryptError, EncryptError};
+
+        /// stands in for a KMS: XOR with the key, tagged with the key id
+        struct InMemoryKms {
+            key_id: u8,
+            key: u8,
+        }
+
+        impl Encryptor for InMemoryKms {
+            fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptError> {
+                if plaintext.is_empty() {
+                    return Err(EncryptError::new("empty plaintext"));
+                }
+                let mut ciphertext = vec![self.key_id];
+                ciphertext.extend(plaintext.iter().map(|b| b ^ self.key));
+                Ok(ciphertext)
+            }
+
+            fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError> {
+                match ciphertext.split_first() {
+                    Some((id, rest)) if *id == self.key_id => {
+                        Ok(rest.iter().map(|b| b ^ self.key).collect())
+                    }
+                    _ => Err(DecryptError::new(
+                        DecryptErrorKind::WrongKeyOrCorrupt,
+                        "unknown key id",
+                    )),
+                }
+            }
+        }
+
+        let kms = InMemoryKms { key_id: 7, key: 42 };
+        let encrypted = encrypt_with(&kms, "https?").unwrap();
+        assert_eq!(decrypt_with(&kms, &encrypted).unwrap(), "https?");
+        assert_eq!(
+            encrypt_with(&kms, "").unwrap_err().to_string(),
+            "empty plaintext"
+        );
+        let other = InMemoryKms { key_id: 8, key: 42 };
+        assert_eq!(
+            decrypt_with(&other, &encrypted).unwrap_err().kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        assert_eq!(
+            decrypt_with(&kms, "not base64!").unwrap_err().kind(),
+            DecryptErrorKind::InvalidBase64
+        );
+
+        // the default backend is interchangeable with encrypt_by_key/decrypt_by_key
+        let local = MagicCryptEncryptor::new("foo");
+        let encrypted = encrypt_with(&local, "https?").unwrap();
+        assert_eq!(
+            encrypted,
+            crate::crypto::encrypt_by_key("https?".to_string(), "foo")
+        );
+        assert_eq!(
+            crate::crypto::decrypt_by_key(encrypted.clone(), "foo"),
+            "https?"
+        );
+        assert_eq!(
+            decrypt_with(&MagicCryptEncryptor::new("bar"), &encrypted)
+                .unwrap_err()
+                .kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+    }
+
+    #[cfg(feature = "crypto-serde")]
+    #[test]
+    fn encrypt_value_test() {
+        use serde::{Deserialize, Serialize};
+
+        use crate::crypto::{decrypt_value, encrypt_value};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Credentials {
+            user: String,
+            password: String,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct DbConfig {
+            url: String,
+            pool_size: u32,
+            credentials: Credentials,
+            replicas: Vec<String>,
+        }
+
+        let config = DbConfig {
+            url: "postgres://db:5432/app".to_string(),
+            pool_size: 8,
+            credentials: Credentials {
+                user: "app".to_string(),
+                password: "hunter2".to_string(),
+            },
+            replicas: vec!["db-1".to_string(), "db-2".to_string()],
+        };
+        let encrypted = encrypt_value(&config, "foo").unwrap();
+        assert!(!encrypted.contains("hunter2"));
+        assert_eq!(
+            decrypt_value::<DbConfig>(&encrypted, "foo").unwrap(),
+            config
+        );
+
+        assert_eq!(
+            decrypt_value::<DbConfig>(&encrypted, "bar")
+                .unwrap_err()
+                .kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        // decrypts but is not a DbConfig
+        let other = encrypt_value(&vec![1, 2, 3], "foo").unwrap();
+        assert_eq!(
+            decrypt_value::<DbConfig>(&other, "foo").unwrap_err().kind(),
+            DecryptErrorKind::Other
+        );
+        // JSON object keys must be strings
+        let unserializable: std::collections::BTreeMap<Vec<u8>, u8> = [(vec![1], 2)].into();
+        let err = encrypt_value(&unserializable, "foo").unwrap_err();
+        assert!(err.to_string().contains("serialize"), "{}", err);
+    }
+
+    #[cfg(any(feature = "crypto-bincode", feature = "crypto-msgpack"))]
+    #[test]
+    fn encrypt_value_as_test() {
+        use serde::{Deserialize, Serialize};
+
+        use crate::crypto::{decrypt_value_as, encrypt_value, encrypt_value_as, SerdeFormat};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Event {
+            user_id: u64,
+            account_number: String,
+            amounts: Vec<u32>,
+        }
+
+        let event = Event {
+            user_id: 42,
+            account_number: "DE89370400440532013000".to_string(),
+            amounts: (0..16).map(|i| i * 1000).collect(),
+        };
+        let json_len = encrypt_value(&event, "foo").unwrap().len();
+        let mut formats = Vec::new();
+        #[cfg(feature = "crypto-bincode")]
+        formats.push(SerdeFormat::Bincode);
+        #[cfg(feature = "crypto-msgpack")]
+        formats.push(SerdeFormat::MessagePack);
+        for format in formats {
+            let encrypted = encrypt_value_as(&event, "foo", format).unwrap();
+            assert_eq!(
+                decrypt_value_as::<Event>(&encrypted, "foo", format).unwrap(),
+                event
+            );
+            assert!(
+                encrypted.len() < json_len * 3 / 4,
+                "{:?} {} vs json {}",
+                format,
+                encrypted.len(),
+                json_len
+            );
+            assert_eq!(
+                decrypt_value_as::<Event>(&encrypted, "bar", format)
+                    .unwrap_err()
+                    .kind(),
+                DecryptErrorKind::WrongKeyOrCorrupt
+            );
+            // decrypts but is not an Event
+            let other = encrypt_value_as(&"not an event", "foo", format).unwrap();
+            assert_eq!(
+                decrypt_value_as::<Event>(&other, "foo", format)
+                    .unwrap_err()
+                    .kind(),
+                DecryptErrorKind::Other
+            );
+        }
+    }
+
+    #[test]
+    fn constant_time_eq_test() {
+        assert!(crate::crypto::constant_time_eq("token", "token"));
+        assert!(!crate::crypto::constant_time_eq("token", "tokem"));
+        assert!(!crate::crypto::constant_time_eq("token", "token2"));
+        assert!(crate::crypto::constant_time_eq("", ""));
+        assert!(crate::crypto::constant_time_eq_bytes(&[1, 2], &[1, 2]));
+        assert!(!crate::crypto::constant_time_eq_bytes(&[1, 2], &[1]));
+    }
+
+    #[cfg(feature = "aead")]
+    #[test]
+    fn aead_chunked_test() {
+        use std::io::Cursor;
+
+        use crate::crypto::{
+            decrypt_aead_chunk, decrypt_aead_chunked, encrypt_aead_chunked, MAX_AEAD_CHUNK_SIZE,
+        };
+
+        let key = "foo";
+        // 3 full chunks of 16 bytes and a final one of 2
+        let plaintext: Vec<u8> = (0..50u8).collect();
+        let mut encrypted = Vec::new();
+        encrypt_aead_chunked(&mut plaintext.as_slice(), &mut encrypted, key, 16).unwrap();
+        assert_eq!(encrypted.len(), 16 + 3 * (16 + 16) + 2 + 16);
+
+        let mut decrypted = Vec::new();
+        decrypt_aead_chunked(&mut encrypted.as_slice(), &mut decrypted, key).unwrap();
+        assert_eq!(decrypted, plaintext);
+        let mut file = Cursor::new(encrypted.clone());
+        assert_eq!(
+            decrypt_aead_chunk(&mut file, key, 1).unwrap(),
+            &plaintext[16..32]
+        );
+        assert_eq!(
+            decrypt_aead_chunk(&mut file, key, 3).unwrap(),
+            &plaintext[48..]
+        );
+        assert!(decrypt_aead_chunk(&mut file, key, 4).is_err());
+
+        // a multiple of the chunk size ends with an empty chunk
+        let mut exact = Vec::new();
+        encrypt_aead_chunked(&mut &plaintext[..32], &mut exact, key, 16).unwrap();
+        let mut decrypted = Vec::new();
+        decrypt_aead_chunked(&mut exact.as_slice(), &mut decrypted, key).unwrap();
+        assert_eq!(decrypted, &plaintext[..32]);
+
+        let decrypt = |encrypted: &[u8], key: &str| {
+            decrypt_aead_chunked(&mut &encrypted[..], &mut Vec::new(), key)
+                .unwrap_err()
+                .kind()
+        };
+        assert_eq!(
+            decrypt(&encrypted, "bar"),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        let mut tampered = encrypted.clone();
+        tampered[16 + 32 + 5] ^= 1;
+        assert_eq!(decrypt(&tampered, key), DecryptErrorKind::WrongKeyOrCorrupt);
+        assert_eq!(
+            decrypt_aead_chunk(&mut Cursor::new(tampered), key, 1)
+                .unwrap_err()
+                .kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        // a forged chunk size does not allocate
+        let mut forged = encrypted.clone();
+        forged[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(decrypt(&forged, key), DecryptErrorKind::WrongKeyOrCorrupt);
+        assert_eq!(
+            decrypt_aead_chunk(&mut Cursor::new(forged), key, 0)
+                .unwrap_err()
+                .kind(),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        assert!(encrypt_aead_chunked(
+            &mut plaintext.as_slice(),
+            &mut Vec::new(),
+            key,
+            MAX_AEAD_CHUNK_SIZE + 1
+        )
+        .is_err());
+        // dropping the last chunk or swapping two chunks is detected too
+        assert_eq!(
+            decrypt(&encrypted[..16 + 3 * 32], key),
+            DecryptErrorKind::WrongKeyOrCorrupt
+        );
+        let mut swapped = encrypted[..16].to_vec();
+        swapped.extend_from_slice(&encrypted[48..80]);
+        swapped.extend_from_slice(&encrypted[16..48]);
+        swapped.extend_from_slice(&encrypted[80..]);
+        assert_eq!(decrypt(&swapped, key), DecryptErrorKind::WrongKeyOrCorrupt);
+        assert_eq!(decrypt(b"not chunked", key), DecryptErrorKind::Other);
+    }
+
+    #[cfg(feature = "aead")]
+    #[test]
+    fn aead_test() {
+        let msg = "https?";
+        let key = "foo";
+        let encrypted = crate::crypto::encrypt_aead(msg, key, "tenant-1");
+        assert_ne!(encrypted, crate::crypto::encrypt_aead(msg, key, "tenant-1"));
+        assert_eq!(
+            crate::crypto::decrypt_aead(&encrypted, key, "tenant-1").unwrap(),
+            msg
+        );
+
+        // wrong aad and wrong key
+        assert!(crate::crypto::decrypt_aead(&encrypted, key, "tenant-2").is_err());
+        assert!(crate::crypto::decrypt_aead(&encrypted, "bar", "tenant-1").is_err());
+
+        // tampering
+        let mut bytes = crate::crypto::base64_decode(&encrypted).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let tampered = crate::crypto::base64_encode(&bytes);
+        assert!(crate::crypto::decrypt_aead(&tampered, key, "tenant-1").is_err());
+
+        assert!(crate::crypto::decrypt_aead("short", key, "tenant-1").is_err());
+    }
 }
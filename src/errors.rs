@@ -1,12 +1,43 @@
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Why a decryption failed, see [`DecryptError::kind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecryptErrorKind {
+    /// the input is not valid base64
+    InvalidBase64,
+    /// the decoded input is not a whole number of cipher blocks, it can not be a ciphertext
+    InvalidPadding,
+    /// the input is a well-formed ciphertext but does not decrypt with the key
+    WrongKeyOrCorrupt,
+    /// any other failure, like a key that can not be loaded
+    Other,
+}
 
 #[derive(Debug)]
 pub struct DecryptError {
+    pub(crate) kind: DecryptErrorKind,
     pub(crate) details: String,
 }
 
+impl DecryptError {
+    /// build the error of a failed `Encryptor::decrypt`
+    pub fn new(kind: DecryptErrorKind, details: impl Into<String>) -> Self {
+        DecryptError {
+            kind,
+            details: details.into(),
+        }
+    }
+
+    pub fn kind(&self) -> DecryptErrorKind {
+        self.kind
+    }
+}
+
 impl Error for DecryptError {}
 
 impl Display for DecryptError {
@@ -15,9 +46,119 @@ impl Display for DecryptError {
     }
 }
 
+/// An error with a message describing what was being done, built by
+/// [`crate::prelude::EnhancedContext::context`]. The wrapped error is its
+/// [`Error::source`].
+#[derive(Debug)]
+pub struct BusyError {
+    pub(crate) context: String,
+    pub(crate) source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl BusyError {
+    /// return the context message, without the source
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+}
+
+impl Error for BusyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+impl Display for BusyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}: {}", self.context, source),
+            None => write!(f, "{}", self.context),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EncryptError {
+    pub(crate) details: String,
+}
+
+impl EncryptError {
+    pub fn new(details: impl Into<String>) -> Self {
+        EncryptError {
+            details: details.into(),
+        }
+    }
+}
+
+impl Error for EncryptError {}
+
+impl Display for EncryptError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+#[derive(Debug)]
+pub struct KeyError {
+    pub(crate) details: String,
+}
+
+impl Error for KeyError {}
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl From<KeyError> for DecryptError {
+    fn from(error: KeyError) -> Self {
+        DecryptError::new(DecryptErrorKind::Other, error.details)
+    }
+}
+
+/// Why `crypto::verify_token` rejected a token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenError {
+    /// the token was not issued by `crypto::issue_token`
+    Malformed,
+    /// the signature does not match, the token was tampered with or signed with another key
+    InvalidSignature,
+    /// the token is past its expiry
+    Expired,
+}
+
+impl Error for TokenError {}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TokenError::Malformed => write!(f, "malformed token"),
+            TokenError::InvalidSignature => write!(f, "invalid token signature"),
+            TokenError::Expired => write!(f, "token expired"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RemoveFilesError {
     pub(crate) details: String,
+    pub(crate) dir: Option<PathBuf>,
+}
+
+impl RemoveFilesError {
+    #[cfg(feature = "logger")]
+    pub(crate) fn new(dir: impl AsRef<Path>, details: String) -> Self {
+        Self {
+            details,
+            dir: Some(dir.as_ref().to_path_buf()),
+        }
+    }
+
+    /// The directory being cleaned up when the error occurred, if any.
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
 }
 
 impl Error for RemoveFilesError {}
@@ -28,10 +169,240 @@ impl Display for RemoveFilesError {
     }
 }
 
+#[cfg(feature = "logger")]
 impl From<tokio_cron_scheduler::JobSchedulerError> for RemoveFilesError {
     fn from(error: tokio_cron_scheduler::JobSchedulerError) -> Self {
         Self {
             details: error.to_string(),
+            dir: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScheduleError {
+    pub(crate) details: String,
+}
+
+impl Error for ScheduleError {}
+
+impl Display for ScheduleError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+#[cfg(feature = "scheduler")]
+impl From<tokio_cron_scheduler::JobSchedulerError> for ScheduleError {
+    fn from(error: tokio_cron_scheduler::JobSchedulerError) -> Self {
+        Self {
+            details: error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InitLoggerError {
+    pub(crate) details: String,
+}
+
+impl Error for InitLoggerError {}
+
+impl Display for InitLoggerError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseLogError {
+    pub(crate) details: String,
+}
+
+impl Error for ParseLogError {}
+
+impl Display for ParseLogError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// a config source could not be read, deserialized or applied
+    Load(String),
+    /// the value of `key` is missing or invalid
+    Invalid { key: String, details: String },
+    /// every problem found by a `config::ConfigValidator`
+    Multiple(Vec<ConfigError>),
+}
+
+impl Error for ConfigError {}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Load(details) => write!(f, "{}", details),
+            ConfigError::Invalid { key, details } => write!(f, "{}: {}", key, details),
+            ConfigError::Multiple(errors) => {
+                write!(f, "{} config errors", errors.len())?;
+                for (i, error) in errors.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { ":" } else { ";" }, error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WatchConfigError {
+    pub(crate) details: String,
+}
+
+impl Error for WatchConfigError {}
+
+impl Display for WatchConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+#[derive(Debug)]
+pub struct TlsConfigError {
+    pub(crate) details: String,
+}
+
+impl Error for TlsConfigError {}
+
+impl Display for TlsConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeoutError {
+    pub(crate) duration: Duration,
+}
+
+impl TimeoutError {
+    /// return the timeout that elapsed
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl Error for TimeoutError {}
+
+impl Display for TimeoutError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "operation timed out after {:?}", self.duration)
+    }
+}
+
+#[derive(Debug)]
+pub enum CircuitBreakerError<E> {
+    /// the circuit is open, the call was rejected without reaching the downstream
+    Open,
+    /// the call went through and failed
+    Inner(E),
+}
+
+impl<E: Error> Error for CircuitBreakerError<E> {}
+
+impl<E: Display> Display for CircuitBreakerError<E> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CircuitBreakerError::Open => write!(f, "circuit breaker is open"),
+            CircuitBreakerError::Inner(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    /// the request or connect timed out
+    Timeout(String),
+    /// failed to connect to the remote host
+    Connect(String),
+    /// the server responded with a 4xx/5xx status code
+    Status(u16),
+    /// the response body could not be decoded
+    Decode(String),
+    /// the request was rejected by an open circuit breaker without being sent
+    CircuitOpen,
+    /// the target address is not allowed by the `http::IpFilter` of the client
+    Blocked(IpAddr),
+    /// the response body is larger than the limit in bytes
+    TooLarge(u64),
+    /// the Content-Type of the response is not allowed
+    ContentType(String),
+    Other(String),
+}
+
+impl HttpError {
+    pub fn status(code: u16) -> Self {
+        HttpError::Status(code)
+    }
+}
+
+impl Error for HttpError {}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            HttpError::Timeout(details) => write!(f, "http timeout: {}", details),
+            HttpError::Connect(details) => write!(f, "http connect error: {}", details),
+            #[cfg(feature = "http")]
+            HttpError::Status(code) => match reqwest::StatusCode::from_u16(*code)
+                .ok()
+                .and_then(|s| s.canonical_reason())
+            {
+                Some(reason) => write!(f, "http status {} {}", code, reason),
+                None => write!(f, "http status {}", code),
+            },
+            #[cfg(not(feature = "http"))]
+            HttpError::Status(code) => write!(f, "http status {}", code),
+            HttpError::Decode(details) => write!(f, "http decode error: {}", details),
+            HttpError::CircuitOpen => write!(f, "http circuit breaker is open"),
+            HttpError::Blocked(addr) => write!(f, "http request to {} is blocked", addr),
+            HttpError::TooLarge(limit) => write!(f, "http response larger than {} bytes", limit),
+            HttpError::ContentType(content_type) => {
+                write!(f, "http content type {:?} is not allowed", content_type)
+            }
+            HttpError::Other(details) => write!(f, "http error: {}", details),
+        }
+    }
+}
+
+impl From<TimeoutError> for HttpError {
+    fn from(error: TimeoutError) -> Self {
+        HttpError::Timeout(error.to_string())
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<reqwest::Error> for HttpError {
+    fn from(error: reqwest::Error) -> Self {
+        // blocked by the resolver or the redirect policy of an `http::IpFilteredClient`
+        let mut source = error.source();
+        while let Some(e) = source {
+            if let Some(HttpError::Blocked(addr)) = e.downcast_ref::<HttpError>() {
+                return HttpError::Blocked(*addr);
+            }
+            source = e.source();
+        }
+        if error.is_timeout() {
+            HttpError::Timeout(error.to_string())
+        } else if error.is_connect() {
+            HttpError::Connect(error.to_string())
+        } else if let Some(status) = error.status() {
+            HttpError::Status(status.as_u16())
+        } else if error.is_decode() {
+            HttpError::Decode(error.to_string())
+        } else {
+            HttpError::Other(error.to_string())
         }
     }
 }
@@ -1,11 +1,2338 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tracing::{Level, Span};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+use uuid::Uuid;
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use crate::errors::TlsConfigError;
+use crate::errors::{CircuitBreakerError, HttpError};
 use crate::prelude::EnhancedUnwrap;
+use crate::retry::{retry_notify, RetryPolicy};
 
 pub type ReqwestError = reqwest::Error;
 pub type ReqwestClient = reqwest::Client;
+pub type CookieJar = reqwest::cookie::Jar;
+pub type StatusCode = reqwest::StatusCode;
+
+/// The TLS backend is chosen by cargo features, `rustls-tls` (default) needs no OpenSSL and
+/// allows fully static musl binaries, `native-tls` uses the platform TLS library.
+/// Enable only one of them, to switch use `default-features = false` and list `native-tls` with
+/// the other modules needed. When both are enabled rustls is used except for PKCS#12 identities.
+fn client_builder() -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder().timeout(timeout_from_env());
+    #[cfg(feature = "rustls-tls")]
+    let builder = builder.use_rustls_tls();
+    #[cfg(all(feature = "native-tls", not(feature = "rustls-tls")))]
+    let builder = builder.use_native_tls();
+    builder
+}
+
+/// Env var overriding the timeout of the clients built by this module, in seconds like `30` or
+/// `2.5`, read when a client is built.
+pub const HTTP_TIMEOUT_ENV: &str = "HTTP_TIMEOUT_SECS";
+
+/// Timeout of the clients built by this module when [`HTTP_TIMEOUT_ENV`] is unset or invalid.
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// return the timeout in [`HTTP_TIMEOUT_ENV`], see [`parse_timeout`]
+fn timeout_from_env() -> Duration {
+    parse_timeout(std::env::var(HTTP_TIMEOUT_ENV).ok().as_deref())
+}
 
+/// return the timeout of `secs`, the value of [`HTTP_TIMEOUT_ENV`], if it is None return
+/// [`DEFAULT_HTTP_TIMEOUT`], if it is invalid or not positive log a warning and return
+/// [`DEFAULT_HTTP_TIMEOUT`]
+fn parse_timeout(secs: Option<&str>) -> Duration {
+    let Some(secs) = secs else {
+        return DEFAULT_HTTP_TIMEOUT;
+    };
+    match secs.trim().parse().map(Duration::try_from_secs_f64) {
+        Ok(Ok(timeout)) if !timeout.is_zero() => timeout,
+        _ => {
+            tracing::warn!(
+                "invalid timeout {:?} in env var {}, using default {:?}",
+                secs,
+                HTTP_TIMEOUT_ENV,
+                DEFAULT_HTTP_TIMEOUT
+            );
+            DEFAULT_HTTP_TIMEOUT
+        }
+    }
+}
+
+/// return a client with the timeout of [`HTTP_TIMEOUT_ENV`], 10s by default
 pub fn default_reqwest_client() -> reqwest::Client {
-    reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
+    client_builder().build().unwp()
+}
+
+/// Turn a 4xx/5xx `resp` into [`HttpError::Status`].
+pub fn check_status(resp: reqwest::Response) -> Result<reqwest::Response, HttpError> {
+    let status = resp.status();
+    if status.is_client_error() || status.is_server_error() {
+        return Err(HttpError::status(status.as_u16()));
+    }
+    Ok(resp)
+}
+
+/// `GET url` and deserialize the JSON response, a 4xx/5xx status is an error. `timeout`
+/// overrides the timeout of `client` for this request only.
+///
+/// ```rust,ignore
+/// let user: User = get_json(&client, "https://example.com/users/1", None).await?;
+/// let report: Report = get_json(&client, report_url, Some(Duration::from_secs(60))).await?;
+/// ```
+pub async fn get_json<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    timeout: Option<Duration>,
+) -> Result<T, HttpError> {
+    let resp = apply_timeout(client.get(url), timeout).send().await?;
+    Ok(check_status(resp)?.json().await?)
+}
+
+/// `POST` `body` as JSON to `url` and deserialize the JSON response, see [`get_json`].
+pub async fn post_json<B: Serialize + ?Sized, T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    body: &B,
+    timeout: Option<Duration>,
+) -> Result<T, HttpError> {
+    let resp = apply_timeout(client.post(url).json(body), timeout)
+        .send()
+        .await?;
+    Ok(check_status(resp)?.json().await?)
+}
+
+/// Send `request` and return the status code with the JSON response deserialized, for APIs
+/// where the 2xx code matters like 200 vs 201. A 4xx/5xx status is [`HttpError::Status`], use
+/// [`send_typed_with_error`] to read the error body.
+///
+/// ```rust,ignore
+/// let (status, user): (StatusCode, User) = send_typed(client.post(url).json(&new_user)).await?;
+/// let created = status == StatusCode::CREATED;
+/// ```
+pub async fn send_typed<T: DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+) -> Result<(StatusCode, T), HttpError> {
+    let resp = check_status(request.send().await?)?;
+    Ok((resp.status(), resp.json().await?))
+}
+
+/// [`send_typed`] deserializing the JSON body of a 4xx/5xx response as `E` instead of failing,
+/// for APIs returning structured error payloads. An error body that is not a valid `E` is
+/// [`HttpError::Decode`].
+///
+/// ```rust,ignore
+/// match send_typed_with_error::<User, ApiError>(client.get(url)).await? {
+///     (_, Ok(user)) => println!("{}", user.name),
+///     (status, Err(e)) => warn!("{} {}", status, e.message),
+/// }
+/// ```
+pub async fn send_typed_with_error<T: DeserializeOwned, E: DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+) -> Result<(StatusCode, Result<T, E>), HttpError> {
+    let resp = request.send().await?;
+    let status = resp.status();
+    if status.is_client_error() || status.is_server_error() {
+        return Ok((status, Err(resp.json().await?)));
+    }
+    Ok((status, Ok(resp.json().await?)))
+}
+
+/// Send `request` retrying timeouts, connect errors, 429 and 5xx responses according to
+/// `policy`, `timeout` overrides the client timeout of every attempt. The last error is
+/// returned when the retries are exhausted, a last 429 or 5xx response as
+/// [`HttpError::Status`]. A request with a streamed body can not be retried and fails with
+/// [`HttpError::Other`].
+pub async fn send_with_retry(
+    request: reqwest::RequestBuilder,
+    policy: &RetryPolicy,
+    timeout: Option<Duration>,
+) -> Result<reqwest::Response, HttpError> {
+    let request = apply_timeout(request, timeout);
+    retry_logged(policy, || async {
+        let attempt = request
+            .try_clone()
+            .ok_or_else(|| HttpError::Other("request body can not be retried".to_string()))?;
+        check_status(attempt.send().await?)
+    })
+    .await
+}
+
+/// Retry `operation` on the errors of [`is_retryable`], logging a WARN event with the attempt
+/// that failed, its error or status and the backoff before each retry, and one when giving up
+/// with the number of attempts.
+async fn retry_logged<T, Fut, Op>(policy: &RetryPolicy, operation: Op) -> Result<T, HttpError>
+where
+    Op: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, HttpError>>,
+{
+    let mut retries = 0;
+    let result = retry_notify(
+        policy,
+        is_retryable,
+        |e, retry, backoff| {
+            retries = retry;
+            tracing::warn!(
+                attempt = retry,
+                error = %e,
+                status = error_status(e),
+                backoff_ms = backoff.as_millis() as u64,
+                "http request failed, retrying"
+            );
+        },
+        operation,
+    )
+    .await;
+    if let Err(e) = &result {
+        if retries > 0 || is_retryable(e) {
+            tracing::warn!(
+                attempts = retries + 1,
+                error = %e,
+                status = error_status(e),
+                "http request failed, giving up"
+            );
+        }
+    }
+    result
+}
+
+/// return the status code of [`HttpError::Status`]
+fn error_status(e: &HttpError) -> Option<u16> {
+    match e {
+        HttpError::Status(code) => Some(*code),
+        _ => None,
+    }
+}
+
+/// whether `e` is worth retrying: timeouts, connect errors, 429 and 5xx responses
+fn is_retryable(e: &HttpError) -> bool {
+    match e {
+        HttpError::Timeout(_) | HttpError::Connect(_) => true,
+        HttpError::Status(code) => *code == 429 || *code >= 500,
+        _ => false,
+    }
+}
+
+/// set `timeout` on `request` if any, it overrides the client timeout
+fn apply_timeout(
+    request: reqwest::RequestBuilder,
+    timeout: Option<Duration>,
+) -> reqwest::RequestBuilder {
+    match timeout {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
+
+/// `GET url` for readiness probes, `Ok(true)` on a 2xx response within `timeout` and
+/// `Ok(false)` on any other status, errors are reserved for transport failures like
+/// [`HttpError::Timeout`] and [`HttpError::Connect`].
+///
+/// ```rust,ignore
+/// let ready = health_check(&client, "http://127.0.0.1:8080/health", Duration::from_secs(1)).await?;
+/// ```
+pub async fn health_check(
+    client: &reqwest::Client,
+    url: &str,
+    timeout: Duration,
+) -> Result<bool, HttpError> {
+    let resp = client.get(url).timeout(timeout).send().await?;
+    Ok(resp.status().is_success())
+}
+
+/// [`health_check`] expecting exactly the status code `expected`.
+pub async fn health_check_status(
+    client: &reqwest::Client,
+    url: &str,
+    timeout: Duration,
+    expected: u16,
+) -> Result<bool, HttpError> {
+    let resp = client.get(url).timeout(timeout).send().await?;
+    Ok(resp.status().as_u16() == expected)
+}
+
+/// Upload the file at `path` as the multipart/form-data field `field_name`, the file is
+/// streamed from disk and its Content-Type guessed from the extension.
+///
+/// ```rust,ignore
+/// let resp = upload_file(&client, "https://example.com/upload", "file", "/tmp/report.csv").await?;
+/// ```
+pub async fn upload_file(
+    client: &reqwest::Client,
+    url: &str,
+    field_name: &str,
+    path: impl AsRef<std::path::Path>,
+) -> Result<reqwest::Response, HttpError> {
+    upload_file_with_fields(client, url, field_name, path, &[]).await
+}
+
+/// [`upload_file`] sending the text `fields` along with the file.
+pub async fn upload_file_with_fields(
+    client: &reqwest::Client,
+    url: &str,
+    field_name: &str,
+    path: impl AsRef<std::path::Path>,
+    fields: &[(&str, &str)],
+) -> Result<reqwest::Response, HttpError> {
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| HttpError::Other(format!("open upload file {:?} failed: {}", path, e)))?;
+    let len = file
+        .metadata()
+        .await
+        .map_err(|e| HttpError::Other(format!("read upload file {:?} failed: {}", path, e)))?
+        .len();
+    let mut part =
+        reqwest::multipart::Part::stream_with_length(file, len).mime_str(content_type(
+            path.extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default(),
+        ))?;
+    if let Some(name) = path.file_name() {
+        part = part.file_name(name.to_string_lossy().to_string());
+    }
+    let mut form = reqwest::multipart::Form::new();
+    for (name, value) in fields {
+        form = form.text(name.to_string(), value.to_string());
+    }
+    form = form.part(field_name.to_string(), part);
+    Ok(client.post(url).multipart(form).send().await?)
+}
+
+/// return the Content-Type of common file extensions, `application/octet-stream` otherwise
+fn content_type(extension: &str) -> &'static str {
+    match extension.to_ascii_lowercase().as_str() {
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Limits of [`download_file_with_limits`] for untrusted URLs, none by default.
+#[derive(Clone, Debug, Default)]
+pub struct DownloadLimits {
+    /// abort once more bytes are received, whatever the Content-Length says
+    pub max_bytes: Option<u64>,
+    /// accepted media types like `application/pdf`, or `image/*` for a whole type, any if empty
+    pub allowed_content_types: Vec<String>,
+}
+
+impl DownloadLimits {
+    fn check_content_type(&self, resp: &reqwest::Response) -> Result<(), HttpError> {
+        if self.allowed_content_types.is_empty() {
+            return Ok(());
+        }
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let allowed = self.allowed_content_types.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            match allowed.strip_suffix("/*") {
+                Some(top) => media_type.split('/').next() == Some(top),
+                None => media_type == allowed,
+            }
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(HttpError::ContentType(content_type.to_string()))
+        }
+    }
+}
+
+/// `GET url` and stream the body into the file at `path`, return the number of bytes written.
+/// The body goes to `{path}.part` first and is renamed once complete, a failed download leaves
+/// no file behind.
+///
+/// ```rust,ignore
+/// let size = download_file(&client, "https://example.com/report.csv", "/tmp/report.csv").await?;
+/// ```
+pub async fn download_file(
+    client: &reqwest::Client,
+    url: &str,
+    path: impl AsRef<std::path::Path>,
+) -> Result<u64, HttpError> {
+    download_file_with_limits(client, url, path, &DownloadLimits::default()).await
+}
+
+/// [`download_file`] failing with [`HttpError::TooLarge`] as soon as the body exceeds
+/// `limits.max_bytes` and with [`HttpError::ContentType`] before writing anything if the
+/// Content-Type is not allowed.
+pub async fn download_file_with_limits(
+    client: &reqwest::Client,
+    url: &str,
+    path: impl AsRef<std::path::Path>,
+    limits: &DownloadLimits,
+) -> Result<u64, HttpError> {
+    let path = path.as_ref();
+    let mut resp = check_status(client.get(url).send().await?)?;
+    limits.check_content_type(&resp)?;
+    if let (Some(max), Some(len)) = (limits.max_bytes, resp.content_length()) {
+        if len > max {
+            return Err(HttpError::TooLarge(max));
+        }
+    }
+
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = std::path::PathBuf::from(part);
+    let file_error =
+        |e: std::io::Error| HttpError::Other(format!("write {:?} failed: {}", path, e));
+    let mut file = tokio::fs::File::create(&part).await.map_err(file_error)?;
+    let result = async {
+        let mut written = 0u64;
+        while let Some(chunk) = resp.chunk().await? {
+            written += chunk.len() as u64;
+            if limits.max_bytes.is_some_and(|max| written > max) {
+                return Err(HttpError::TooLarge(limits.max_bytes.unwp()));
+            }
+            file.write_all(&chunk).await.map_err(file_error)?;
+        }
+        file.flush().await.map_err(file_error)?;
+        Ok(written)
+    }
+    .await;
+    drop(file);
+    match result {
+        Ok(written) => {
+            tokio::fs::rename(&part, path).await.map_err(file_error)?;
+            Ok(written)
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part).await;
+            Err(e)
+        }
+    }
+}
+
+/// Same as [`default_reqwest_client`] but cookies set by responses are stored in `jar` and sent
+/// back on subsequent requests to the same domain, keep a clone of `jar` to inspect it.
+///
+/// ```rust,ignore
+/// let jar = Arc::new(CookieJar::default());
+/// let client = cookie_reqwest_client(jar.clone());
+/// client.post(login_url).send().await?;
+/// let cookies = jar.cookies(&login_url);
+/// ```
+pub fn cookie_reqwest_client(jar: Arc<CookieJar>) -> reqwest::Client {
+    client_builder().cookie_provider(jar).build().unwp()
+}
+
+/// return a client accepting any server certificate, self-signed, expired or for another host,
+/// to reach local dev services. Never use it in production, it logs a WARN event every time it
+/// is built and only exists with the `insecure-tls` feature, enable it for dev builds only:
+///
+/// ```toml
+/// [features]
+/// dev = ["busylib/insecure-tls"]
+/// ```
+#[cfg(all(
+    feature = "insecure-tls",
+    any(feature = "rustls-tls", feature = "native-tls")
+))]
+pub fn insecure_reqwest_client() -> reqwest::Client {
+    tracing::warn!(
+        "INSECURE http client built, TLS certificates are NOT verified, never use it in production"
+    );
+    client_builder()
+        .danger_accept_invalid_certs(true)
         .build()
         .unwp()
 }
+
+/// Client identity presented to servers requiring mutual TLS.
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+#[derive(Clone, Copy, Debug)]
+pub enum ClientIdentity<'a> {
+    /// PEM containing the private key and the certificate chain, needs the `rustls-tls` feature
+    #[cfg(feature = "rustls-tls")]
+    Pem(&'a [u8]),
+    /// DER-encoded PKCS#12 archive protected by `password`, needs the `native-tls` feature
+    #[cfg(feature = "native-tls")]
+    Pkcs12 { der: &'a [u8], password: &'a str },
+}
+
+/// Same as [`default_reqwest_client`] but authenticating with a client certificate,
+/// `root_ca_pem` is trusted in addition to the built-in roots.
+///
+/// ```rust,ignore
+/// let identity = std::fs::read("/etc/certs/client.pem")?;
+/// let client = mtls_reqwest_client(ClientIdentity::Pem(&identity), None)?;
+/// ```
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+pub fn mtls_reqwest_client(
+    identity: ClientIdentity,
+    root_ca_pem: Option<&[u8]>,
+) -> Result<ReqwestClient, TlsConfigError> {
+    let mut builder = client_builder();
+    builder = match identity {
+        #[cfg(feature = "rustls-tls")]
+        ClientIdentity::Pem(pem) => {
+            let identity = reqwest::Identity::from_pem(pem).map_err(|e| TlsConfigError {
+                details: format!(
+                    "invalid client identity, expect a PEM with a private key and certificate: {}",
+                    e
+                ),
+            })?;
+            builder.use_rustls_tls().identity(identity)
+        }
+        #[cfg(feature = "native-tls")]
+        ClientIdentity::Pkcs12 { der, password } => {
+            let identity =
+                reqwest::Identity::from_pkcs12_der(der, password).map_err(|e| TlsConfigError {
+                    details: format!("invalid PKCS#12 client identity: {}", e),
+                })?;
+            builder.use_native_tls().identity(identity)
+        }
+    };
+    if let Some(pem) = root_ca_pem {
+        // rustls silently ignores a PEM without any certificate in it
+        if !String::from_utf8_lossy(pem).contains("-----BEGIN CERTIFICATE-----") {
+            return Err(TlsConfigError {
+                details: "invalid root CA certificate: no PEM certificate found".to_string(),
+            });
+        }
+        let cert = reqwest::Certificate::from_pem(pem).map_err(|e| TlsConfigError {
+            details: format!("invalid root CA certificate: {}", e),
+        })?;
+        builder = builder.add_root_certificate(cert);
+    }
+    builder.build().map_err(|e| TlsConfigError {
+        details: format!("failed to build TLS client: {}", e),
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// calls go through, outcomes are recorded in the sliding window
+    Closed,
+    /// calls are rejected immediately until `open_duration` elapsed
+    Open,
+    /// a single probe call is let through to check whether the downstream recovered
+    HalfOpen,
+}
+
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerConfig {
+    /// number of most recent calls considered when computing the failure rate
+    pub window_size: usize,
+    /// minimum number of calls in the window before the breaker may open
+    pub min_calls: usize,
+    /// failure rate in `0.0..=1.0` at which the breaker opens
+    pub failure_rate_threshold: f64,
+    /// how long the breaker stays open before half-opening
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 20,
+            min_calls: 10,
+            failure_rate_threshold: 0.5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    state: CircuitState,
+    // `true` for a failed call
+    window: VecDeque<bool>,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+/// Wrap a [`ReqwestClient`] and stop calling a flaky downstream once its failure rate goes over
+/// the threshold, which prevents retry storms. Clones share the same state.
+///
+/// ```rust,ignore
+/// let breaker = CircuitBreaker::new(default_reqwest_client(), CircuitBreakerConfig::default());
+/// let resp = breaker.send(breaker.client().get("https://example.com")).await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    client: ReqwestClient,
+    config: CircuitBreakerConfig,
+    inner: Arc<Mutex<BreakerState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(client: ReqwestClient, config: CircuitBreakerConfig) -> Self {
+        Self {
+            client,
+            config,
+            inner: Arc::new(Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                window: VecDeque::new(),
+                opened_at: None,
+                probing: false,
+            })),
+        }
+    }
+
+    pub fn client(&self) -> &ReqwestClient {
+        &self.client
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let mut inner = self.inner.lock().unwp();
+        self.refresh(&mut inner);
+        inner.state
+    }
+
+    /// Send `request` through the breaker, transport errors and 5xx responses count as failures.
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, CircuitBreakerError<HttpError>> {
+        let permit = self.acquire()?;
+        let result = request.send().await;
+        permit.record(matches!(&result, Ok(resp) if !resp.status().is_server_error()));
+        result.map_err(|e| CircuitBreakerError::Inner(e.into()))
+    }
+
+    /// Run any fallible async operation through the breaker, `Err` counts as a failure.
+    pub async fn call<T, E, F, Fut>(&self, f: F) -> Result<T, CircuitBreakerError<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let permit = self.acquire()?;
+        let result = f().await;
+        permit.record(result.is_ok());
+        result.map_err(CircuitBreakerError::Inner)
+    }
+
+    fn refresh(&self, inner: &mut BreakerState) {
+        if inner.state == CircuitState::Open
+            && !matches!(inner.opened_at, Some(t) if t.elapsed() < self.config.open_duration)
+        {
+            inner.state = CircuitState::HalfOpen;
+            inner.probing = false;
+        }
+    }
+
+    fn acquire<E>(&self) -> Result<BreakerPermit<'_>, CircuitBreakerError<E>> {
+        let mut inner = self.inner.lock().unwp();
+        self.refresh(&mut inner);
+        match inner.state {
+            CircuitState::Closed => {}
+            CircuitState::Open => return Err(CircuitBreakerError::Open),
+            CircuitState::HalfOpen if inner.probing => return Err(CircuitBreakerError::Open),
+            CircuitState::HalfOpen => inner.probing = true,
+        }
+        Ok(BreakerPermit {
+            breaker: self,
+            recorded: false,
+        })
+    }
+
+    fn record(&self, success: bool) {
+        let mut inner = self.inner.lock().unwp();
+        match inner.state {
+            CircuitState::HalfOpen if success => {
+                inner.state = CircuitState::Closed;
+                inner.window.clear();
+                inner.probing = false;
+            }
+            CircuitState::HalfOpen => {
+                inner.state = CircuitState::Open;
+                inner.opened_at = Some(Instant::now());
+                inner.probing = false;
+            }
+            CircuitState::Closed => {
+                inner.window.push_back(!success);
+                while inner.window.len() > self.config.window_size {
+                    inner.window.pop_front();
+                }
+                let failures = inner.window.iter().filter(|failed| **failed).count();
+                if inner.window.len() >= self.config.min_calls
+                    && failures as f64 / inner.window.len() as f64
+                        >= self.config.failure_rate_threshold
+                {
+                    inner.state = CircuitState::Open;
+                    inner.opened_at = Some(Instant::now());
+                    inner.window.clear();
+                }
+            }
+            // a call started before the breaker opened, nothing to record
+            CircuitState::Open => {}
+        }
+    }
+}
+
+/// A call let through by [`CircuitBreaker::acquire`], dropping it before
+/// [`BreakerPermit::record`] counts as a failure, so a cancelled half-open probe does not keep
+/// the breaker rejecting every call.
+struct BreakerPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl BreakerPermit<'_> {
+    fn record(mut self, success: bool) {
+        self.recorded = true;
+        self.breaker.record(success);
+    }
+}
+
+impl Drop for BreakerPermit<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.record(false);
+        }
+    }
+}
+
+/// Retries, rate limiting and circuit breaking in one client, each enabled in
+/// [`ResilientClientBuilder`]. Every attempt waits for the rate limiter then goes through the
+/// circuit breaker, attempts failing as in [`send_with_retry`] are retried but not the ones
+/// rejected by an open breaker. Clones share the rate limiter and breaker state.
+///
+/// ```rust,ignore
+/// let client = ResilientClient::builder(default_reqwest_client())
+///     .retry(RetryPolicy::default())
+///     .rate_limit(50)
+///     .circuit_breaker(CircuitBreakerConfig::default())
+///     .build();
+/// let user: User = client.get_json("https://example.com/users/1", None).await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ResilientClient {
+    client: ReqwestClient,
+    retry: Option<RetryPolicy>,
+    rate_limiter: Option<RateLimitedClient>,
+    breaker: Option<CircuitBreaker>,
+}
+
+pub struct ResilientClientBuilder {
+    client: ReqwestClient,
+    retry: Option<RetryPolicy>,
+    requests_per_sec: Option<u32>,
+    breaker: Option<CircuitBreakerConfig>,
+}
+
+impl ResilientClientBuilder {
+    /// Retry failed attempts according to `policy`.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Send at most `requests_per_sec` attempts per second, see [`RateLimitedClient`].
+    pub fn rate_limit(mut self, requests_per_sec: u32) -> Self {
+        self.requests_per_sec = Some(requests_per_sec);
+        self
+    }
+
+    /// Stop calling a failing downstream, see [`CircuitBreaker`].
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = Some(config);
+        self
+    }
+
+    pub fn build(self) -> ResilientClient {
+        ResilientClient {
+            rate_limiter: self
+                .requests_per_sec
+                .map(|rate| RateLimitedClient::new(self.client.clone(), rate)),
+            breaker: self
+                .breaker
+                .map(|config| CircuitBreaker::new(self.client.clone(), config)),
+            client: self.client,
+            retry: self.retry,
+        }
+    }
+}
+
+impl ResilientClient {
+    pub fn builder(client: ReqwestClient) -> ResilientClientBuilder {
+        ResilientClientBuilder {
+            client,
+            retry: None,
+            requests_per_sec: None,
+            breaker: None,
+        }
+    }
+
+    pub fn client(&self) -> &ReqwestClient {
+        &self.client
+    }
+
+    /// return the state of the circuit breaker, `None` if it is not enabled
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker.as_ref().map(|b| b.state())
+    }
+
+    /// Send `request`, a 4xx/5xx status is an error. A request with a streamed body can not be
+    /// retried and fails with [`HttpError::Other`] when retries are enabled.
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HttpError> {
+        let Some(policy) = &self.retry else {
+            return self.attempt(request).await;
+        };
+        retry_logged(policy, || async {
+            let attempt = request
+                .try_clone()
+                .ok_or_else(|| HttpError::Other("request body can not be retried".to_string()))?;
+            self.attempt(attempt).await
+        })
+        .await
+    }
+
+    /// [`get_json`] through this client.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        timeout: Option<Duration>,
+    ) -> Result<T, HttpError> {
+        let resp = self
+            .send(apply_timeout(self.client.get(url), timeout))
+            .await?;
+        Ok(resp.json().await?)
+    }
+
+    /// [`post_json`] through this client.
+    pub async fn post_json<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        url: &str,
+        body: &B,
+        timeout: Option<Duration>,
+    ) -> Result<T, HttpError> {
+        let resp = self
+            .send(apply_timeout(self.client.post(url).json(body), timeout))
+            .await?;
+        Ok(resp.json().await?)
+    }
+
+    async fn attempt(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HttpError> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
+        }
+        let resp = match &self.breaker {
+            Some(breaker) => breaker.send(request).await.map_err(|e| match e {
+                CircuitBreakerError::Open => HttpError::CircuitOpen,
+                CircuitBreakerError::Inner(e) => e,
+            })?,
+            None => request.send().await?,
+        };
+        check_status(resp)
+    }
+}
+
+/// Which IP addresses an [`IpFilteredClient`] may connect to. [`IpFilter::new`] blocks the
+/// loopback, private, link-local and other non-public ranges, [`IpFilter::deny`] blocks more and
+/// [`IpFilter::allow`] lets selected ranges through anyway.
+///
+/// ```rust,ignore
+/// let filter = IpFilter::new()
+///     .allow("10.1.0.0".parse()?, 16)
+///     .deny("203.0.113.7".parse()?, 32);
+/// ```
+#[derive(Clone, Debug)]
+pub struct IpFilter {
+    allow: Vec<(IpAddr, u8)>,
+    deny: Vec<(IpAddr, u8)>,
+}
+
+impl Default for IpFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        IpFilter {
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+
+    /// Allow the `prefix_len` bits network of `addr`, this wins over every deny.
+    pub fn allow(mut self, addr: IpAddr, prefix_len: u8) -> Self {
+        self.allow.push((addr, prefix_len));
+        self
+    }
+
+    /// Also deny the `prefix_len` bits network of `addr`.
+    pub fn deny(mut self, addr: IpAddr, prefix_len: u8) -> Self {
+        self.deny.push((addr, prefix_len));
+        self
+    }
+
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            v4 => v4,
+        };
+        if self
+            .allow
+            .iter()
+            .any(|(net, len)| in_network(addr, *net, *len))
+        {
+            return true;
+        }
+        is_public(addr)
+            && !self
+                .deny
+                .iter()
+                .any(|(net, len)| in_network(addr, *net, *len))
+    }
+
+    /// return Err [`HttpError::Blocked`] if `addr` is not allowed
+    pub fn check(&self, addr: IpAddr) -> Result<(), HttpError> {
+        if self.is_allowed(addr) {
+            Ok(())
+        } else {
+            Err(HttpError::Blocked(addr))
+        }
+    }
+
+    /// check the host of `url` if it is an IP literal, which is never resolved
+    fn check_url(&self, url: &reqwest::Url) -> Result<(), HttpError> {
+        let host = url.host_str().unwrap_or_default();
+        match host.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(addr) => self.check(addr),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+fn in_network(addr: IpAddr, net: IpAddr, prefix_len: u8) -> bool {
+    fn matches(addr: u128, net: u128, prefix_len: u8, bits: u8) -> bool {
+        let prefix_len = prefix_len.min(bits);
+        if prefix_len == 0 {
+            return true;
+        }
+        let shift = bits - prefix_len;
+        addr >> shift == net >> shift
+    }
+    match (addr, net) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            matches(u32::from(a) as u128, u32::from(n) as u128, prefix_len, 32)
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => matches(u128::from(a), u128::from(n), prefix_len, 128),
+        _ => false,
+    }
+}
+
+/// whether `addr` is routable on the internet, `IpAddr::is_global` is still unstable
+fn is_public(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || a == 0
+                // shared address space 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                // IETF protocol assignments 192.0.0.0/24
+                || (a == 192 && b == 0 && c == 0)
+                // benchmarking 198.18.0.0/15
+                || (a == 198 && (18..20).contains(&b))
+                // reserved 240.0.0.0/4
+                || a >= 240)
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            let first = segments[0];
+            let embedded_v4 = |high: u16, low: u16| {
+                IpAddr::V4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+            };
+            // NAT64 64:ff9b::/96 and 6to4 2002::/16 reach the embedded IPv4 address
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return is_public(embedded_v4(segments[6], segments[7]));
+            }
+            if first == 0x2002 {
+                return is_public(embedded_v4(segments[1], segments[2]));
+            }
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // unique local fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // link-local fe80::/10
+                || (first & 0xffc0) == 0xfe80
+                // deprecated site-local fec0::/10
+                || (first & 0xffc0) == 0xfec0
+                // documentation 2001:db8::/32
+                || (first == 0x2001 && segments[1] == 0x0db8))
+        }
+    }
+}
+
+/// Suffix of the proxy host that [`IpFilteredClient`] routes blocked IP literals to, reqwest
+/// connects to IP literals without resolving them, so the proxy is the only hook before
+/// connecting and [`FilteringResolver`] refuses to resolve that host.
+const BLOCKED_PROXY_SUFFIX: &str = ".blocked.invalid";
+
+/// return the proxy url of a blocked `addr`, its host carries the address as hex
+fn blocked_proxy_url(addr: IpAddr) -> String {
+    let v6 = match addr {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    };
+    format!("http://{:032x}{}", u128::from(v6), BLOCKED_PROXY_SUFFIX)
+}
+
+/// return the address carried by the host of [`blocked_proxy_url`]
+fn blocked_proxy_addr(host: &str) -> Option<IpAddr> {
+    let hex = host.strip_suffix(BLOCKED_PROXY_SUFFIX)?;
+    let v6 = Ipv6Addr::from(u128::from_str_radix(hex, 16).ok()?);
+    Some(v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4))
+}
+
+/// resolver of [`IpFilteredClient`] dropping the addresses its filter does not allow
+struct FilteringResolver {
+    filter: Arc<IpFilter>,
+}
+
+impl reqwest::dns::Resolve for FilteringResolver {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        let filter = self.filter.clone();
+        Box::pin(async move {
+            if let Some(blocked) = blocked_proxy_addr(name.as_str()) {
+                return Err(Box::new(HttpError::Blocked(blocked)) as _);
+            }
+            let resolved: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            let allowed: Vec<SocketAddr> = resolved
+                .iter()
+                .filter(|addr| filter.is_allowed(addr.ip()))
+                .copied()
+                .collect();
+            match (allowed.is_empty(), resolved.first()) {
+                (true, Some(blocked)) => Err(Box::new(HttpError::Blocked(blocked.ip())) as _),
+                _ => Ok(Box::new(allowed.into_iter()) as reqwest::dns::Addrs),
+            }
+        })
+    }
+}
+
+/// Client for URLs supplied by users that refuses to connect to the addresses its [`IpFilter`]
+/// blocks, by default anything but public addresses, against SSRF. Hosts are checked once
+/// resolved so DNS names pointing inside are blocked too, as are redirects to blocked IP
+/// literals. IP literals are checked by the client itself, so requests sent straight from
+/// [`IpFilteredClient::client`] are filtered as well. Env proxies are not used since they would
+/// resolve the hosts themselves. Requests to a blocked address fail with [`HttpError::Blocked`].
+///
+/// ```rust,ignore
+/// let client = IpFilteredClient::new(IpFilter::new());
+/// let resp = client.send(client.client().get(user_supplied_url)).await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct IpFilteredClient {
+    client: ReqwestClient,
+    filter: Arc<IpFilter>,
+}
+
+impl IpFilteredClient {
+    pub fn new(filter: IpFilter) -> Self {
+        let filter = Arc::new(filter);
+        let redirect_filter = filter.clone();
+        let proxy_filter = filter.clone();
+        let client = client_builder()
+            .no_proxy()
+            // route blocked IP literals to a proxy host the resolver refuses
+            .proxy(reqwest::Proxy::custom(move |url| {
+                match proxy_filter.check_url(url) {
+                    Err(HttpError::Blocked(addr)) => Some(blocked_proxy_url(addr)),
+                    _ => None,
+                }
+            }))
+            .dns_resolver(Arc::new(FilteringResolver {
+                filter: filter.clone(),
+            }))
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= 10 {
+                    return attempt.error("too many redirects");
+                }
+                match redirect_filter.check_url(attempt.url()) {
+                    Ok(()) => attempt.follow(),
+                    Err(e) => attempt.error(e),
+                }
+            }))
+            .build()
+            .unwp();
+        IpFilteredClient { client, filter }
+    }
+
+    /// the client to build requests with, send them with [`IpFilteredClient::send`], requests
+    /// sent by the client itself are filtered too
+    pub fn client(&self) -> &ReqwestClient {
+        &self.client
+    }
+
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HttpError> {
+        let request = request.build()?;
+        self.filter.check_url(request.url())?;
+        Ok(self.client.execute(request).await?)
+    }
+}
+
+/// Trace id shared by all spans under the same root span, created on first use.
+#[derive(Clone, Copy, Debug)]
+struct TraceId(Uuid);
+
+/// Return `(trace_id, span_id)` of the active `tracing` span in the W3C trace context format,
+/// the trace id is shared by every span under the same root span.
+/// `None` when there is no active span or the subscriber is not built on a [`Registry`],
+/// like the one `logger::init_logger` installs.
+pub fn current_trace_context() -> Option<(String, String)> {
+    Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+            let span = registry.span(id)?;
+            let root = span.scope().from_root().next()?;
+            let mut extensions = root.extensions_mut();
+            let trace_id = match extensions.get_mut::<TraceId>() {
+                Some(trace_id) => trace_id.0,
+                None => {
+                    let trace_id = Uuid::new_v4();
+                    extensions.insert(TraceId(trace_id));
+                    trace_id
+                }
+            };
+            Some((
+                trace_id.simple().to_string(),
+                format!("{:016x}", id.into_u64()),
+            ))
+        })
+        .flatten()
+}
+
+/// Add `traceparent` and `X-Request-ID` headers from the active span to `request`, so the
+/// downstream service can join its logs with ours. `request` is unchanged without an active span.
+///
+/// ```rust,ignore
+/// let span = tracing::info_span!("handle_order");
+/// let _enter = span.enter();
+/// with_trace_headers(client.get(url)).send().await?;
+/// ```
+pub fn with_trace_headers(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match current_trace_context() {
+        Some((trace_id, span_id)) => request
+            .header("traceparent", format!("00-{}-{}-01", trace_id, span_id))
+            .header("X-Request-ID", trace_id),
+        None => request,
+    }
+}
+
+/// Send `request` and emit one structured access log event at INFO with the target
+/// `busylib::http::access`, for latency dashboards built from the logs. The fields are
+/// `method`, `host`, `path`, `status` (0 when no response was received), `bytes` (the
+/// Content-Length of the response, 0 when unknown), `duration_ms` and `trace_id`, the one of
+/// [`current_trace_context`] to join the entry with the logs of the active request span, empty
+/// without one.
+///
+/// ```rust,ignore
+/// let resp = send_with_access_log(client.get("https://example.com/orders")).await?;
+/// ```
+pub async fn send_with_access_log(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, HttpError> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().to_string();
+    let host = request.url().host_str().unwrap_or_default().to_string();
+    let path = request.url().path().to_string();
+    let trace_id = current_trace_context()
+        .map(|(trace_id, _)| trace_id)
+        .unwrap_or_default();
+
+    let start = Instant::now();
+    let result = client.execute(request).await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let (status, bytes) = match &result {
+        Ok(resp) => (resp.status().as_u16(), resp.content_length().unwrap_or(0)),
+        Err(e) => (e.status().map(|s| s.as_u16()).unwrap_or(0), 0),
+    };
+    tracing::info!(
+        target: "busylib::http::access",
+        method = %method,
+        host = %host,
+        path = %path,
+        status,
+        bytes,
+        duration_ms,
+        trace_id = %trace_id,
+        "http access"
+    );
+    Ok(result?)
+}
+
+const REDACTED: &str = "***";
+
+/// Wrap a [`ReqwestClient`] and log every outgoing request (method, url, status, elapsed) via
+/// `tracing`, so requests end up in the same logs as `logger::init_logger` sets up.
+/// Only the scheme, host and path of the url are logged, credentials and the query are not.
+///
+/// ```rust,ignore
+/// let client = LoggingClient::new(default_reqwest_client()).with_level(Level::DEBUG);
+/// let resp = client.send(client.client().get("https://example.com")).await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct LoggingClient {
+    client: ReqwestClient,
+    level: Level,
+    log_body: bool,
+    redact_fields: Vec<String>,
+}
+
+impl LoggingClient {
+    /// Log at INFO without request bodies by default.
+    pub fn new(client: ReqwestClient) -> Self {
+        Self {
+            client,
+            level: Level::INFO,
+            log_body: false,
+            redact_fields: ["password", "token", "secret", "authorization"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Also log the request body, values of `redact_fields` in a JSON body are masked and
+    /// non-JSON bodies are only logged by size.
+    pub fn with_body(mut self, log_body: bool) -> Self {
+        self.log_body = log_body;
+        self
+    }
+
+    /// JSON keys (case-insensitive) whose values are masked when the body is logged.
+    pub fn with_redact_fields(mut self, fields: &[&str]) -> Self {
+        self.redact_fields = fields.iter().map(|f| f.to_lowercase()).collect();
+        self
+    }
+
+    pub fn client(&self) -> &ReqwestClient {
+        &self.client
+    }
+
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HttpError> {
+        let request = request.build()?;
+        let method = request.method().to_string();
+        let full_url = request.url().to_string();
+        let url = redact_url(request.url());
+        let body = match request.body().and_then(|b| b.as_bytes()) {
+            Some(bytes) if self.log_body => self.redact_body(bytes),
+            _ => String::new(),
+        };
+
+        let start = Instant::now();
+        let result = self.client.execute(request).await;
+        let elapsed_ms = start.elapsed().as_millis() as u64;
+        let (status, error) = match &result {
+            Ok(resp) => (resp.status().as_u16(), String::new()),
+            Err(e) => (
+                e.status().map(|s| s.as_u16()).unwrap_or(0),
+                e.to_string().replace(&full_url, &url),
+            ),
+        };
+        macro_rules! log_request {
+            ($level:expr) => {
+                tracing::event!(
+                    $level,
+                    method = %method,
+                    url = %url,
+                    status,
+                    elapsed_ms,
+                    body = %body,
+                    error = %error,
+                    "http request"
+                )
+            };
+        }
+        match self.level {
+            Level::TRACE => log_request!(Level::TRACE),
+            Level::DEBUG => log_request!(Level::DEBUG),
+            Level::INFO => log_request!(Level::INFO),
+            Level::WARN => log_request!(Level::WARN),
+            Level::ERROR => log_request!(Level::ERROR),
+        }
+        Ok(result?)
+    }
+
+    fn redact_body(&self, bytes: &[u8]) -> String {
+        match serde_json::from_slice::<Value>(bytes) {
+            Ok(mut value) => {
+                self.redact_value(&mut value);
+                value.to_string()
+            }
+            Err(_) => format!("<{} bytes>", bytes.len()),
+        }
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, v) in map.iter_mut() {
+                    if self.redact_fields.contains(&key.to_lowercase()) {
+                        *v = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_value(v);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.redact_value(v)),
+            _ => {}
+        }
+    }
+}
+
+/// return `url` without user info, query and fragment
+fn redact_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+    url.set_query(None);
+    url.set_fragment(None);
+    url.to_string()
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Wrap a [`ReqwestClient`] with a token bucket so that at most `requests_per_sec` requests are
+/// dispatched per second, bursts up to the same amount are allowed.
+/// Clones share one bucket, so concurrent tasks respect one global limit.
+///
+/// ```rust,ignore
+/// let client = RateLimitedClient::new(default_reqwest_client(), 5);
+/// let resp = client.send(client.client().get("https://example.com")).await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct RateLimitedClient {
+    client: ReqwestClient,
+    rate: f64,
+    bucket: Arc<tokio::sync::Mutex<TokenBucket>>,
+}
+
+impl RateLimitedClient {
+    pub fn new(client: ReqwestClient, requests_per_sec: u32) -> Self {
+        let rate = requests_per_sec.max(1) as f64;
+        Self {
+            client,
+            rate,
+            bucket: Arc::new(tokio::sync::Mutex::new(TokenBucket {
+                tokens: rate,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn client(&self) -> &ReqwestClient {
+        &self.client
+    }
+
+    /// Wait until a token is available and take it, waiters are served in order.
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+        self.refill(&mut bucket);
+        if bucket.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate);
+            tokio::time::sleep(wait).await;
+            self.refill(&mut bucket);
+        }
+        bucket.tokens -= 1.0;
+    }
+
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HttpError> {
+        self.acquire().await;
+        Ok(request.send().await?)
+    }
+
+    fn refill(&self, bucket: &mut TokenBucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use reqwest::cookie::CookieStore;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    #[cfg(feature = "logger")]
+    use tracing::{Instrument, Level};
+
+    use crate::errors::{CircuitBreakerError, HttpError};
+    use crate::http::{
+        check_status, cookie_reqwest_client, current_trace_context, default_reqwest_client,
+        download_file, download_file_with_limits, get_json, health_check, health_check_status,
+        parse_timeout, post_json, send_typed, send_typed_with_error, send_with_retry, upload_file,
+        upload_file_with_fields, with_trace_headers, CircuitBreaker, CircuitBreakerConfig,
+        CircuitState, CookieJar, DownloadLimits, IpFilter, IpFilteredClient, RateLimitedClient,
+        ResilientClient, StatusCode, DEFAULT_HTTP_TIMEOUT,
+    };
+    #[cfg(feature = "logger")]
+    use crate::http::{send_with_access_log, LoggingClient};
+    use crate::retry::RetryPolicy;
+
+    /// Serve `response` to every connection and keep the raw requests received.
+    async fn mock_server(response: &'static str) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        slow_mock_server(response, Duration::ZERO).await
+    }
+
+    /// [`mock_server`] waiting `delay` before responding.
+    async fn slow_mock_server(
+        response: &'static str,
+        delay: Duration,
+    ) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 8192];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&buf[..n]).to_string());
+                tokio::time::sleep(delay).await;
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        (addr, requests)
+    }
+
+    const OK_RESPONSE: &str = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok";
+
+    const JSON_RESPONSE: &str = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 10\r\nconnection: close\r\n\r\n{\"id\": 42}";
+
+    #[tokio::test]
+    async fn test_per_request_timeout() {
+        #[derive(serde::Deserialize)]
+        struct User {
+            id: u32,
+        }
+
+        let (addr, requests) = slow_mock_server(JSON_RESPONSE, Duration::from_millis(300)).await;
+        let url = format!("http://{}/users/42", addr);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let err = get_json::<User>(&client, &url, None).await.err().unwrap();
+        assert!(matches!(err, HttpError::Timeout(_)));
+        let user: User = get_json(&client, &url, Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(user.id, 42);
+        let user: User = post_json(&client, &url, &[1, 2], Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(user.id, 42);
+        assert!(requests.lock().unwrap()[2].ends_with("[1,2]"));
+
+        let policy = RetryPolicy {
+            max_retries: 1,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let err = send_with_retry(client.get(&url), &policy, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HttpError::Timeout(_)));
+        let resp = send_with_retry(client.get(&url), &policy, Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        // the timed out request was sent twice
+        assert_eq!(requests.lock().unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_send_typed() {
+        #[derive(Debug, serde::Deserialize)]
+        struct User {
+            id: u32,
+        }
+        #[derive(Debug, serde::Deserialize)]
+        struct ApiError {
+            code: String,
+        }
+
+        const CREATED_RESPONSE: &str = "HTTP/1.1 201 Created\r\ncontent-type: application/json\r\ncontent-length: 10\r\nconnection: close\r\n\r\n{\"id\": 42}";
+        const BAD_REQUEST_RESPONSE: &str = "HTTP/1.1 400 Bad Request\r\ncontent-type: application/json\r\ncontent-length: 20\r\nconnection: close\r\n\r\n{\"code\": \"bad_name\"}";
+
+        let client = reqwest::Client::new();
+        let (addr, _) = mock_server(CREATED_RESPONSE).await;
+        let url = format!("http://{}/users", addr);
+        let (status, user): (_, User) = send_typed(client.post(&url)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(user.id, 42);
+        let (status, user) = send_typed_with_error::<User, ApiError>(client.post(&url))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(user.unwrap().id, 42);
+
+        let (addr, _) = mock_server(BAD_REQUEST_RESPONSE).await;
+        let url = format!("http://{}/users", addr);
+        let err = send_typed::<User>(client.post(&url)).await.unwrap_err();
+        assert!(matches!(err, HttpError::Status(400)), "{:?}", err);
+        let (status, error) = send_typed_with_error::<User, ApiError>(client.post(&url))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.unwrap_err().code, "bad_name");
+        let err = send_typed_with_error::<User, User>(client.post(&url))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HttpError::Decode(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout(None), DEFAULT_HTTP_TIMEOUT);
+        assert_eq!(parse_timeout(Some("0.2")), Duration::from_millis(200));
+        assert_eq!(parse_timeout(Some(" 3 ")), Duration::from_secs(3));
+        for val in ["soon", "0", "-1", "inf"] {
+            assert_eq!(parse_timeout(Some(val)), DEFAULT_HTTP_TIMEOUT, "{}", val);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let client = default_reqwest_client();
+        let timeout = Duration::from_secs(1);
+        let (addr, _) = mock_server(OK_RESPONSE).await;
+        let url = format!("http://{}/health", addr);
+        assert!(health_check(&client, &url, timeout).await.unwrap());
+        assert!(health_check_status(&client, &url, timeout, 200)
+            .await
+            .unwrap());
+
+        let (addr, _) = mock_server(
+            "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+        )
+        .await;
+        let url = format!("http://{}/health", addr);
+        assert!(!health_check(&client, &url, timeout).await.unwrap());
+        assert!(health_check_status(&client, &url, timeout, 503)
+            .await
+            .unwrap());
+
+        // nothing listens on the port anymore
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(matches!(
+            health_check(&client, &url, timeout).await,
+            Err(HttpError::Connect(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_download_file() {
+        let client = default_reqwest_client();
+        let path = std::env::temp_dir().join(format!("busylib_download_{}", std::process::id()));
+
+        let (addr, _) = mock_server(JSON_RESPONSE).await;
+        let url = format!("http://{}/report.json", addr);
+        assert_eq!(download_file(&client, &url, &path).await.unwrap(), 10);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"id\": 42}");
+        std::fs::remove_file(&path).unwrap();
+
+        let limits = DownloadLimits {
+            max_bytes: Some(1024),
+            allowed_content_types: vec!["application/json".to_string()],
+        };
+        assert_eq!(
+            download_file_with_limits(&client, &url, &path, &limits)
+                .await
+                .unwrap(),
+            10
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // disallowed content type, nothing is written
+        let limits = DownloadLimits {
+            allowed_content_types: vec!["image/*".to_string(), "text/csv".to_string()],
+            ..Default::default()
+        };
+        let err = download_file_with_limits(&client, &url, &path, &limits)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, HttpError::ContentType(t) if t == "application/json"),
+            "{:?}",
+            err
+        );
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_file_too_large() {
+        let client = default_reqwest_client();
+        let path =
+            std::env::temp_dir().join(format!("busylib_download_large_{}", std::process::id()));
+        let part = path.with_file_name(format!(
+            "busylib_download_large_{}.part",
+            std::process::id()
+        ));
+        let limits = DownloadLimits {
+            max_bytes: Some(50),
+            ..Default::default()
+        };
+
+        // declared too large, rejected before reading the body
+        let (addr, _) = mock_server(
+            "HTTP/1.1 200 OK\r\ncontent-length: 100\r\nconnection: close\r\n\r\n\
+             0123456789012345678901234567890123456789012345678901234567890123456789\
+             012345678901234567890123456789",
+        )
+        .await;
+        let err = download_file_with_limits(&client, &format!("http://{}/", addr), &path, &limits)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HttpError::TooLarge(50)), "{:?}", err);
+
+        // no Content-Length, the body is cut off while streaming
+        let (addr, _) = mock_server(
+            "HTTP/1.1 200 OK\r\nconnection: close\r\n\r\n\
+             0123456789012345678901234567890123456789012345678901234567890123456789\
+             012345678901234567890123456789",
+        )
+        .await;
+        let err = download_file_with_limits(&client, &format!("http://{}/", addr), &path, &limits)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HttpError::TooLarge(50)), "{:?}", err);
+        assert!(!path.exists());
+        assert!(!part.exists());
+    }
+
+    #[tokio::test]
+    async fn test_upload_file() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = vec![0u8; 8192];
+            // read until the closing multipart boundary
+            while !String::from_utf8_lossy(&received).ends_with("--\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(OK_RESPONSE.as_bytes()).await.unwrap();
+            String::from_utf8(received).unwrap()
+        });
+
+        let path = std::env::temp_dir().join(format!("busylib_upload_{}.csv", std::process::id()));
+        std::fs::write(&path, "a,b\n1,2\n").unwrap();
+        let resp = upload_file_with_fields(
+            &default_reqwest_client(),
+            &format!("http://{}/upload", addr),
+            "report",
+            &path,
+            &[("owner", "ops")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), 200);
+
+        let request = server.await.unwrap();
+        assert!(request.contains("multipart/form-data; boundary="));
+        assert!(request.contains("name=\"owner\"\r\n\r\nops"));
+        assert!(request.contains(&format!(
+            "name=\"report\"; filename=\"{}\"",
+            path.file_name().unwrap().to_string_lossy()
+        )));
+        assert!(request
+            .to_lowercase()
+            .contains("content-type: text/csv\r\n\r\na,b\n1,2\n"));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            upload_file(&default_reqwest_client(), "http://127.0.0.1:1", "f", &path).await,
+            Err(HttpError::Other(_))
+        ));
+    }
+
+    #[cfg(feature = "logger")]
+    #[tokio::test]
+    async fn test_logging_client_emits_event() {
+        let (addr, _) = mock_server(OK_RESPONSE).await;
+        let logs = crate::logger::capture();
+
+        let client = LoggingClient::new(default_reqwest_client())
+            .with_level(Level::DEBUG)
+            .with_body(true);
+        for _ in 0..2 {
+            let request = client
+                .client()
+                .post(format!("http://user:pass@{}/login?token=abc#frag", addr))
+                .body(r#"{"user":"foo","password":"bar"}"#);
+            let resp = client.send(request).await.unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+        }
+
+        let requests: Vec<_> = logs
+            .entries()
+            .into_iter()
+            .filter(|e| e.message == "http request")
+            .collect();
+        assert_eq!(requests.len(), 2);
+        let entry = &requests[0];
+        assert_eq!(entry.level, "DEBUG");
+        assert_eq!(entry.fields["method"], "POST");
+        assert_eq!(entry.fields["url"], format!("http://{}/login", addr));
+        assert_eq!(entry.fields["status"], 200);
+        let body = entry.fields["body"].as_str().unwrap();
+        assert!(body.contains(r#""password":"***""#), "{}", body);
+        assert!(!body.contains("bar"), "{}", body);
+        let line = logs.lines().join("\n");
+        assert!(
+            !line.contains("token=abc") && !line.contains("user:pass"),
+            "{}",
+            line
+        );
+    }
+
+    #[cfg(feature = "logger")]
+    #[tokio::test]
+    async fn test_access_log_fields() {
+        let (addr, _) = mock_server(OK_RESPONSE).await;
+        let logs = crate::logger::capture();
+
+        let span = tracing::info_span!("handle_order");
+        let trace_id = async {
+            let resp = send_with_access_log(
+                default_reqwest_client().get(format!("http://{}/orders/1?full=true", addr)),
+            )
+            .await
+            .unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+            current_trace_context().unwrap().0
+        }
+        .instrument(span)
+        .await;
+
+        let access: Vec<_> = logs
+            .entries()
+            .into_iter()
+            .filter(|e| e.message == "http access")
+            .collect();
+        assert_eq!(access.len(), 1);
+        let entry = &access[0];
+        assert_eq!(entry.level, "INFO");
+        assert_eq!(entry.target, "busylib::http::access");
+        assert_eq!(entry.fields["method"], "GET");
+        assert_eq!(entry.fields["host"], "127.0.0.1");
+        assert_eq!(entry.fields["path"], "/orders/1");
+        assert_eq!(entry.fields["status"], 200);
+        assert_eq!(entry.fields["bytes"], 2);
+        assert!(entry.fields["duration_ms"].is_u64());
+        assert_eq!(entry.fields["trace_id"], trace_id);
+    }
+
+    #[cfg(feature = "logger")]
+    #[tokio::test]
+    async fn test_retry_events() {
+        const UNAVAILABLE_RESPONSE: &str =
+            "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+        let (addr, requests) = mock_server(UNAVAILABLE_RESPONSE).await;
+        let logs = crate::logger::capture();
+
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let url = format!("http://{}/flaky", addr);
+        let err = send_with_retry(default_reqwest_client().get(&url), &policy, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HttpError::Status(503)));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        let entries = logs.entries();
+        let retries: Vec<_> = entries
+            .iter()
+            .filter(|e| e.message == "http request failed, retrying")
+            .collect();
+        assert_eq!(retries.len(), 2);
+        for (i, entry) in retries.iter().enumerate() {
+            assert_eq!(entry.level, "WARN");
+            assert_eq!(entry.fields["attempt"], i + 1);
+            assert_eq!(entry.fields["status"], 503);
+            assert_eq!(entry.fields["backoff_ms"], 1 << i);
+        }
+        let give_up: Vec<_> = entries
+            .iter()
+            .filter(|e| e.message == "http request failed, giving up")
+            .collect();
+        assert_eq!(give_up.len(), 1);
+        assert_eq!(give_up[0].level, "WARN");
+        assert_eq!(give_up[0].fields["attempts"], 3);
+
+        // a request succeeding at once logs nothing
+        let (addr, _) = mock_server(OK_RESPONSE).await;
+        send_with_retry(
+            default_reqwest_client().get(format!("http://{}/", addr)),
+            &policy,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            logs.entries().iter().filter(|e| e.level == "WARN").count(),
+            3
+        );
+    }
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(
+            default_reqwest_client(),
+            CircuitBreakerConfig {
+                window_size: 4,
+                min_calls: 4,
+                failure_rate_threshold: 0.5,
+                open_duration: Duration::from_millis(50),
+            },
+        )
+    }
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<(), CircuitBreakerError<&'static str>> {
+        breaker.call(|| async { Err::<(), _>("boom") }).await
+    }
+
+    async fn succeed(breaker: &CircuitBreaker) -> Result<(), CircuitBreakerError<&'static str>> {
+        breaker.call(|| async { Ok::<_, &str>(()) }).await
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_transitions() {
+        let breaker = breaker();
+        succeed(&breaker).await.unwrap();
+        succeed(&breaker).await.unwrap();
+        assert!(matches!(
+            fail(&breaker).await,
+            Err(CircuitBreakerError::Inner("boom"))
+        ));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        let _ = fail(&breaker).await;
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // rejected fast while open
+        assert!(matches!(
+            succeed(&breaker).await,
+            Err(CircuitBreakerError::Open)
+        ));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        // failed probe opens again
+        let _ = fail(&breaker).await;
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        succeed(&breaker).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_single_probe() {
+        let breaker = breaker();
+        for _ in 0..4 {
+            let _ = fail(&breaker).await;
+        }
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let probe = breaker.clone();
+        let slow = tokio::spawn(async move {
+            probe
+                .call(|| async {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok::<_, &str>(())
+                })
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            succeed(&breaker).await,
+            Err(CircuitBreakerError::Open)
+        ));
+        slow.await.unwrap().unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_dropped_probe() {
+        let breaker = breaker();
+        for _ in 0..4 {
+            let _ = fail(&breaker).await;
+        }
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        // the probe is cancelled before it completes
+        let probe = breaker.call(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok::<_, &str>(())
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(10), probe)
+            .await
+            .is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        succeed(&breaker).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    /// Respond 503 to the first `failures` connections then 200 with a JSON body, and count the
+    /// connections.
+    async fn flaky_mock_server(failures: usize) -> (SocketAddr, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let count = Arc::new(Mutex::new(0));
+        let received = count.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 8192];
+                let _ = stream.read(&mut buf).await;
+                let n = {
+                    let mut count = received.lock().unwrap();
+                    *count += 1;
+                    *count
+                };
+                let response = if n <= failures {
+                    "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                } else {
+                    JSON_RESPONSE
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        (addr, count)
+    }
+
+    fn fast_retry(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resilient_client_retries() {
+        #[derive(serde::Deserialize)]
+        struct User {
+            id: u32,
+        }
+
+        let (addr, count) = flaky_mock_server(2).await;
+        let url = format!("http://{}/users/42", addr);
+        let client = ResilientClient::builder(default_reqwest_client())
+            .retry(fast_retry(3))
+            .rate_limit(100)
+            .circuit_breaker(CircuitBreakerConfig {
+                window_size: 10,
+                min_calls: 5,
+                failure_rate_threshold: 0.5,
+                open_duration: Duration::from_secs(30),
+            })
+            .build();
+        let user: User = client.get_json(&url, None).await.unwrap();
+        assert_eq!(user.id, 42);
+        assert_eq!(*count.lock().unwrap(), 3);
+        assert_eq!(client.circuit_state(), Some(CircuitState::Closed));
+
+        // without retries the 503 is returned as is
+        let (addr, _) = flaky_mock_server(1).await;
+        let client = ResilientClient::builder(default_reqwest_client()).build();
+        let err = client
+            .send(client.client().get(format!("http://{}/", addr)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HttpError::Status(503)));
+        assert_eq!(client.circuit_state(), None);
+    }
+
+    #[tokio::test]
+    async fn test_resilient_client_breaker_stops_retries() {
+        let (addr, count) = flaky_mock_server(usize::MAX).await;
+        let client = ResilientClient::builder(default_reqwest_client())
+            .retry(fast_retry(5))
+            .circuit_breaker(CircuitBreakerConfig {
+                window_size: 2,
+                min_calls: 2,
+                failure_rate_threshold: 0.5,
+                open_duration: Duration::from_secs(30),
+            })
+            .build();
+        let err = client
+            .send(client.client().get(format!("http://{}/", addr)))
+            .await
+            .unwrap_err();
+        // the breaker opened after 2 failures and the open rejection is not retried
+        assert!(matches!(err, HttpError::CircuitOpen));
+        assert_eq!(*count.lock().unwrap(), 2);
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let filter = IpFilter::new();
+        for blocked in [
+            "127.0.0.1",
+            "169.254.169.254",
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.1.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::ffff:127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+            "fec0::1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::7f00:1",
+            "2002:a00:1::1",
+            "2002:c0a8:101::",
+        ] {
+            assert!(!filter.is_allowed(blocked.parse().unwrap()), "{}", blocked);
+        }
+        for public in [
+            "8.8.8.8",
+            "93.184.216.34",
+            "2606:4700::1111",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(filter.is_allowed(public.parse().unwrap()), "{}", public);
+        }
+
+        let filter = IpFilter::new()
+            .deny("8.8.8.0".parse().unwrap(), 24)
+            .allow("127.0.0.1".parse().unwrap(), 32);
+        assert!(!filter.is_allowed("8.8.8.8".parse().unwrap()));
+        assert!(filter.is_allowed("8.8.4.4".parse().unwrap()));
+        assert!(filter.is_allowed("127.0.0.1".parse().unwrap()));
+        assert!(!filter.is_allowed("127.0.0.2".parse().unwrap()));
+        assert!(matches!(
+            filter.check("8.8.8.8".parse().unwrap()),
+            Err(HttpError::Blocked(addr)) if addr.to_string() == "8.8.8.8"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ip_filtered_client() {
+        let (addr, requests) = mock_server(OK_RESPONSE).await;
+        let client = IpFilteredClient::new(IpFilter::new());
+        for url in [
+            format!("http://{}/", addr),
+            format!("http://localhost:{}/", addr.port()),
+            "http://169.254.169.254/latest/meta-data/".to_string(),
+            "http://[::1]/".to_string(),
+        ] {
+            let err = client.send(client.client().get(&url)).await.unwrap_err();
+            assert!(matches!(err, HttpError::Blocked(_)), "{}: {:?}", url, err);
+        }
+        // sent by the inner client without IpFilteredClient::send
+        for url in [
+            format!("http://{}/", addr),
+            "http://[::1]/".to_string(),
+            "https://127.0.0.1/".to_string(),
+        ] {
+            let err = client.client().get(&url).send().await.unwrap_err();
+            assert!(
+                matches!(HttpError::from(err), HttpError::Blocked(ip) if ip.is_loopback()),
+                "{}",
+                url
+            );
+        }
+        assert!(requests.lock().unwrap().is_empty());
+
+        // explicitly allowed
+        let client = IpFilteredClient::new(IpFilter::new().allow(addr.ip(), 32));
+        let resp = client
+            .send(client.client().get(format!("http://{}/", addr)))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+
+        // a redirect to a blocked address is not followed
+        let (redirect, _) = mock_server(
+            "HTTP/1.1 302 Found\r\nlocation: http://169.254.169.254/\r\ncontent-length: 0\r\n\r\n",
+        )
+        .await;
+        let client = IpFilteredClient::new(IpFilter::new().allow(redirect.ip(), 32));
+        let err = client
+            .send(client.client().get(format!("http://{}/", redirect)))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, HttpError::Blocked(addr) if addr.to_string() == "169.254.169.254"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_client() {
+        let (addr, requests) = mock_server(OK_RESPONSE).await;
+        let client = RateLimitedClient::new(default_reqwest_client(), 10);
+        let start = std::time::Instant::now();
+        let mut tasks = Vec::new();
+        for _ in 0..15 {
+            let client = client.clone();
+            let url = format!("http://{}/", addr);
+            tasks.push(tokio::spawn(async move {
+                client.send(client.client().get(url)).await.unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // a burst of 10, then 5 more at 100ms intervals
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        assert_eq!(requests.lock().unwrap().len(), 15);
+    }
+
+    #[cfg(feature = "rustls-tls")]
+    #[test]
+    fn test_mtls_reqwest_client() {
+        use crate::http::{mtls_reqwest_client, ClientIdentity};
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = certified.cert.pem();
+        let identity = format!("{}{}", certified.key_pair.serialize_pem(), cert);
+
+        assert!(mtls_reqwest_client(ClientIdentity::Pem(identity.as_bytes()), None).is_ok());
+        assert!(mtls_reqwest_client(
+            ClientIdentity::Pem(identity.as_bytes()),
+            Some(cert.as_bytes())
+        )
+        .is_ok());
+
+        let err = mtls_reqwest_client(ClientIdentity::Pem(cert.as_bytes()), None).unwrap_err();
+        assert!(err.to_string().starts_with("invalid client identity"));
+        let err = mtls_reqwest_client(
+            ClientIdentity::Pem(identity.as_bytes()),
+            Some(b"not a certificate"),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("invalid root CA certificate"));
+    }
+
+    #[cfg(all(
+        feature = "insecure-tls",
+        feature = "logger",
+        any(feature = "rustls-tls", feature = "native-tls")
+    ))]
+    #[test]
+    fn test_insecure_reqwest_client() {
+        use crate::http::insecure_reqwest_client;
+
+        let logs = crate::logger::capture();
+        let _client = insecure_reqwest_client();
+
+        let entries = logs.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].level, "WARN");
+        assert!(
+            entries[0].message.contains("INSECURE"),
+            "{}",
+            entries[0].message
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookie_reqwest_client() {
+        let (addr, requests) = mock_server(
+            "HTTP/1.1 200 OK\r\nset-cookie: session=abc; Path=/\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+        )
+        .await;
+        let jar = Arc::new(CookieJar::default());
+        let client = cookie_reqwest_client(jar.clone());
+        let url = format!("http://{}/", addr);
+        client.get(&url).send().await.unwrap();
+        client.get(&url).send().await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert!(!requests[0].to_lowercase().contains("cookie: session=abc"));
+        assert!(requests[1].to_lowercase().contains("cookie: session=abc"));
+        let cookies = jar.cookies(&url.parse().unwrap()).unwrap();
+        assert_eq!(cookies.to_str().unwrap(), "session=abc");
+    }
+
+    #[tokio::test]
+    async fn test_trace_headers() {
+        let (addr, requests) = mock_server(OK_RESPONSE).await;
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry());
+        let client = default_reqwest_client();
+        let url = format!("http://{}/", addr);
+
+        with_trace_headers(client.get(&url)).send().await.unwrap();
+        assert!(current_trace_context().is_none());
+
+        let root = tracing::info_span!("root");
+        let _root = root.enter();
+        let (root_trace_id, _) = current_trace_context().unwrap();
+        let child = tracing::info_span!("child");
+        let _child = child.enter();
+        let (trace_id, span_id) = current_trace_context().unwrap();
+        assert_eq!(trace_id, root_trace_id);
+        assert_eq!(trace_id.len(), 32);
+        assert_eq!(span_id.len(), 16);
+        with_trace_headers(client.get(&url)).send().await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert!(!requests[0].contains("traceparent"));
+        assert!(requests[1].contains(&format!("traceparent: 00-{}-{}-01", trace_id, span_id)));
+        assert!(requests[1]
+            .to_lowercase()
+            .contains(&format!("x-request-id: {}", trace_id)));
+    }
+
+    #[tokio::test]
+    async fn test_http_error_mapping() {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        // accept the connection but never respond
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+        let err: HttpError = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, HttpError::Timeout(_)));
+
+        // nothing listens on a just released port
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let err: HttpError = client
+            .get(format!("http://{}/", closed_addr))
+            .send()
+            .await
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, HttpError::Connect(_)));
+
+        let (addr, _) = mock_server(
+            "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+        )
+        .await;
+        let resp = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+        let err: HttpError = resp.error_for_status().unwrap_err().into();
+        assert!(matches!(err, HttpError::Status(503)));
+        let resp = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+        assert!(matches!(check_status(resp), Err(HttpError::Status(503))));
+        assert_eq!(
+            HttpError::status(503).to_string(),
+            "http status 503 Service Unavailable"
+        );
+
+        let (addr, _) = mock_server(OK_RESPONSE).await;
+        let resp = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+        let err: HttpError = resp.json::<Vec<u8>>().await.unwrap_err().into();
+        assert!(matches!(err, HttpError::Decode(_)));
+
+        let err: HttpError = client.get("not a url").send().await.unwrap_err().into();
+        assert!(matches!(err, HttpError::Other(_)));
+    }
+}
@@ -1,10 +1,44 @@
 //! An out-of-the-box wrapper for awesome libs
+//!
+//! On `wasm32` only the `crypto`, `aead`, `hash`, `crypto-serde` and `config` features are
+//! available, without the helpers reading files:
+//!
+//! ```toml
+//! busylib = { version = "0.4", default-features = false, features = ["crypto", "config"] }
+//! ```
 
+#[cfg(all(
+    target_arch = "wasm32",
+    any(
+        feature = "logger",
+        feature = "http",
+        feature = "retry",
+        feature = "timeout",
+        feature = "async",
+        feature = "watch",
+        feature = "scheduler"
+    )
+))]
+compile_error!(
+    "only the crypto and config features of busylib build for wasm32, \
+     use `default-features = false`"
+);
+
+#[cfg(feature = "config")]
 pub mod config;
+#[cfg(feature = "crypto")]
 pub mod crypto;
 pub mod errors;
+#[cfg(feature = "http")]
 pub mod http;
+#[cfg(feature = "logger")]
 pub mod logger;
 pub mod prelude;
+#[cfg(feature = "retry")]
+pub mod retry;
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
+#[cfg(feature = "timeout")]
+pub mod timeout;
 
 pub const ANY: &str = "any";
@@ -1,315 +1,4673 @@
 // #![allow(unused)]
 
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::{env, fs, path::PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, fs::File, path::PathBuf};
 
 use chrono::{DateTime, Utc};
-use log::{debug, warn};
-use time::UtcOffset;
-use tokio_cron_scheduler::Job;
-use tracing_appender::non_blocking::WorkerGuard;
+use log::{debug, info, warn};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::format_description::{self, OwnedFormatItem};
+use time::{Date, OffsetDateTime, UtcOffset};
+use tokio_cron_scheduler::{Job, JobScheduler};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::STATIC_MAX_LEVEL;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_appender::non_blocking::{
+    ErrorCounter, NonBlockingBuilder, WorkerGuard, DEFAULT_BUFFERED_LINES_LIMIT,
+};
 use tracing_subscriber::{
+    field::RecordFields,
     filter,
-    filter::Targets,
-    fmt::{time::OffsetTime, MakeWriter},
-    layer::SubscriberExt,
+    filter::{FilterExt, Targets},
+    fmt,
+    fmt::{
+        format::{Format, FormatEvent, FormatFields, Json, JsonFields, Writer},
+        time::FormatTime,
+        writer::BoxMakeWriter,
+        FmtContext, FormattedFields, MakeWriter,
+    },
+    layer,
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
     reload,
     reload::Handle,
     util::SubscriberInitExt,
     Layer, Registry,
 };
+use uuid::Uuid;
 
-use crate::errors::RemoveFilesError;
+use crate::errors::{ConfigError, InitLoggerError, ParseLogError, RemoveFilesError};
 use crate::{
-    config::debug_mode,
+    config::{debug_mode, env_bool},
     prelude::{EnhancedExpect, EnhancedUnwrap},
 };
 
 pub type LogHandle = Handle<Targets, Registry>;
 
+fn log_offset() -> UtcOffset {
+    UtcOffset::from_hms(8, 0, 0).ex("UtcOffset::from_hms should work")
+}
+
+/// Format of the timestamps written by the logger, see [`LoggerBuilder::timestamp_format`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// `2023-09-01T08:00:00.123456+08:00`
+    #[default]
+    Rfc3339,
+    /// `Fri, 01 Sep 2023 08:00:00 +0800`
+    Rfc2822,
+    /// milliseconds since the Unix epoch
+    UnixMillis,
+    /// a `time` format description like `[year]-[month]-[day] [hour]:[minute]:[second]`, see
+    /// <https://time-rs.github.io/book/api/format-description.html>
+    Custom(String),
+}
+
+impl TimestampFormat {
+    fn timer(&self) -> Result<LogTimer, InitLoggerError> {
+        let format = match self {
+            TimestampFormat::Rfc3339 => TimerFormat::Rfc3339,
+            TimestampFormat::Rfc2822 => TimerFormat::Rfc2822,
+            TimestampFormat::UnixMillis => TimerFormat::UnixMillis,
+            TimestampFormat::Custom(description) => TimerFormat::Custom(
+                format_description::parse_owned::<2>(description).map_err(|e| InitLoggerError {
+                    details: format!("invalid timestamp format {:?}: {}", description, e),
+                })?,
+            ),
+        };
+        Ok(LogTimer {
+            offset: log_offset(),
+            format,
+        })
+    }
+}
+
+#[derive(Clone)]
+enum TimerFormat {
+    Rfc3339,
+    Rfc2822,
+    UnixMillis,
+    Custom(OwnedFormatItem),
+}
+
+#[derive(Clone)]
+struct LogTimer {
+    offset: UtcOffset,
+    format: TimerFormat,
+}
+
+impl FormatTime for LogTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        let now = OffsetDateTime::now_utc().to_offset(self.offset);
+        let formatted = match &self.format {
+            TimerFormat::Rfc3339 => now.format(&Rfc3339),
+            TimerFormat::Rfc2822 => now.format(&Rfc2822),
+            TimerFormat::UnixMillis => {
+                return write!(w, "{}", now.unix_timestamp_nanos() / 1_000_000)
+            }
+            TimerFormat::Custom(items) => now.format(items),
+        };
+        write!(w, "{}", formatted.map_err(|_| std::fmt::Error)?)
+    }
+}
+
+impl Default for LogTimer {
+    fn default() -> Self {
+        LogTimer {
+            offset: log_offset(),
+            format: TimerFormat::Rfc3339,
+        }
+    }
+}
+
+/// The JSON lines layer writing the log files, read them back with [`parse_log_file`].
+fn json_file_layer<S, W>(
+    writer: W,
+    timer: LogTimer,
+) -> fmt::Layer<S, JsonFields, Format<Json, LogTimer>, W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .with_timer(timer)
+        .with_writer(writer)
+        .json()
+}
+
+/// Install the global subscriber logging to stdout and to daily rolling JSON files in
+/// `log_directory` (default [`log_path`]) unless env var [`FILE_LOG_ENV`] is off, panic on
+/// failure, see [`try_init_logger`] and [`LoggerBuilder`] for more options.
 pub fn init_logger(
     bin_name: &str,
     crates_to_log: &[&str],
     debug: bool,
     log_directory: Option<PathBuf>,
 ) -> (Option<WorkerGuard>, Option<LogHandle>) {
-    let level_filter = if debug {
-        filter::LevelFilter::DEBUG
-    } else {
-        filter::LevelFilter::INFO
-    };
+    try_init_logger(bin_name, crates_to_log, debug, log_directory).ex("init logger failed")
+}
 
-    let log_directory = {
-        if log_directory.is_some() {
-            log_directory.unwp()
-        } else {
-            log_path(None, None)
+/// [`init_logger`] safe to call more than once, e.g. from library code or tests, only the first
+/// call installs the subscriber and gets the [`WorkerGuard`], later calls get `None` and the
+/// [`LogHandle`] of the first call, whatever their arguments. Never panics, if the first call
+/// fails, e.g. as another global subscriber is installed, the error is printed to stderr and
+/// every call gets `(None, None)`.
+pub fn init_logger_once(
+    bin_name: &str,
+    crates_to_log: &[&str],
+    debug: bool,
+    log_directory: Option<PathBuf>,
+) -> (Option<WorkerGuard>, Option<LogHandle>) {
+    static LOG_HANDLE: OnceCell<Option<LogHandle>> = OnceCell::new();
+    let mut guard = None;
+    let handle = LOG_HANDLE.get_or_init(|| {
+        match try_init_logger(bin_name, crates_to_log, debug, log_directory) {
+            Ok((worker_guard, handle)) => {
+                guard = worker_guard;
+                handle
+            }
+            Err(e) => {
+                // there is no logger to report it to
+                eprintln!("init logger failed: {}", e);
+                None
+            }
         }
-    };
+    });
+    (guard, handle.clone())
+}
 
-    let timer = OffsetTime::new(
-        UtcOffset::from_hms(8, 0, 0).ex("UtcOffset::from_hms should work"),
-        time::format_description::well_known::Rfc3339,
-    );
-    let stdout_log = tracing_subscriber::fmt::layer().with_timer(timer.clone());
-    let reg = tracing_subscriber::registry();
-
-    let mut base_filter = Targets::new().with_target(bin_name, level_filter);
-    for crate_name in crates_to_log {
-        base_filter = base_filter.with_target(*crate_name, level_filter);
-    }
-    let (filter, reload_handle) = reload::Layer::new(base_filter.clone());
-    let file_appender =
-        tracing_appender::rolling::daily(log_directory, format!("{}.log", bin_name));
-    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
-    let file_filter = tracing_subscriber::fmt::layer()
-        .with_timer(timer)
-        .with_writer(non_blocking.make_writer())
-        .json()
-        .with_filter(base_filter);
+/// Resolve on SIGTERM or SIGINT, for the main loop to start its graceful shutdown, see
+/// [`flush_after`] to flush the logs once it is drained.
+///
+/// ```rust,ignore
+/// let (guard, _handle) = init_logger("app", &["app"], false, None);
+/// let server = axum::serve(listener, app).with_graceful_shutdown(shutdown_signal());
+/// flush_after(server, guard).await?;
+/// ```
+#[cfg(feature = "async")]
+pub async fn shutdown_signal() {
+    wait_for_signal().await;
+    info!("shutdown signal received");
+}
 
-    reg.with(stdout_log.with_filter(filter).and_then(file_filter))
-        .init();
-    (Some(guard), Some(reload_handle))
+/// Run `shutdown` to completion then drop `guard` to flush the log lines still buffered by the
+/// non-blocking file writer, so the lines logged while draining are written too, return the
+/// output of `shutdown`.
+#[cfg(feature = "async")]
+pub async fn flush_after<F: std::future::IntoFuture>(
+    shutdown: F,
+    guard: Option<WorkerGuard>,
+) -> F::Output {
+    let output = shutdown.await;
+    info!("shutdown complete, flushing logs");
+    drop(guard);
+    output
 }
 
-pub trait LogCleanerErrorHandler {
-    fn handle_error(&self, error: RemoveFilesError);
+#[cfg(all(feature = "async", unix))]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).ex("install SIGTERM handler failed");
+    let mut interrupt = signal(SignalKind::interrupt()).ex("install SIGINT handler failed");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct LogCleaner<P, H>
-where
-    P: AsRef<Path>,
-    H: LogCleanerErrorHandler,
-{
-    pub dir: P,
-    pub days: i64,
-    pub cron_expression: Option<String>,
-    pub error_handler: H,
+#[cfg(all(feature = "async", not(unix)))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .ex("install ctrl-c handler failed");
 }
 
-impl<P, H> LogCleaner<P, H>
+/// [`init_logger`] with a prebuilt `filter` used verbatim for the console and the files, see
+/// [`LoggerBuilder::filter`].
+///
+/// ```rust,ignore
+/// let filter = Targets::new()
+///     .with_target("my_app", LevelFilter::DEBUG)
+///     .with_target("hyper", LevelFilter::WARN);
+/// let (_guard, handle) = init_logger_with_filter("my_app", filter, None);
+/// ```
+pub fn init_logger_with_filter(
+    bin_name: &str,
+    filter: Targets,
+    log_directory: Option<PathBuf>,
+) -> (Option<WorkerGuard>, Option<LogHandle>) {
+    let builder = LoggerBuilder::new(bin_name).filter(filter);
+    match log_directory {
+        Some(dir) => builder.log_directory(dir).init(),
+        None => builder.init(),
+    }
+}
+
+/// [`init_logger`] returning an error instead of panicking, `log_directory` is created if it
+/// does not exist yet.
+pub fn try_init_logger(
+    bin_name: &str,
+    crates_to_log: &[&str],
+    debug: bool,
+    log_directory: Option<PathBuf>,
+) -> Result<(Option<WorkerGuard>, Option<LogHandle>), InitLoggerError> {
+    let builder = LoggerBuilder::new(bin_name)
+        .crates(crates_to_log)
+        .debug(debug);
+    match log_directory {
+        Some(dir) => builder.log_directory(dir).try_init(),
+        None => builder.try_init(),
+    }
+}
+
+/// Line format of the logger, see [`LoggerBuilder::format`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// JSON lines in the files, read back by [`parse_log_file`], and text on the console
+    #[default]
+    Json,
+    /// `logfmt` lines like `ts=... level=info target=app msg="..." key=val` in the files and on
+    /// the console, parsed natively by Loki
+    Logfmt,
+}
+
+/// [`FormatEvent`] writing the `logfmt` lines of [`LogFormat::Logfmt`], the fields of the
+/// current spans from the root come before the event fields.
+struct Logfmt {
+    timer: LogTimer,
+}
+
+impl<S, N> FormatEvent<S, N> for Logfmt
 where
-    P: AsRef<Path> + Sync + Send + Clone + 'static,
-    H: LogCleanerErrorHandler + Sync + Send + Clone + 'static,
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
 {
-    pub fn new(dir: P, days: i64, cron_expression: Option<String>, error_handler: H) -> Self {
-        Self {
-            dir,
-            days,
-            cron_expression,
-            error_handler,
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut timestamp = String::new();
+        self.timer.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut fields = LogfmtVisitor::default();
+        event.record(&mut fields);
+        write!(
+            writer,
+            "ts={} level={} target={} msg={}",
+            logfmt_value(&timestamp),
+            event.metadata().level().as_str().to_lowercase(),
+            logfmt_value(event.metadata().target()),
+            logfmt_value(&fields.message)
+        )?;
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                match extensions.get::<FormattedFields<N>>() {
+                    Some(span_fields) if !span_fields.is_empty() => {
+                        write!(writer, " {}", span_fields)?
+                    }
+                    _ => {}
+                }
+            }
         }
+        for (key, value) in fields.fields {
+            write!(writer, " {}={}", key, logfmt_value(&value))?;
+        }
+        writeln!(writer)
     }
+}
 
-    /// Immediately clean up files in the specified `self.dir` that have been modified more than
-    /// a specified number of `self.days` ago.
-    /// Typically used to clean up log files with.
-    ///
-    /// ```rust,ignore
-    ///
-    /// cleanup_files_immediately("/opt/logs/apps/", 30);
-    /// ```
-    pub fn cleanup_files_immediately(&self) -> Result<(), RemoveFilesError> {
-        let paths = fs::read_dir(&self.dir).map_err(|e| RemoveFilesError {
-            details: format!(
-                "An error occurred in reading the directory and the cleanup file failed: {}",
-                e
-            ),
-        })?;
+/// [`FormatFields`] of the span fields in [`Logfmt`] lines, `key=value` separated by spaces
+struct LogfmtFields;
 
-        for path in paths.flatten().map(|e| e.path()) {
-            let modified = fs::metadata(&path)
-                .and_then(|metadata| metadata.modified())
-                .map_err(|e| RemoveFilesError {
-                    details: format!("An error occurred in getting file modified time and the cleanup file failed: {}", e),
-                })?;
-            if (Utc::now() - DateTime::from(modified)).num_days() > self.days {
-                fs::remove_file(&path).map_err(|e| RemoveFilesError {
-                    details: format!("delete file failed, path: {:?}, error: {}", path, e),
-                })?;
-            }
+impl<'writer> FormatFields<'writer> for LogfmtFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        let mut visitor = LogfmtVisitor::default();
+        fields.record(&mut visitor);
+        if !visitor.message.is_empty() {
+            visitor.fields.insert(0, ("message", visitor.message));
+        }
+        for (i, (key, value)) in visitor.fields.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            write!(writer, "{}{}={}", sep, key, logfmt_value(value))?;
         }
         Ok(())
     }
+}
 
-    /// Clean up files in the specified `self.dir` that have been modified more than
-    /// a specified number of `self.days` ago.
-    ///
-    /// ```rust,ignore
-    /// // The parameter `cron_expression` default is `0 0 0 * * * *`.
-    /// // The parameter `cron_expression` sample: 0 15 6,8,10 * Mar,Jun Fri 2017
-    /// // means Run at second 0 of the 15th minute of the 6th, 8th, and 10th hour of any day in March
-    /// // and June that is a Friday of the year 2017.
-    /// // More information about `cron_expression` parameter see
-    /// // https://docs.rs/job_scheduler/latest/job_scheduler/
-    ///
-    /// schedule_cleanup_log_files("/opt/logs/apps/", 30, None);
-    /// ```
-    pub async fn schedule_cleanup_log_files(self) -> Result<(), RemoveFilesError> {
-        let sched = tokio_cron_scheduler::JobScheduler::new().await?;
-        let cron = self
-            .clone()
-            .cron_expression
-            .unwrap_or("0 0 0 * * * *".to_string());
-        sched
-            .add(Job::new_async(cron.as_str(), move |uuid, mut l| {
-                let cleaner = self.clone();
-                Box::pin(async move {
-                    if let Err(e) = cleaner.cleanup_files_immediately() {
-                        cleaner.error_handler.handle_error(e);
-                    };
-                    let next_tick = l.next_tick_for_job(uuid).await;
-                    if let Ok(Some(ts)) = next_tick {
-                        tokio::time::sleep(tokio::time::Duration::from_secs(
-                            (ts - Utc::now()).num_seconds() as u64,
-                        ))
-                        .await
-                    }
-                })
-            })?)
-            .await?;
-        sched.start().await?;
-        Ok(())
-    }
+#[derive(Default)]
+struct LogfmtVisitor {
+    message: String,
+    fields: Vec<(&'static str, String)>,
 }
 
-#[allow(unused, unreachable_code)]
-pub fn change_debug(handle: &LogHandle, debug: &str) -> bool {
-    // TODO: change_debug
-    panic!("TODO: ");
-    let base_filter = filter::Targets::new().with_target("foo", filter::LevelFilter::DEBUG);
-    handle.modify(|filter| *filter = base_filter);
-    true
+impl Visit for LogfmtVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_string(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_string(field, format!("{:?}", value));
+    }
 }
 
-pub fn log_path(log_path: Option<&str>, env_log_path_key: Option<&str>) -> PathBuf {
-    if debug_mode() {
-        let dir = env::temp_dir();
-        debug!(
-            "log will be saved to temporary directory: {}",
-            dir.display()
-        );
-        return dir;
+impl LogfmtVisitor {
+    fn record_string(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = value,
+            name => self.fields.push((name, value)),
+        }
     }
+}
 
-    // log path from param is first if it have been set
-    if log_path.is_some() {
-        return PathBuf::from(log_path.unwp().trim());
+/// return `value` quoted and escaped if it is empty or contains spaces, `=` or `"`
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c.is_control())
+    {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
     }
+    quoted.push('"');
+    quoted
+}
 
-    // default log path
-    let log_path = r"/opt/logs/apps/";
-    if env_log_path_key.is_some() {
-        let env_log_path = env::var(env_log_path_key.unwp());
-        match env_log_path {
-            Ok(env_log_path) => return PathBuf::from(env_log_path),
-            Err(_) => warn!(
-                "{} is not set, use default log path: {}",
-                env_log_path_key.unwp(),
-                log_path
-            ),
+/// Event format adding the fields of [`LoggerBuilder::field`] to the lines of `inner`, as keys
+/// of the JSON object if `json` or else as `key=value` at the end of the line.
+struct GlobalFields<F> {
+    inner: F,
+    fields: Arc<Vec<(String, String)>>,
+    json: bool,
+}
+
+impl<S, N, F> FormatEvent<S, N> for GlobalFields<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        if self.fields.is_empty() {
+            return self.inner.format_event(ctx, writer, event);
         }
-    };
-    PathBuf::from(log_path)
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        let newline = line.ends_with('\n');
+        if newline {
+            line.pop();
+        }
+        match line.strip_prefix('{') {
+            Some(rest) if self.json => {
+                let fields: Vec<String> = self
+                    .fields
+                    .iter()
+                    .map(|(k, v)| {
+                        format!("{}:{}", Value::from(k.as_str()), Value::from(v.as_str()))
+                    })
+                    .collect();
+                let separator = if rest.starts_with('}') { "" } else { "," };
+                write!(writer, "{{{}{}{}", fields.join(","), separator, rest)?;
+            }
+            _ => {
+                writer.write_str(&line)?;
+                for (k, v) in self.fields.iter() {
+                    write!(writer, " {}={}", k, logfmt_value(v))?;
+                }
+            }
+        }
+        if newline {
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
 }
 
-#[cfg(test)]
-mod logger_test {
-    use std::time::Duration;
-    use std::{env, fs};
+/// Patterns of [`LoggerBuilder::redact`] for the usual secrets: bearer tokens, emails and card
+/// numbers.
+pub const DEFAULT_REDACT_PATTERNS: &[&str] = &[
+    r"(?i)bearer\s+[a-z0-9\-._~+/]+=*",
+    r"[a-zA-Z0-9._%+\-]+@[a-zA-Z0-9.\-]+\.[a-zA-Z]{2,}",
+    r"\b\d{4}[ -]\d{4}[ -]\d{4}[ -]\d{4}\b|\b\d{16}\b",
+];
 
-    use crate::errors::RemoveFilesError;
-    use chrono::{DateTime, Utc};
-    use log::{debug, info};
+/// [`MakeWriter`] replacing the matches of the redact patterns by `***` in every line before it
+/// reaches `inner`.
+struct RedactingMakeWriter<M> {
+    inner: M,
+    patterns: Arc<Vec<regex::bytes::Regex>>,
+}
 
-    use crate::logger::{log_path, LogCleaner, LogCleanerErrorHandler};
-    use crate::prelude::EnhancedUnwrap;
+/// Buffer of one formatted event, redacted and written out when dropped.
+struct RedactingWriter<W: Write> {
+    inner: W,
+    patterns: Arc<Vec<regex::bytes::Regex>>,
+    buf: Vec<u8>,
+}
 
-    #[derive(Clone)]
-    struct MyLoggerErrorHandler;
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
 
-    // define custom error handler and implement LogCleanerErrorHandler trait in application code
-    impl LogCleanerErrorHandler for MyLoggerErrorHandler {
-        fn handle_error(&self, error: RemoveFilesError) {
-            // put custom error handling logic here
-            dbg!("handling error: {:?}", error);
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+            patterns: self.patterns.clone(),
+            buf: Vec::new(),
         }
     }
+}
 
-    #[test]
-    fn test_delete_log_files() {
-        let cleaner = LogCleaner {
-            dir: "/opt/logs/apps/",
-            days: 30,
-            cron_expression: None,
-            error_handler: MyLoggerErrorHandler,
-        };
-        if let Err(e) = cleaner.cleanup_files_immediately() {
-            panic!("test_delete_log_files failed, error: {}", e);
-        }
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
     }
 
-    #[tokio::test]
-    async fn test_schedule_cleanup_log_files() {
-        let dir = "/opt/logs/apps/";
-        let days = 30;
-        let cleaner = LogCleaner {
-            dir,
-            days,
-            // execute once every 5 seconds for testing
-            cron_expression: Some("1/5 * * * * * *".to_string()),
-            error_handler: MyLoggerErrorHandler,
-        };
-
-        println!("test_schedule_cleanup_log_files start");
-        if let Err(e) = cleaner.schedule_cleanup_log_files().await {
-            panic!("schedule_cleanup_log_files failed, error: {}", e)
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return self.inner.flush();
         }
-        println!("test_schedule_cleanup_log_files end");
-
-        let mut has_files = true;
-        let mut count = 0;
-        while count < 3 {
-            if let Ok(entries) = fs::read_dir(dir) {
-                has_files = entries.filter_map(|entry| entry.ok()).any(|entry| {
-                    entry
-                        .metadata()
-                        .ok()
-                        .map(|md| {
-                            (Utc::now() - DateTime::from(md.modified().unwp())).num_days() > days
-                        })
-                        .unwrap_or(false)
-                });
-                if !has_files {
-                    return;
-                }
-                tokio::time::sleep(Duration::from_secs(6)).await;
-                count += 1;
+        let mut redacted = std::mem::take(&mut self.buf);
+        for pattern in self.patterns.iter() {
+            if let std::borrow::Cow::Owned(replaced) = pattern.replace_all(&redacted, &b"***"[..]) {
+                redacted = replaced;
             }
         }
-        assert!(!has_files);
+        self.inner.write_all(&redacted)?;
+        self.inner.flush()
     }
+}
 
-    #[test]
-    fn test_get_log_path() {
-        let log_path_default = log_path(None, None);
-        assert_eq!(log_path_default.to_str().unwp(), "/opt/logs/apps/");
+impl<W: Write> Drop for RedactingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
 
-        let log_path_from_param = log_path(Some("/a/b/c"), None);
-        assert_eq!(log_path_from_param.to_str().unwp(), "/a/b/c");
+/// Options of the global subscriber installed by [`init_logger`], the defaults are the same.
+///
+/// ```rust,ignore
+/// let (_guard, handle) = LoggerBuilder::new("my_app")
+///     .crates(&["busylib"])
+///     .log_directory("/var/log/my_app")
+///     .timestamp_format(TimestampFormat::UnixMillis)
+///     .init();
+/// ```
+pub struct LoggerBuilder {
+    bin_name: String,
+    crates_to_log: Vec<String>,
+    debug: bool,
+    log_directory: Option<PathBuf>,
+    timestamp_format: TimestampFormat,
+    console_writer: BoxMakeWriter,
+    format: LogFormat,
+    redact_patterns: Vec<String>,
+    filter: Option<Targets>,
+    fields: Vec<(String, String)>,
+    buffered_lines_limit: usize,
+    lossy: bool,
+    max_file_size: Option<(u64, usize)>,
+    error_file: bool,
+    audit_targets: Vec<String>,
+    file: bool,
+}
 
-        env::set_var("LOG_PATH", "/xx/xx");
-        let log_path_from_env = log_path(None, Some("LOG_PATH"));
-        assert_eq!(log_path_from_env.to_str().unwp(), "/xx/xx");
+impl LoggerBuilder {
+    pub fn new(bin_name: impl Into<String>) -> Self {
+        LoggerBuilder {
+            bin_name: bin_name.into(),
+            crates_to_log: Vec::new(),
+            debug: false,
+            log_directory: None,
+            timestamp_format: TimestampFormat::default(),
+            console_writer: BoxMakeWriter::new(std::io::stdout),
+            format: LogFormat::default(),
+            redact_patterns: Vec::new(),
+            filter: None,
+            fields: Vec::new(),
+            buffered_lines_limit: DEFAULT_BUFFERED_LINES_LIMIT,
+            lossy: true,
+            max_file_size: None,
+            error_file: false,
+            audit_targets: Vec::new(),
+            file: env_bool(FILE_LOG_ENV, true),
+        }
     }
 
-    #[test]
-    fn test_init_logger() {
-        let log_path = log_path(Some("./"), None);
-        let (_, _) = super::init_logger("busylib", &["busylib"], false, Some(log_path));
-        debug!("test_init_logger - debug");
-        info!("test_init_logger - info, message: {}", "xxxadf");
+    /// Log these crates at the level of `bin_name` too.
+    pub fn crates(mut self, crates_to_log: &[&str]) -> Self {
+        self.crates_to_log = crates_to_log.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// Log at DEBUG instead of INFO, capped at the level compiled in, see the
+    /// `release-max-level-info` feature.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Add `key=value` to every event, like `service`, `version` or `env` for filtering in the
+    /// log aggregator, as a JSON key in the files and `key=value` at the end of text lines.
+    pub fn field(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    /// Use `filter` verbatim for the console and the files instead of the one built from
+    /// `bin_name`, [`LoggerBuilder::crates`] and [`LoggerBuilder::debug`], which are then ignored.
+    /// The returned [`LogHandle`] reloads it.
+    pub fn filter(mut self, filter: Targets) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Write the log files into `log_directory` instead of [`log_path`].
+    pub fn log_directory(mut self, log_directory: impl Into<PathBuf>) -> Self {
+        self.log_directory = Some(log_directory.into());
+        self
+    }
+
+    pub fn timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Queue at most `limit` lines for the background thread writing the log files, 128,000 by
+    /// default. What happens to the lines logged when the queue is full is set by
+    /// [`LoggerBuilder::lossy`].
+    pub fn buffered_lines_limit(mut self, limit: usize) -> Self {
+        self.buffered_lines_limit = limit;
+        self
+    }
+
+    /// Drop the lines logged while the file queue is full, the default, or block the logging
+    /// thread until there is room with `false`. Dropped lines are counted and reported with a
+    /// WARN of `busylib` at most every [`DROPPED_LINES_REPORT_INTERVAL`].
+    pub fn lossy(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    /// Roll the log file by size instead of daily, see [`SizeRotatingAppender`]: `{bin_name}.log`
+    /// is rolled to `{bin_name}.log.1` once it would exceed `max_bytes` and only `max_files`
+    /// rolled files are kept, for disk-constrained hosts.
+    pub fn max_file_size(mut self, max_bytes: u64, max_files: usize) -> Self {
+        self.max_file_size = Some((max_bytes, max_files));
+        self
+    }
+
+    /// Also write the WARN and ERROR events of the file filter to `{bin_name}.error.log` files
+    /// next to the main ones, rolled the same way, so on-call can glance at one small file.
+    /// They are written synchronously, not through the queue of [`LoggerBuilder::lossy`].
+    pub fn error_file(mut self, error_file: bool) -> Self {
+        self.error_file = error_file;
+        self
+    }
+
+    /// Write the events of `targets` at any level to `{bin_name}.audit.log` files next to the
+    /// main ones, rolled the same way. Each line is written synchronously and fsynced before the
+    /// logging call returns, so it survives a crash, at the cost of a disk flush per event.
+    ///
+    /// ```rust,ignore
+    /// let (_guard, _handle) = LoggerBuilder::new("my_app").audit_targets(&["audit"]).init();
+    /// info!(target: "audit", user = %user, action = "delete", "record deleted");
+    /// ```
+    pub fn audit_targets(mut self, targets: &[&str]) -> Self {
+        self.audit_targets = targets.iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    /// Write the log files, on unless env var [`FILE_LOG_ENV`] is off. When off only the
+    /// console is logged to, no directory or file is created and the [`WorkerGuard`] of
+    /// [`LoggerBuilder::init`] is None.
+    pub fn file(mut self, file: bool) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// Write the console logs to stderr instead of stdout, so stdout only carries the real
+    /// output of the program.
+    pub fn stderr(self, stderr: bool) -> Self {
+        if stderr {
+            self.console_writer(std::io::stderr)
+        } else {
+            self.console_writer(std::io::stdout)
+        }
+    }
+
+    /// Replace the matches of the regex `patterns` by `***` in every line written to the console
+    /// and the files, for secrets like tokens in logged request bodies, see
+    /// [`DEFAULT_REDACT_PATTERNS`]. The patterns apply to the formatted lines, fields and
+    /// timestamps included.
+    pub fn redact<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.redact_patterns
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Write the console logs to `writer` instead of stdout.
+    pub fn console_writer<W>(mut self, writer: W) -> Self
+    where
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        self.console_writer = BoxMakeWriter::new(writer);
+        self
+    }
+
+    /// Install the global subscriber, panic on failure.
+    pub fn init(self) -> (Option<WorkerGuard>, Option<LogHandle>) {
+        self.try_init().ex("init logger failed")
+    }
+
+    /// Install the global subscriber, the log directory is created if it does not exist yet.
+    pub fn try_init(self) -> Result<(Option<WorkerGuard>, Option<LogHandle>), InitLoggerError> {
+        let BuiltLogger {
+            subscriber,
+            guard,
+            handle,
+            directory,
+            unwritable_log_path,
+            ..
+        } = self.build()?;
+        subscriber.try_init().map_err(|e| InitLoggerError {
+            details: format!("set global logger failed: {}", e),
+        })?;
+        if let Some(directory) = directory {
+            if let Some(unwritable) = unwritable_log_path {
+                warn!(
+                    "{} is not writable, use log path: {}",
+                    unwritable.display(),
+                    directory.log_directory().display()
+                );
+            }
+            let _ = LOG_DIRECTORY_HANDLE.set(directory);
+        }
+        Ok((guard, Some(handle)))
+    }
+
+    /// return the logger without installing it
+    fn build(self) -> Result<BuiltLogger<impl Subscriber + Send + Sync>, InitLoggerError> {
+        let level_filter = if self.debug {
+            filter::LevelFilter::DEBUG
+        } else {
+            filter::LevelFilter::INFO
+        }
+        .min(STATIC_MAX_LEVEL);
+        let timer = self.timestamp_format.timer()?;
+
+        let reg = tracing_subscriber::registry();
+
+        let base_filter = match self.filter {
+            Some(filter) => filter,
+            None => self.crates_to_log.iter().fold(
+                Targets::new().with_target(&self.bin_name, level_filter),
+                |t, c| t.with_target(c, level_filter),
+            ),
+        };
+        let (filter, reload_handle) = reload::Layer::new(base_filter);
+        let mut unwritable_log_path = None;
+        let directory = if self.file {
+            let log_directory = match self.log_directory {
+                Some(log_directory) => log_directory,
+                None => {
+                    let default = log_path(None, None);
+                    let log_directory = writable_or_fallback(&default);
+                    if log_directory != default {
+                        unwritable_log_path = Some(default);
+                    }
+                    log_directory
+                }
+            };
+            fs::create_dir_all(&log_directory).map_err(|e| InitLoggerError {
+                details: format!(
+                    "create log directory {} failed: {}",
+                    log_directory.display(),
+                    e
+                ),
+            })?;
+            let open_file = |name: String| {
+                match self.max_file_size {
+                    Some((max_bytes, max_files)) => {
+                        SizeRotatingAppender::new(&log_directory, name, max_bytes, max_files)
+                            .map(FileAppender::Size)
+                    }
+                    None => LocalDailyAppender::new(&log_directory, name, log_offset())
+                        .map(FileAppender::Daily),
+                }
+                .map(|appender| Arc::new(Mutex::new(appender)))
+                .map_err(|e| InitLoggerError {
+                    details: format!(
+                        "create log file in {} failed: {}",
+                        log_directory.display(),
+                        e
+                    ),
+                })
+            };
+            Some(LogDirectoryHandle {
+                appender: open_file(format!("{}.log", self.bin_name))?,
+                error_appender: match self.error_file {
+                    true => Some(open_file(format!("{}.error.log", self.bin_name))?),
+                    false => None,
+                },
+                audit_appender: match self.audit_targets.is_empty() {
+                    false => Some(open_file(format!("{}.audit.log", self.bin_name))?),
+                    true => None,
+                },
+            })
+        } else {
+            None
+        };
+        let (non_blocking, guard) = match &directory {
+            Some(directory) => {
+                let reported_lines = Arc::new(OnceCell::new());
+                let (non_blocking, guard) = NonBlockingBuilder::default()
+                    .buffered_lines_limit(self.buffered_lines_limit)
+                    .lossy(self.lossy)
+                    .thread_name("busylib-log-writer")
+                    .finish(QueueAppender {
+                        appender: SharedAppender(directory.appender.clone()),
+                        dropped_lines: reported_lines.clone(),
+                        reported: 0,
+                        last_report: Instant::now(),
+                    });
+                if self.lossy {
+                    let _ = reported_lines.set(non_blocking.error_counter());
+                }
+                (Some(non_blocking), Some(guard))
+            }
+            None => (None, None),
+        };
+        #[cfg(test)]
+        let dropped_lines = non_blocking.as_ref().map(|w| w.error_counter());
+        let patterns = self
+            .redact_patterns
+            .iter()
+            .map(|p| regex::bytes::Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| InitLoggerError {
+                details: format!("invalid redact pattern: {}", e),
+            })?;
+        let patterns = Arc::new(patterns);
+        let redact = |writer: BoxMakeWriter| {
+            if patterns.is_empty() {
+                writer
+            } else {
+                BoxMakeWriter::new(RedactingMakeWriter {
+                    inner: writer,
+                    patterns: patterns.clone(),
+                })
+            }
+        };
+        let console_writer = redact(self.console_writer);
+        let file_writer = non_blocking.map(|w| redact(BoxMakeWriter::new(w)));
+        let error_writer = directory
+            .as_ref()
+            .and_then(|d| d.error_appender.clone())
+            .map(|appender| redact(BoxMakeWriter::new(move || SharedAppender(appender.clone()))));
+        let audit_writer = directory
+            .as_ref()
+            .and_then(|d| d.audit_appender.clone())
+            .map(|appender| redact(BoxMakeWriter::new(move || SyncedAppender(appender.clone()))));
+        let fields = Arc::new(self.fields);
+        // the text console line is formatted into a buffer when there are global fields, which
+        // loses the ANSI setting of the writer, so set it on the format like `fmt::layer` does
+        let ansi = env::var("NO_COLOR").map_or(true, |v| v.is_empty());
+        // the unfiltered layer of the files
+        let file_layer = |writer: BoxMakeWriter| match self.format {
+            LogFormat::Json => json_file_layer(writer, timer.clone())
+                .map_event_format(|f| GlobalFields {
+                    inner: f,
+                    fields: fields.clone(),
+                    json: true,
+                })
+                .boxed(),
+            LogFormat::Logfmt => tracing_subscriber::fmt::layer()
+                .fmt_fields(LogfmtFields)
+                .event_format(GlobalFields {
+                    inner: Logfmt {
+                        timer: timer.clone(),
+                    },
+                    fields: fields.clone(),
+                    json: false,
+                })
+                .with_writer(writer)
+                .boxed(),
+        };
+        let main_layer = file_writer.map(|file_writer| {
+            file_layer(file_writer)
+                .with_filter(HandleFilter(reload_handle.clone()))
+                .boxed()
+        });
+        let error_layer = error_writer.map(|error_writer| {
+            let error_filter = HandleFilter(reload_handle.clone()).and(filter::LevelFilter::WARN);
+            file_layer(error_writer).with_filter(error_filter).boxed()
+        });
+        let audit_layer = audit_writer.map(|audit_writer| {
+            let audit_filter = Targets::new().with_targets(
+                self.audit_targets
+                    .iter()
+                    .map(|target| (target.clone(), filter::LevelFilter::TRACE)),
+            );
+            file_layer(audit_writer).with_filter(audit_filter).boxed()
+        });
+        let mut layers = match self.format {
+            LogFormat::Json => vec![tracing_subscriber::fmt::layer()
+                .with_timer(timer.clone())
+                .with_writer(console_writer)
+                .map_event_format(|f| GlobalFields {
+                    inner: if fields.is_empty() {
+                        f
+                    } else {
+                        f.with_ansi(ansi)
+                    },
+                    fields: fields.clone(),
+                    json: false,
+                })
+                .with_filter(filter)
+                .boxed()],
+            LogFormat::Logfmt => vec![tracing_subscriber::fmt::layer()
+                .fmt_fields(LogfmtFields)
+                .event_format(GlobalFields {
+                    inner: Logfmt {
+                        timer: timer.clone(),
+                    },
+                    fields: fields.clone(),
+                    json: false,
+                })
+                .with_writer(console_writer)
+                .with_filter(filter)
+                .boxed()],
+        };
+
+        layers.extend(main_layer);
+        layers.extend(error_layer);
+        layers.extend(audit_layer);
+
+        let subscriber = reg.with(layers);
+        Ok(BuiltLogger {
+            subscriber,
+            guard,
+            handle: reload_handle,
+            #[cfg(test)]
+            dropped_lines,
+            directory,
+            unwritable_log_path,
+        })
+    }
+}
+
+/// A logger built by a [`LoggerBuilder`], not installed yet.
+struct BuiltLogger<S> {
+    subscriber: S,
+    /// the guard of [`LoggerBuilder::init`], None when [`LoggerBuilder::file`] is off
+    guard: Option<WorkerGuard>,
+    /// the handle of [`LoggerBuilder::init`]
+    handle: LogHandle,
+    /// the counter of the lines dropped by the file writer, None when the files are off
+    #[cfg(test)]
+    dropped_lines: Option<ErrorCounter>,
+    /// the handle moving the log files, None when the files are off
+    directory: Option<LogDirectoryHandle>,
+    /// the default log path replaced by [`platform_log_path`] as it is not writable, warned
+    /// about once the logger is installed
+    unwritable_log_path: Option<PathBuf>,
+}
+
+static LOG_DIRECTORY_HANDLE: OnceCell<LogDirectoryHandle> = OnceCell::new();
+
+/// return the [`LogDirectoryHandle`] of the global logger, `None` if it was not installed by
+/// [`init_logger`] or [`LoggerBuilder::init`]
+pub fn log_directory_handle() -> Option<LogDirectoryHandle> {
+    LOG_DIRECTORY_HANDLE.get().cloned()
+}
+
+/// Moves the log files of a [`LoggerBuilder`] logger to another directory at runtime, for
+/// example during a disk migration, see [`log_directory_handle`].
+#[derive(Clone)]
+pub struct LogDirectoryHandle {
+    appender: Arc<Mutex<FileAppender>>,
+    /// the file of [`LoggerBuilder::error_file`]
+    error_appender: Option<Arc<Mutex<FileAppender>>>,
+    /// the file of [`LoggerBuilder::audit_targets`]
+    audit_appender: Option<Arc<Mutex<FileAppender>>>,
+}
+
+impl LogDirectoryHandle {
+    /// return the directory the log files are currently written to
+    pub fn log_directory(&self) -> PathBuf {
+        self.appender
+            .lock()
+            .ex("log appender lock poisoned")
+            .dir()
+            .to_path_buf()
+    }
+
+    /// return the canonical paths of the files currently written to
+    fn live_files(&self) -> Vec<PathBuf> {
+        [
+            Some(&self.appender),
+            self.error_appender.as_ref(),
+            self.audit_appender.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|appender| appender.lock().ex("log appender lock poisoned").path())
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect()
+    }
+
+    /// Write the next lines to the current files in `log_directory`, created if it does not
+    /// exist yet, after flushing the current files. The lines still queued for the file writer
+    /// land in the new directory too. On error the current directory is kept.
+    pub fn set_log_directory(
+        &self,
+        log_directory: impl Into<PathBuf>,
+    ) -> Result<(), InitLoggerError> {
+        let log_directory = log_directory.into();
+        let mut appenders: Vec<_> = std::iter::once(&self.appender)
+            .chain(&self.error_appender)
+            .chain(&self.audit_appender)
+            .map(|appender| appender.lock().ex("log appender lock poisoned"))
+            .collect();
+        let new_appenders = appenders
+            .iter()
+            .map(|appender| appender.reopen(&log_directory))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| InitLoggerError {
+                details: format!(
+                    "create log file in {} failed: {}",
+                    log_directory.display(),
+                    e
+                ),
+            })?;
+        for (appender, new_appender) in appenders.iter_mut().zip(new_appenders) {
+            if let Err(e) = appender.flush() {
+                warn!(
+                    "flush log file in {} failed: {}",
+                    appender.dir().display(),
+                    e
+                );
+            }
+            **appender = new_appender;
+        }
+        Ok(())
+    }
+}
+
+/// The file writer of a [`LoggerBuilder`] logger.
+enum FileAppender {
+    Daily(LocalDailyAppender),
+    Size(SizeRotatingAppender),
+}
+
+impl FileAppender {
+    fn dir(&self) -> &Path {
+        match self {
+            FileAppender::Daily(appender) => &appender.dir,
+            FileAppender::Size(appender) => &appender.dir,
+        }
+    }
+
+    /// return the same appender writing into `dir`
+    fn reopen(&self, dir: &Path) -> io::Result<Self> {
+        match self {
+            FileAppender::Daily(appender) => {
+                LocalDailyAppender::new(dir, appender.prefix.clone(), appender.offset)
+                    .map(FileAppender::Daily)
+            }
+            FileAppender::Size(appender) => SizeRotatingAppender::new(
+                dir,
+                appender.name.clone(),
+                appender.max_bytes,
+                appender.max_files,
+            )
+            .map(FileAppender::Size),
+        }
+    }
+}
+
+impl FileAppender {
+    /// return the file currently written to
+    fn path(&self) -> Option<PathBuf> {
+        match self {
+            FileAppender::Daily(appender) => appender
+                .current
+                .as_ref()
+                .map(|(date, _)| appender.dir.join(format!("{}.{}", appender.prefix, date))),
+            FileAppender::Size(appender) => Some(appender.dir.join(&appender.name)),
+        }
+    }
+
+    /// Flush the data of the current file to disk.
+    fn sync_data(&self) -> io::Result<()> {
+        match self {
+            FileAppender::Daily(appender) => match &appender.current {
+                Some((_, file)) => file.sync_data(),
+                None => Ok(()),
+            },
+            FileAppender::Size(appender) => appender.file.sync_data(),
+        }
+    }
+}
+
+impl Write for FileAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileAppender::Daily(appender) => appender.write(buf),
+            FileAppender::Size(appender) => appender.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileAppender::Daily(appender) => appender.flush(),
+            FileAppender::Size(appender) => appender.flush(),
+        }
+    }
+}
+
+/// The file writer of [`LogDirectoryHandle`], shared with the non-blocking writer thread.
+struct SharedAppender(Arc<Mutex<FileAppender>>);
+
+impl Write for SharedAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().ex("log appender lock poisoned").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().ex("log appender lock poisoned").flush()
+    }
+}
+
+/// [`SharedAppender`] syncing every write to disk, for [`LoggerBuilder::audit_targets`].
+struct SyncedAppender(Arc<Mutex<FileAppender>>);
+
+impl Write for SyncedAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut appender = self.0.lock().ex("log appender lock poisoned");
+        let written = appender.write(buf)?;
+        appender.sync_data()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().ex("log appender lock poisoned").flush()
+    }
+}
+
+/// The filter reloaded by a [`LogHandle`], for the file layers of a [`LoggerBuilder`] to follow
+/// the reloads of the console one, a [`reload::Layer`] filters a single layer.
+struct HandleFilter(LogHandle);
+
+impl<S> layer::Filter<S> for HandleFilter {
+    fn enabled(&self, meta: &tracing::Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        self.0
+            .with_current(|f| layer::Filter::<S>::enabled(f, meta, cx))
+            .unwrap_or(false)
+    }
+
+    fn callsite_enabled(
+        &self,
+        meta: &'static tracing::Metadata<'static>,
+    ) -> tracing::subscriber::Interest {
+        // the interests are rebuilt on every reload
+        self.0
+            .with_current(|f| layer::Filter::<S>::callsite_enabled(f, meta))
+            .unwrap_or_else(|_| tracing::subscriber::Interest::never())
+    }
+
+    fn max_level_hint(&self) -> Option<filter::LevelFilter> {
+        self.0
+            .with_current(|f| layer::Filter::<S>::max_level_hint(f))
+            .ok()
+            .flatten()
+    }
+}
+
+/// How often at most the lines dropped by a lossy [`LoggerBuilder`] are reported.
+pub const DROPPED_LINES_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The writer of the file writer thread, also warning about the lines dropped by a lossy queue
+/// from that thread, at most every [`DROPPED_LINES_REPORT_INTERVAL`] when lines are written.
+struct QueueAppender {
+    appender: SharedAppender,
+    /// the counter of the queue once it is created, only when lossy
+    dropped_lines: Arc<OnceCell<ErrorCounter>>,
+    reported: usize,
+    last_report: Instant,
+}
+
+impl QueueAppender {
+    fn report_dropped_lines(&mut self) {
+        let Some(dropped_lines) = self.dropped_lines.get() else {
+            return;
+        };
+        if self.last_report.elapsed() < DROPPED_LINES_REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+        let dropped = dropped_lines.dropped_lines();
+        if dropped > self.reported {
+            tracing::warn!(
+                dropped = dropped - self.reported,
+                total = dropped,
+                "log lines dropped, the file writer queue is full"
+            );
+            self.reported = dropped;
+        }
+    }
+}
+
+impl Write for QueueAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.appender.write(buf)?;
+        self.report_dropped_lines();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.appender.flush()
+    }
+}
+
+/// Daily rolling file writer like `tracing_appender::rolling::daily`, but the day changes at
+/// midnight in a [`UtcOffset`] instead of UTC so a file holds exactly one local calendar day.
+/// Files are named `{prefix}.YYYY-MM-DD` with the local date.
+pub struct LocalDailyAppender {
+    dir: PathBuf,
+    prefix: String,
+    offset: UtcOffset,
+    clock: fn() -> OffsetDateTime,
+    current: Option<(Date, File)>,
+}
+
+impl LocalDailyAppender {
+    /// Open the file of the current local day in `dir`, `dir` is created if it does not exist yet.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        prefix: impl Into<String>,
+        offset: UtcOffset,
+    ) -> io::Result<Self> {
+        Self::with_clock(dir, prefix, offset, OffsetDateTime::now_utc)
+    }
+
+    fn with_clock(
+        dir: impl Into<PathBuf>,
+        prefix: impl Into<String>,
+        offset: UtcOffset,
+        clock: fn() -> OffsetDateTime,
+    ) -> io::Result<Self> {
+        let mut appender = LocalDailyAppender {
+            dir: dir.into(),
+            prefix: prefix.into(),
+            offset,
+            clock,
+            current: None,
+        };
+        appender.file()?;
+        Ok(appender)
+    }
+
+    /// return the file of the current local day, opening a new one after midnight
+    fn file(&mut self) -> io::Result<&mut File> {
+        let today = (self.clock)().to_offset(self.offset).date();
+        if self.current.as_ref().map(|(date, _)| *date) != Some(today) {
+            fs::create_dir_all(&self.dir)?;
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(format!("{}.{}", self.prefix, today)))?;
+            self.current = Some((today, file));
+        }
+        Ok(&mut self.current.as_mut().ex("current log file just set").1)
+    }
+}
+
+impl Write for LocalDailyAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// File writer rolling by size instead of time: it appends to `{dir}/{name}` and once a write
+/// would make it exceed `max_bytes`, the file is renamed to `{name}.1`, the older rolled files
+/// shift to `{name}.2` and so on, and only `max_files` rolled files are kept. A write is never
+/// split over two files, so neither is a line written at once like the logger does, and a
+/// single write larger than `max_bytes` gets a file of its own. Use
+/// [`LoggerBuilder::max_file_size`] for the log files of the logger, or
+/// `Mutex<SizeRotatingAppender>` as any [`MakeWriter`].
+///
+/// ```rust,ignore
+/// let appender = SizeRotatingAppender::new("/var/log/my_app", "audit.log", 100 << 20, 10)?;
+/// let layer = fmt::layer().with_writer(Mutex::new(appender));
+/// ```
+pub struct SizeRotatingAppender {
+    dir: PathBuf,
+    name: String,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRotatingAppender {
+    /// Append to `name` in `dir`, `dir` is created if it does not exist yet.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        name: impl Into<String>,
+        max_bytes: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        let dir = dir.into();
+        let name = name.into();
+        fs::create_dir_all(&dir)?;
+        let file = Self::open(&dir.join(&name))?;
+        let written = file.metadata()?.len();
+        Ok(SizeRotatingAppender {
+            dir,
+            name,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn open(path: &Path) -> io::Result<File> {
+        fs::OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rolled(&self, n: usize) -> PathBuf {
+        self.dir.join(format!("{}.{}", self.name, n))
+    }
+
+    /// shift the rolled files, dropping the oldest, and start a new file
+    fn roll(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(self.dir.join(&self.name))?;
+        } else {
+            match fs::remove_file(self.rolled(self.max_files)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for n in (1..self.max_files).rev() {
+                match fs::rename(self.rolled(n), self.rolled(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(self.dir.join(&self.name), self.rolled(1))?;
+        }
+        self.file = Self::open(&self.dir.join(&self.name))?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.roll()?;
+        }
+        // whole, so the size check holds for the next write
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// One line of the JSON log files written by [`init_logger`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    /// RFC 3339 timestamp
+    pub timestamp: String,
+    /// `TRACE`, `DEBUG`, `INFO`, `WARN` or `ERROR`
+    pub level: String,
+    pub target: String,
+    /// the event message, taken out of the event fields
+    pub message: String,
+    /// the other event fields
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct RawLogEntry {
+    timestamp: String,
+    level: String,
+    target: String,
+    #[serde(default)]
+    fields: Map<String, Value>,
+}
+
+impl FromStr for LogEntry {
+    type Err = ParseLogError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let raw: RawLogEntry = serde_json::from_str(line).map_err(|e| ParseLogError {
+            details: format!("invalid log line: {}", e),
+        })?;
+        let mut fields = raw.fields;
+        let message = match fields.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        Ok(LogEntry {
+            timestamp: raw.timestamp,
+            level: raw.level,
+            target: raw.target,
+            message,
+            fields,
+        })
+    }
+}
+
+/// Read back a JSON log file written by [`init_logger`], one entry per non-empty line.
+///
+/// ```rust,ignore
+/// for entry in parse_log_file("/opt/logs/apps/app.log.2023-09-01")? {
+///     let entry = entry?;
+///     println!("{} {}", entry.level, entry.message);
+/// }
+/// ```
+pub fn parse_log_file(
+    path: impl AsRef<Path>,
+) -> Result<impl Iterator<Item = Result<LogEntry, ParseLogError>>, ParseLogError> {
+    let path = path.as_ref();
+    let file = fs::File::open(path).map_err(|e| ParseLogError {
+        details: format!("open log file {:?} failed: {}", path, e),
+    })?;
+    Ok(BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(i, line)| {
+            let line = line.map_err(|e| ParseLogError {
+                details: format!("read line {} failed: {}", i + 1, e),
+            })?;
+            line.parse::<LogEntry>().map_err(|e| ParseLogError {
+                details: format!("line {}: {}", i + 1, e),
+            })
+        }))
+}
+
+/// return the last `n` lines of the current log file of `bin_name` in the directory of the
+/// global logger, or [`log_path`] if it was not installed, see [`tail_log_in`]
+pub fn tail_log(bin_name: &str, n: usize) -> Result<Vec<String>, ParseLogError> {
+    let dir = log_directory_handle()
+        .map(|directory| directory.log_directory())
+        .unwrap_or_else(|| log_path(None, None));
+    tail_log_in(dir, bin_name, n)
+}
+
+/// return the last `n` lines of the newest `{bin_name}.log.*` file written by [`init_logger`]
+/// in `dir`, or of `{bin_name}.log` when the files roll by size, see
+/// [`LoggerBuilder::max_file_size`], files compressed by [`LogCleanerBuilder::compression`]
+/// are skipped
+pub fn tail_log_in(
+    dir: impl AsRef<Path>,
+    bin_name: &str,
+    n: usize,
+) -> Result<Vec<String>, ParseLogError> {
+    let dir = dir.as_ref();
+    let size_rotated = dir.join(format!("{}.log", bin_name));
+    if size_rotated.is_file() {
+        return tail_file(size_rotated, n);
+    }
+    let prefix = format!("{}.log.", bin_name);
+    let entries = fs::read_dir(dir).map_err(|e| ParseLogError {
+        details: format!("read log directory {:?} failed: {}", dir, e),
+    })?;
+    // the date suffix sorts chronologically
+    let current = entries
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with(&prefix) && !Compression::is_compressed(Path::new(name)))
+        .max()
+        .ok_or_else(|| ParseLogError {
+            details: format!("no log file of {} in {:?}", bin_name, dir),
+        })?;
+    tail_file(dir.join(current), n)
+}
+
+/// return the last `n` lines of the file at `path`, reading it backwards from the end so only
+/// the tail is loaded
+pub fn tail_file(path: impl AsRef<Path>, n: usize) -> Result<Vec<String>, ParseLogError> {
+    const BLOCK: u64 = 8 * 1024;
+
+    let path = path.as_ref();
+    let read_err = |e: std::io::Error| ParseLogError {
+        details: format!("read log file {:?} failed: {}", path, e),
+    };
+    let mut file = fs::File::open(path).map_err(|e| ParseLogError {
+        details: format!("open log file {:?} failed: {}", path, e),
+    })?;
+    let mut pos = file.metadata().map_err(read_err)?.len();
+    // blocks from the end of the file, only the new block is searched for newlines
+    let mut blocks = Vec::new();
+    let mut newlines = 0;
+    // a newline ending the file does not start another line
+    while pos > 0 && newlines <= n {
+        let len = pos.min(BLOCK);
+        pos -= len;
+        let mut block = vec![0; len as usize];
+        file.seek(SeekFrom::Start(pos)).map_err(read_err)?;
+        file.read_exact(&mut block).map_err(read_err)?;
+        newlines += block.iter().filter(|b| **b == b'\n').count();
+        blocks.push(block);
+    }
+    blocks.reverse();
+    let tail = blocks.concat();
+    let text = String::from_utf8_lossy(&tail);
+    let lines: Vec<_> = text.lines().collect();
+    Ok(lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// Layer writing the events inside a span carrying the field `field` to the daily rolling JSON
+/// file `tenant-<value>.log` of that value in `dir`, events outside such spans are skipped.
+/// Lines are the ones of [`init_logger`] files, the tenant is in their span list. Errors
+/// writing the files go to the [`TenantErrorHandler`], [`LoggingErrorHandler`] by default,
+/// whose own events are not written to the tenant files.
+///
+/// ```rust,ignore
+/// tracing_subscriber::registry()
+///     .with(TenantFileLayer::new("/opt/logs/tenants/", "tenant_id").max_tenants(100))
+///     .init();
+/// let _span = info_span!("request", tenant_id = %tenant).entered();
+/// info!("handled");
+/// ```
+pub struct TenantFileLayer<S, H = LoggingErrorHandler> {
+    dir: PathBuf,
+    field: &'static str,
+    max_tenants: usize,
+    files: Mutex<TenantFiles>,
+    format: fmt::Layer<S, JsonFields, Format<Json, LogTimer>, TenantLineWriter>,
+    error_handler: H,
+}
+
+/// Receives the errors of a [`TenantFileLayer`] writing the file of `tenant`.
+pub trait TenantErrorHandler {
+    fn handle_error(&self, tenant: &str, error: io::Error);
+}
+
+/// Pass a closure directly as handler, the parameter types have to be spelled out:
+///
+/// ```rust,ignore
+/// TenantFileLayer::new(dir, "tenant_id").error_handler(|tenant: &str, e: io::Error| {
+///     eprintln!("{tenant}: {e}")
+/// });
+/// ```
+impl<F> TenantErrorHandler for F
+where
+    F: Fn(&str, io::Error),
+{
+    fn handle_error(&self, tenant: &str, error: io::Error) {
+        self(tenant, error)
+    }
+}
+
+/// the open files of a [`TenantFileLayer`] by sanitized tenant, with the write count when they
+/// were last written to
+#[derive(Default)]
+struct TenantFiles {
+    appenders: HashMap<String, (LocalDailyAppender, u64)>,
+    writes: u64,
+}
+
+/// the tenant of a span, stored in its extensions
+struct TenantId(String);
+
+struct TenantVisitor<'a> {
+    field: &'a str,
+    value: Option<String>,
+}
+
+impl Visit for TenantVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == self.field {
+            self.value = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == self.field {
+            self.value = Some(format!("{:?}", value));
+        }
+    }
+}
+
+thread_local! {
+    /// the line formatted for a [`TenantFileLayer`], before it is written to the tenant file
+    static TENANT_LINE: std::cell::RefCell<Vec<u8>> = const { std::cell::RefCell::new(Vec::new()) };
+    /// set while the error handler of a [`TenantFileLayer`] runs
+    static TENANT_ERROR: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// the writer of the JSON layer of a [`TenantFileLayer`], into [`TENANT_LINE`]
+struct TenantLineWriter;
+
+impl Write for TenantLineWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        TENANT_LINE.with(|line| line.borrow_mut().extend_from_slice(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for TenantLineWriter {
+    type Writer = TenantLineWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        TenantLineWriter
+    }
+}
+
+impl<S> TenantFileLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    pub fn new(dir: impl Into<PathBuf>, field: &'static str) -> Self {
+        TenantFileLayer {
+            dir: dir.into(),
+            field,
+            max_tenants: 1024,
+            files: Mutex::new(TenantFiles::default()),
+            format: json_file_layer(TenantLineWriter, LogTimer::default()),
+            error_handler: LoggingErrorHandler,
+        }
+    }
+}
+
+impl<S, H> TenantFileLayer<S, H>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    H: TenantErrorHandler,
+{
+    /// Keep at most `max_tenants` files open, 1,024 by default, the least recently written one
+    /// is closed to open the file of a new tenant and reopened when its tenant logs again.
+    pub fn max_tenants(mut self, max_tenants: usize) -> Self {
+        self.max_tenants = max_tenants;
+        self
+    }
+
+    /// Pass the errors writing the files to `error_handler`.
+    pub fn error_handler<H2>(self, error_handler: H2) -> TenantFileLayer<S, H2>
+    where
+        H2: TenantErrorHandler,
+    {
+        TenantFileLayer {
+            dir: self.dir,
+            field: self.field,
+            max_tenants: self.max_tenants,
+            files: self.files,
+            format: self.format,
+            error_handler,
+        }
+    }
+
+    fn record_tenant(&self, id: &Id, values: &Record<'_>, ctx: &Context<'_, S>) {
+        let mut visitor = TenantVisitor {
+            field: self.field,
+            value: None,
+        };
+        values.record(&mut visitor);
+        if let (Some(tenant), Some(span)) = (visitor.value, ctx.span(id)) {
+            span.extensions_mut().replace(TenantId(tenant));
+        }
+    }
+
+    fn write_line(&self, tenant: &str, line: &[u8]) -> io::Result<()> {
+        // keep the file inside `self.dir` whatever the tenant value is
+        let name: String = tenant
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let mut files = self.files.lock().ex("tenant log files lock poisoned");
+        files.writes += 1;
+        let writes = files.writes;
+        if !files.appenders.contains_key(&name) && files.appenders.len() >= self.max_tenants {
+            let least_recent = files
+                .appenders
+                .iter()
+                .min_by_key(|(_, (_, written))| *written)
+                .map(|(name, _)| name.clone());
+            if let Some(least_recent) = least_recent {
+                files.appenders.remove(&least_recent);
+            }
+        }
+        let (appender, written) = match files.appenders.entry(name) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let appender = LocalDailyAppender::new(
+                    &self.dir,
+                    format!("tenant-{}.log", e.key()),
+                    log_offset(),
+                )?;
+                e.insert((appender, writes))
+            }
+        };
+        *written = writes;
+        appender.write_all(line)
+    }
+}
+
+impl<S, H> Layer<S> for TenantFileLayer<S, H>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    H: TenantErrorHandler + 'static,
+    Self: 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.record_tenant(id, &Record::new(attrs.values()), &ctx);
+        self.format.on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.record_tenant(id, values, &ctx);
+        self.format.on_record(id, values, ctx);
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.format.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.format.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.format.on_close(id, ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if TENANT_ERROR.with(|handling| handling.get()) {
+            return;
+        }
+        let Some(tenant) = ctx.event_scope(event).and_then(|mut scope| {
+            scope.find_map(|span| span.extensions().get::<TenantId>().map(|t| t.0.clone()))
+        }) else {
+            return;
+        };
+        self.format.on_event(event, ctx);
+        let line = TENANT_LINE.with(|line| std::mem::take(&mut *line.borrow_mut()));
+        if let Err(e) = self.write_line(&tenant, &line) {
+            TENANT_ERROR.with(|handling| handling.set(true));
+            self.error_handler.handle_error(&tenant, e);
+            TENANT_ERROR.with(|handling| handling.set(false));
+        }
+    }
+}
+
+/// Events captured in memory by [`capture`], capturing stops when it is dropped.
+#[cfg(any(test, feature = "test-util"))]
+pub struct LogCapture {
+    buffer: std::sync::Arc<Mutex<Vec<u8>>>,
+    _guard: tracing::subscriber::DefaultGuard,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Clone)]
+struct CaptureWriter(std::sync::Arc<Mutex<Vec<u8>>>);
+
+#[cfg(any(test, feature = "test-util"))]
+impl std::io::Write for CaptureWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .ex("log capture lock poisoned")
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl<'a> MakeWriter<'a> for CaptureWriter {
+    type Writer = CaptureWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Capture the events of the current thread at every level in memory, as the JSON lines
+/// [`init_logger`] writes to files. Needs the `test-util` feature outside of this crate.
+///
+/// ```rust,ignore
+/// let logs = capture();
+/// handle_request();
+/// assert!(logs.contains("request handled"));
+/// ```
+#[cfg(any(test, feature = "test-util"))]
+pub fn capture() -> LogCapture {
+    let buffer = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(json_file_layer(
+        CaptureWriter(buffer.clone()),
+        LogTimer::default(),
+    ));
+    LogCapture {
+        buffer,
+        _guard: tracing::subscriber::set_default(subscriber),
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl LogCapture {
+    /// return the raw JSON lines captured so far
+    pub fn lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.buffer.lock().ex("log capture lock poisoned"))
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    /// return the entries captured so far
+    pub fn entries(&self) -> Vec<LogEntry> {
+        self.lines()
+            .iter()
+            .filter_map(|line| line.parse().ok())
+            .collect()
+    }
+
+    /// whether a captured message contains `text`
+    pub fn contains(&self, text: &str) -> bool {
+        self.entries().iter().any(|e| e.message.contains(text))
+    }
+}
+
+/// Counters collected by a single [`LogCleaner::cleanup_files_immediately`] run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CleanupStats {
+    /// number of directory entries inspected
+    pub scanned: u64,
+    /// number of files removed
+    pub deleted: u64,
+    /// total size in bytes of the removed files
+    pub bytes_freed: u64,
+    /// number of files compressed, see [`LogCleanerBuilder::compression`]
+    pub compressed: u64,
+    /// number of entries that could not be inspected or removed
+    pub errors: u64,
+}
+
+/// Compression algorithm of [`Compression`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionAlgo {
+    /// `.gz` files readable everywhere, levels 0 to 9
+    #[default]
+    Gzip,
+    /// `.zst` files, better ratio for archival, levels 1 to 22
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl CompressionAlgo {
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        match self {
+            CompressionAlgo::Gzip => 0..=9,
+            #[cfg(feature = "zstd")]
+            CompressionAlgo::Zstd => 1..=22,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            CompressionAlgo::Gzip => "gz",
+            #[cfg(feature = "zstd")]
+            CompressionAlgo::Zstd => "zst",
+        }
+    }
+}
+
+/// How [`LogCleaner`] compresses old log files, see [`LogCleanerBuilder::compression`].
+///
+/// ```rust,ignore
+/// let cleaner = LogCleaner::builder("/opt/logs/apps/")
+///     .compression(Compression {
+///         algo: CompressionAlgo::Zstd,
+///         level: 19,
+///         ..Default::default()
+///     })
+///     .build()?;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    pub algo: CompressionAlgo,
+    pub level: u32,
+    /// files smaller than this are left uncompressed however old they are, default 0
+    pub min_size_bytes: u64,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            algo: CompressionAlgo::Gzip,
+            level: 6,
+            min_size_bytes: 0,
+        }
+    }
+}
+
+impl Compression {
+    /// whether `path` is already an archive of any algorithm
+    fn is_compressed(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("gz" | "zst")
+        )
+    }
+
+    /// replace `path` by its compressed copy with the same `modified` time, return the new path
+    fn compress_file(&self, path: &Path, modified: SystemTime) -> io::Result<PathBuf> {
+        let mut target = path.as_os_str().to_owned();
+        target.push(".");
+        target.push(self.algo.extension());
+        let target = PathBuf::from(target);
+        let result = self.compress_to(path, &target).and_then(|file| {
+            file.set_modified(modified)?;
+            fs::remove_file(path)
+        });
+        if let Err(e) = result {
+            let _ = fs::remove_file(&target);
+            return Err(e);
+        }
+        Ok(target)
+    }
+
+    fn compress_to(&self, path: &Path, target: &Path) -> io::Result<File> {
+        let mut input = File::open(path)?;
+        let output = File::create(target)?;
+        match self.algo {
+            CompressionAlgo::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(output, flate2::Compression::new(self.level));
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            CompressionAlgo::Zstd => {
+                let mut encoder = zstd::Encoder::new(output, self.level as i32)?;
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Current size of the directories managed by a [`LogCleaner`], see [`LogCleaner::disk_usage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// total size in bytes of the files
+    pub total_bytes: u64,
+    /// number of files
+    pub files: u64,
+    /// modification time of the oldest file, `None` if there are no files
+    pub oldest: Option<DateTime<Utc>>,
+    /// modification time of the newest file, `None` if there are no files
+    pub newest: Option<DateTime<Utc>>,
+}
+
+/// A file in a directory managed by a [`LogCleaner`], see [`LogCleaner::list_files`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFileInfo {
+    pub path: PathBuf,
+    /// size in bytes
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+    /// whole days since `modified` by [`LogCleaner::with_clock`], the age compared to the
+    /// retention days of the directory
+    pub age_days: i64,
+}
+
+/// Default cron expression of [`LogCleaner`], run at midnight every day.
+pub const DEFAULT_CLEANUP_CRON: &str = "0 0 0 * * * *";
+
+/// Check that `cron` is a valid cron expression for [`LogCleaner::cron_expression`].
+pub fn validate_cron(cron: &str) -> Result<(), RemoveFilesError> {
+    cron::Schedule::from_str(cron)
+        .map(|_| ())
+        .map_err(|e| RemoveFilesError {
+            details: format!("invalid cron expression `{}`: {}", cron, e),
+            dir: None,
+        })
+}
+
+pub trait LogCleanerErrorHandler {
+    fn handle_error(&self, error: RemoveFilesError);
+}
+
+/// Pass a closure directly as handler, the parameter type has to be spelled out:
+///
+/// ```rust,ignore
+/// LogCleaner::new(dir, 30, None, |e: RemoveFilesError| warn!("{e}"))?;
+/// ```
+impl<F> LogCleanerErrorHandler for F
+where
+    F: Fn(RemoveFilesError),
+{
+    fn handle_error(&self, error: RemoveFilesError) {
+        self(error)
+    }
+}
+
+/// [`LogCleanerErrorHandler`] and [`TenantErrorHandler`] emitting every error with
+/// `tracing::warn!`, the default of [`LogCleaner::builder`] and [`TenantFileLayer::new`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoggingErrorHandler;
+
+impl LogCleanerErrorHandler for LoggingErrorHandler {
+    fn handle_error(&self, error: RemoveFilesError) {
+        tracing::warn!(dir = ?error.dir(), "log cleanup error: {}", error);
+    }
+}
+
+impl TenantErrorHandler for LoggingErrorHandler {
+    fn handle_error(&self, tenant: &str, error: io::Error) {
+        tracing::warn!(tenant, "write tenant log file failed: {}", error);
+    }
+}
+
+/// [`LogCleanerErrorHandler`] and [`TenantErrorHandler`] ignoring every error.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopErrorHandler;
+
+impl LogCleanerErrorHandler for NoopErrorHandler {
+    fn handle_error(&self, _error: RemoveFilesError) {}
+}
+
+impl TenantErrorHandler for NoopErrorHandler {
+    fn handle_error(&self, _tenant: &str, _error: io::Error) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct LogCleaner<P, H>
+where
+    P: AsRef<Path>,
+    H: LogCleanerErrorHandler,
+{
+    pub dir: P,
+    pub days: i64,
+    pub cron_expression: Option<String>,
+    pub error_handler: H,
+    /// additional directories with their own retention days, see [`LogCleaner::with_dir`]
+    extra_dirs: Vec<(PathBuf, i64)>,
+    /// time source used to compute file age, default is [`Utc::now`]
+    clock: fn() -> DateTime<Utc>,
+    /// also clean up files in subdirectories
+    recursive: bool,
+    /// `bin_name` of [`init_logger`] whose current log file is never deleted, see
+    /// [`LogCleanerBuilder::skip_active_log`]
+    active_log: Option<String>,
+    /// compress retained files, see [`LogCleanerBuilder::compression`]
+    compression: Option<Compression>,
+    /// also clean up once when scheduled, see [`LogCleanerBuilder::run_on_start`]
+    run_on_start: bool,
+}
+
+/// Fluent builder of [`LogCleaner`], see [`LogCleaner::builder`].
+#[derive(Clone, Debug)]
+pub struct LogCleanerBuilder<P, H> {
+    dir: P,
+    days: i64,
+    cron_expression: Option<String>,
+    error_handler: H,
+    extra_dirs: Vec<(PathBuf, i64)>,
+    clock: fn() -> DateTime<Utc>,
+    recursive: bool,
+    active_log: Option<String>,
+    compression: Option<Compression>,
+    run_on_start: bool,
+}
+
+impl<P> LogCleaner<P, LoggingErrorHandler>
+where
+    P: AsRef<Path> + Sync + Send + Clone + 'static,
+{
+    /// Start building a cleaner of `dir`, which keeps 30 days of files, runs at
+    /// [`DEFAULT_CLEANUP_CRON`] and logs errors with [`LoggingErrorHandler`] unless configured
+    /// otherwise.
+    ///
+    /// ```rust,ignore
+    /// let cleaner = LogCleaner::builder("/opt/logs/apps/")
+    ///     .days(7)
+    ///     .cron("0 0 3 * * * *")
+    ///     .recursive(true)
+    ///     .error_handler(MyHandler)
+    ///     .build()?;
+    /// ```
+    pub fn builder(dir: P) -> LogCleanerBuilder<P, LoggingErrorHandler> {
+        LogCleanerBuilder {
+            dir,
+            days: 30,
+            cron_expression: None,
+            error_handler: LoggingErrorHandler,
+            extra_dirs: Vec::new(),
+            clock: Utc::now,
+            recursive: false,
+            active_log: None,
+            compression: None,
+            run_on_start: false,
+        }
+    }
+}
+
+impl<P, H> LogCleanerBuilder<P, H>
+where
+    P: AsRef<Path> + Sync + Send + Clone + 'static,
+    H: LogCleanerErrorHandler + Sync + Send + Clone + 'static,
+{
+    /// Files modified more than `days` ago are removed.
+    pub fn days(mut self, days: i64) -> Self {
+        self.days = days;
+        self
+    }
+
+    pub fn cron(mut self, cron_expression: impl Into<String>) -> Self {
+        self.cron_expression = Some(cron_expression.into());
+        self
+    }
+
+    /// Also clean up files in subdirectories, symlinked directories are not followed.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Never delete the log file [`init_logger`] is writing for `bin_name`, the newest
+    /// `{bin_name}.log.*` file of each directory, or `{bin_name}.log` itself, however old it is.
+    /// The files of the logger installed in this process are always skipped, this is for the
+    /// ones of other processes.
+    pub fn skip_active_log(mut self, bin_name: impl Into<String>) -> Self {
+        self.active_log = Some(bin_name.into());
+        self
+    }
+
+    /// Compress the files kept by the cleanup once they have not been modified for a day and are
+    /// at least [`Compression::min_size_bytes`] large, into
+    /// `{file}.gz` or `{file}.zst` with the modified time of the original. Compressed files are
+    /// removed after `days` like the others.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Also clean up once right away when the job is scheduled by
+    /// [`LogCleaner::schedule_cleanup_log_files`] or [`LogCleaner::add_to_scheduler`], so the
+    /// backlog of a service that was down is reclaimed without waiting for the next cron tick.
+    pub fn run_on_start(mut self, run_on_start: bool) -> Self {
+        self.run_on_start = run_on_start;
+        self
+    }
+
+    pub fn error_handler<H2>(self, error_handler: H2) -> LogCleanerBuilder<P, H2>
+    where
+        H2: LogCleanerErrorHandler + Sync + Send + Clone + 'static,
+    {
+        LogCleanerBuilder {
+            dir: self.dir,
+            days: self.days,
+            cron_expression: self.cron_expression,
+            error_handler,
+            extra_dirs: self.extra_dirs,
+            clock: self.clock,
+            recursive: self.recursive,
+            active_log: self.active_log,
+            compression: self.compression,
+            run_on_start: self.run_on_start,
+        }
+    }
+
+    /// See [`LogCleaner::with_dir`].
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>, days: i64) -> Self {
+        self.extra_dirs.push((dir.into(), days));
+        self
+    }
+
+    /// See [`LogCleaner::with_clock`].
+    pub fn clock(mut self, clock: fn() -> DateTime<Utc>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Validate the settings and build the cleaner.
+    pub fn build(self) -> Result<LogCleaner<P, H>, RemoveFilesError> {
+        if let Some(cron) = &self.cron_expression {
+            validate_cron(cron)?;
+        }
+        for (dir, days) in std::iter::once((self.dir.as_ref(), self.days))
+            .chain(self.extra_dirs.iter().map(|(d, days)| (d.as_path(), *days)))
+        {
+            if days < 0 {
+                return Err(RemoveFilesError::new(
+                    dir,
+                    format!("days must not be negative, got {}", days),
+                ));
+            }
+        }
+        if let Some(compression) = &self.compression {
+            let levels = compression.algo.levels();
+            if !levels.contains(&compression.level) {
+                return Err(RemoveFilesError::new(
+                    self.dir.as_ref(),
+                    format!(
+                        "{:?} compression level must be in {:?}, got {}",
+                        compression.algo, levels, compression.level
+                    ),
+                ));
+            }
+        }
+        Ok(LogCleaner {
+            dir: self.dir,
+            days: self.days,
+            cron_expression: self.cron_expression,
+            error_handler: self.error_handler,
+            extra_dirs: self.extra_dirs,
+            clock: self.clock,
+            recursive: self.recursive,
+            active_log: self.active_log,
+            compression: self.compression,
+            run_on_start: self.run_on_start,
+        })
+    }
+}
+
+impl<P, H> LogCleaner<P, H>
+where
+    P: AsRef<Path> + Sync + Send + Clone + 'static,
+    H: LogCleanerErrorHandler + Sync + Send + Clone + 'static,
+{
+    /// Build a cleaner, `cron_expression` is validated up front so a misconfiguration fails here
+    /// rather than later inside [`LogCleaner::schedule_cleanup_log_files`].
+    /// `None` means the default [`DEFAULT_CLEANUP_CRON`].
+    /// Shorthand of [`LogCleaner::builder`], which offers more options.
+    pub fn new(
+        dir: P,
+        days: i64,
+        cron_expression: Option<String>,
+        error_handler: H,
+    ) -> Result<Self, RemoveFilesError> {
+        let builder = LogCleaner::builder(dir)
+            .days(days)
+            .error_handler(error_handler);
+        match cron_expression {
+            Some(cron) => builder.cron(cron).build(),
+            None => builder.build(),
+        }
+    }
+
+    /// Replace the time source used to compute file age, mostly useful in tests to simulate
+    /// files being N days old without touching their modified time.
+    pub fn with_clock(mut self, clock: fn() -> DateTime<Utc>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Register another directory with its own retention `days` to be swept together with
+    /// `self.dir`, so one cron job can clean up several sibling log directories.
+    ///
+    /// ```rust,ignore
+    /// LogCleaner::new("/opt/logs/apps/", 30, None, handler)?
+    ///     .with_dir("/opt/logs/access/", 7)
+    ///     .with_dir("/opt/logs/gc/", 3);
+    /// ```
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>, days: i64) -> Self {
+        self.extra_dirs.push((dir.into(), days));
+        self
+    }
+
+    /// return the next time the cron expression fires after now by [`LogCleaner::with_clock`],
+    /// in UTC like the scheduler, without starting it. None if the expression never fires again.
+    ///
+    /// ```rust,ignore
+    /// if let Some(next) = cleaner.next_run() {
+    ///     println!("next cleanup in {}m", (next - Utc::now()).num_minutes());
+    /// }
+    /// ```
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        let cron = self
+            .cron_expression
+            .as_deref()
+            .unwrap_or(DEFAULT_CLEANUP_CRON);
+        cron::Schedule::from_str(cron)
+            .ok()?
+            .after(&(self.clock)())
+            .next()
+    }
+
+    /// All directories managed by this cleaner with their retention days, `self.dir` first.
+    pub fn dirs(&self) -> Vec<(&Path, i64)> {
+        let mut dirs = vec![(self.dir.as_ref(), self.days)];
+        dirs.extend(self.extra_dirs.iter().map(|(d, days)| (d.as_path(), *days)));
+        dirs
+    }
+
+    /// Immediately clean up files in the specified `self.dir` (and every directory registered
+    /// with [`LogCleaner::with_dir`]) that have been modified more than a specified number of
+    /// `self.days` ago.
+    /// Typically used to clean up log files with.
+    ///
+    /// Errors on individual files and directories added with [`LogCleaner::with_dir`] that can
+    /// not be read are passed to `self.error_handler` and counted in [`CleanupStats::errors`],
+    /// the sweep carries on with the remaining ones. If `self.dir` can not be read, return Err.
+    ///
+    /// ```rust,ignore
+    ///
+    /// cleanup_files_immediately("/opt/logs/apps/", 30);
+    /// ```
+    pub fn cleanup_files_immediately(&self) -> Result<CleanupStats, RemoveFilesError> {
+        let mut stats = CleanupStats::default();
+        self.cleanup_dir(self.dir.as_ref(), self.days, &mut stats)?;
+        for (dir, days) in &self.extra_dirs {
+            if let Err(e) = self.cleanup_dir(dir, *days, &mut stats) {
+                stats.errors += 1;
+                self.error_handler.handle_error(e);
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Report size, file count and modification time range of the files in every managed
+    /// directory without deleting anything, entries are walked as in
+    /// [`LogCleaner::cleanup_files_immediately`].
+    pub fn disk_usage(&self) -> Result<DiskUsage, RemoveFilesError> {
+        let mut usage = DiskUsage::default();
+        let mut stats = CleanupStats::default();
+        for (dir, _) in self.dirs() {
+            self.walk_dir(dir, &mut stats, &mut |_, _, metadata, modified, _| {
+                let modified = DateTime::<Utc>::from(modified);
+                usage.total_bytes += metadata.len();
+                usage.files += 1;
+                usage.oldest = Some(usage.oldest.map_or(modified, |t| t.min(modified)));
+                usage.newest = Some(usage.newest.map_or(modified, |t| t.max(modified)));
+            })?;
+        }
+        Ok(usage)
+    }
+
+    /// List the files of every managed directory, oldest first, without deleting anything,
+    /// entries are walked as in [`LogCleaner::cleanup_files_immediately`].
+    pub fn list_files(&self) -> Result<Vec<LogFileInfo>, RemoveFilesError> {
+        let now = (self.clock)();
+        let mut files = Vec::new();
+        let mut stats = CleanupStats::default();
+        for (dir, _) in self.dirs() {
+            self.walk_dir(dir, &mut stats, &mut |_, path, metadata, modified, _| {
+                let modified = DateTime::<Utc>::from(modified);
+                files.push(LogFileInfo {
+                    path: path.to_path_buf(),
+                    size: metadata.len(),
+                    modified,
+                    age_days: (now - modified).num_days(),
+                });
+            })?;
+        }
+        files.sort_by(|a, b| {
+            a.modified
+                .cmp(&b.modified)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        Ok(files)
+    }
+
+    fn cleanup_dir(
+        &self,
+        dir: &Path,
+        days: i64,
+        stats: &mut CleanupStats,
+    ) -> Result<(), RemoveFilesError> {
+        let mut active_logs = HashMap::new();
+        // the files the global logger is writing are never touched
+        let live_files: Vec<_> = LOG_DIRECTORY_HANDLE
+            .get()
+            .map(|directory| directory.live_files())
+            .unwrap_or_default();
+        self.walk_dir(dir, stats, &mut |dir, path, metadata, modified, stats| {
+            if live_files.iter().any(|live| {
+                live.file_name() == path.file_name()
+                    && fs::canonicalize(path).ok().as_ref() == Some(live)
+            }) {
+                return;
+            }
+            if let Some(bin_name) = &self.active_log {
+                let active = active_logs
+                    .entry(dir.to_path_buf())
+                    .or_insert_with(|| active_log_file(dir, bin_name));
+                if path.file_name() == active.as_deref().and_then(|p| p.file_name()) {
+                    return;
+                }
+            }
+            let age = ((self.clock)() - DateTime::from(modified)).num_days();
+            if age > days {
+                match fs::remove_file(path) {
+                    Ok(_) => {
+                        stats.deleted += 1;
+                        stats.bytes_freed += metadata.len();
+                    }
+                    Err(e) => {
+                        stats.errors += 1;
+                        self.error_handler.handle_error(RemoveFilesError::new(
+                            dir,
+                            format!("delete file failed, path: {:?}, error: {}", path, e),
+                        ));
+                    }
+                }
+            } else if let Some(compression) = &self.compression {
+                if age >= 1
+                    && metadata.len() >= compression.min_size_bytes
+                    && !Compression::is_compressed(path)
+                {
+                    match compression.compress_file(path, modified) {
+                        Ok(_) => stats.compressed += 1,
+                        Err(e) => {
+                            stats.errors += 1;
+                            self.error_handler.handle_error(RemoveFilesError::new(
+                                dir,
+                                format!("compress file failed, path: {:?}, error: {}", path, e),
+                            ));
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Call `visit(dir, path, metadata, modified, stats)` for every file in `dir`, descending
+    /// into subdirectories if `self.recursive`. Symlinks are skipped, neither followed nor
+    /// visited, so nothing outside the managed directories is touched and a link loop ends.
+    fn walk_dir<F>(
+        &self,
+        dir: &Path,
+        stats: &mut CleanupStats,
+        visit: &mut F,
+    ) -> Result<(), RemoveFilesError>
+    where
+        F: FnMut(&Path, &Path, &fs::Metadata, SystemTime, &mut CleanupStats),
+    {
+        let paths = fs::read_dir(dir).map_err(|e| {
+            RemoveFilesError::new(
+                dir,
+                format!(
+                    "An error occurred in reading the directory and the cleanup file failed: {}",
+                    e
+                ),
+            )
+        })?;
+
+        for path in paths.flatten().map(|e| e.path()) {
+            stats.scanned += 1;
+            let (metadata, modified) = match fs::symlink_metadata(&path)
+                .and_then(|m| m.modified().map(|t| (m, t)))
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    stats.errors += 1;
+                    self.error_handler.handle_error(RemoveFilesError::new(
+                        dir,
+                        format!("An error occurred in getting file modified time and the cleanup file failed: {}", e),
+                    ));
+                    continue;
+                }
+            };
+            if metadata.is_dir() {
+                if self.recursive {
+                    if let Err(e) = self.walk_dir(&path, stats, visit) {
+                        stats.errors += 1;
+                        self.error_handler.handle_error(e);
+                    }
+                }
+                continue;
+            }
+            if metadata.file_type().is_symlink() {
+                // a dangling link is still reported, its target is never touched
+                if let Err(e) = fs::metadata(&path) {
+                    stats.errors += 1;
+                    self.error_handler.handle_error(RemoveFilesError::new(
+                        dir,
+                        format!("An error occurred in getting file modified time and the cleanup file failed: {}", e),
+                    ));
+                }
+                continue;
+            }
+            visit(dir, &path, &metadata, modified, stats);
+        }
+        Ok(())
+    }
+
+    /// Clean up files in the specified `self.dir` that have been modified more than
+    /// a specified number of `self.days` ago.
+    ///
+    /// ```rust,ignore
+    /// // The parameter `cron_expression` default is `0 0 0 * * * *`.
+    /// // The parameter `cron_expression` sample: 0 15 6,8,10 * Mar,Jun Fri 2017
+    /// // means Run at second 0 of the 15th minute of the 6th, 8th, and 10th hour of any day in March
+    /// // and June that is a Friday of the year 2017.
+    /// // More information about `cron_expression` parameter see
+    /// // https://docs.rs/job_scheduler/latest/job_scheduler/
+    ///
+    /// let handle = schedule_cleanup_log_files("/opt/logs/apps/", 30, None);
+    /// // on graceful termination
+    /// handle.shutdown().await?;
+    /// ```
+    ///
+    /// The job keeps running if the returned handle is dropped.
+    pub async fn schedule_cleanup_log_files(self) -> Result<LogCleanerHandle, RemoveFilesError> {
+        let sched = JobScheduler::new().await?;
+        self.add_to_scheduler(&sched).await?;
+        sched.start().await?;
+        Ok(LogCleanerHandle { sched })
+    }
+
+    /// Register the cleanup job onto an existing `sched` and return the job id, so a host app
+    /// can own one [`JobScheduler`] for all of its periodic work.
+    ///
+    /// Unlike [`LogCleaner::schedule_cleanup_log_files`] the scheduler is not started here,
+    /// starting, stopping and removing the job (with the returned id) are left to the caller.
+    ///
+    /// ```rust,ignore
+    /// let sched = JobScheduler::new().await?;
+    /// cleaner.add_to_scheduler(&sched).await?;
+    /// sched.add(other_job).await?;
+    /// sched.start().await?;
+    /// ```
+    pub async fn add_to_scheduler(self, sched: &JobScheduler) -> Result<Uuid, RemoveFilesError> {
+        let cleaner = self.clone();
+        let cron = self
+            .clone()
+            .cron_expression
+            .unwrap_or(DEFAULT_CLEANUP_CRON.to_string());
+        let job = Job::new_async(cron.as_str(), move |uuid, mut l| {
+            let cleaner = self.clone();
+            Box::pin(async move {
+                // the file system calls block
+                let _ = tokio::task::spawn_blocking(move || cleaner.run_cleanup()).await;
+                let next_tick = l.next_tick_for_job(uuid).await;
+                if let Ok(Some(ts)) = next_tick {
+                    tokio::time::sleep(tokio::time::Duration::from_secs(
+                        (ts - Utc::now()).num_seconds() as u64,
+                    ))
+                    .await
+                }
+            })
+        })?;
+        let id = sched.add(job).await?;
+        if cleaner.run_on_start {
+            let dir = cleaner.dir.as_ref().to_path_buf();
+            tokio::task::spawn_blocking(move || cleaner.run_cleanup())
+                .await
+                .map_err(|e| RemoveFilesError::new(dir, format!("cleanup task failed: {}", e)))?;
+        }
+        Ok(id)
+    }
+
+    /// [`LogCleaner::cleanup_files_immediately`] logging the stats, errors go to the handler
+    fn run_cleanup(&self) {
+        match self.cleanup_files_immediately() {
+            Ok(stats) => info!(
+                "log cleanup finished, dirs: {:?}, scanned: {}, deleted: {}, bytes_freed: {}, errors: {}",
+                self.dirs(),
+                stats.scanned,
+                stats.deleted,
+                stats.bytes_freed,
+                stats.errors
+            ),
+            Err(e) => self.error_handler.handle_error(e),
+        };
+    }
+}
+
+/// return the file [`init_logger`] is writing for `bin_name` in `dir`, `{bin_name}.log` if it
+/// exists or else the newest `{bin_name}.log.*` rotation
+fn active_log_file(dir: &Path, bin_name: &str) -> Option<PathBuf> {
+    let name = format!("{}.log", bin_name);
+    if dir.join(&name).is_file() {
+        return Some(dir.join(name));
+    }
+    let prefix = format!("{}.", name);
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|n| n.starts_with(&prefix))
+        .max()
+        .map(|n| dir.join(n))
+}
+
+/// Returned by [`LogCleaner::schedule_cleanup_log_files`] to stop the scheduled cleanup.
+pub struct LogCleanerHandle {
+    sched: JobScheduler,
+}
+
+impl LogCleanerHandle {
+    /// Stop the scheduler, no further cleanup runs after this returns.
+    pub async fn shutdown(mut self) -> Result<(), RemoveFilesError> {
+        self.sched.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// return the most verbose level enabled by the filter behind `handle`, `OFF` if the
+/// subscriber of `handle` is gone
+pub fn current_level(handle: &LogHandle) -> filter::LevelFilter {
+    current_levels(handle)
+        .into_iter()
+        .map(|(_, level)| level)
+        .max()
+        .unwrap_or(filter::LevelFilter::OFF)
+}
+
+/// return the level of every target of the filter behind `handle`, the default level of
+/// targets not listed has an empty target
+pub fn current_levels(handle: &LogHandle) -> Vec<(String, filter::LevelFilter)> {
+    handle
+        .with_current(|targets| {
+            let mut levels: Vec<_> = targets
+                .iter()
+                .map(|(target, level)| (target.to_string(), level))
+                .collect();
+            if let Some(level) = targets.default_level() {
+                levels.push((String::new(), level));
+            }
+            levels
+        })
+        .unwrap_or_default()
+}
+
+/// return the level named `level`, one of `off/error/warn/info/debug/trace` in any case and
+/// surrounding whitespace, None otherwise
+pub fn parse_level(level: &str) -> Option<filter::LevelFilter> {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "off" => Some(filter::LevelFilter::OFF),
+        "error" => Some(filter::LevelFilter::ERROR),
+        "warn" => Some(filter::LevelFilter::WARN),
+        "info" => Some(filter::LevelFilter::INFO),
+        "debug" => Some(filter::LevelFilter::DEBUG),
+        "trace" => Some(filter::LevelFilter::TRACE),
+        _ => None,
+    }
+}
+
+/// Set every target of the filter behind `handle` to the level in env var `var` like `debug` or
+/// `warn`, return the new level. Meant to run on SIGHUP so operators change the verbosity without
+/// a restart.
+///
+/// ```rust,ignore
+/// let mut hangup = signal(SignalKind::hangup())?;
+/// while hangup.recv().await.is_some() {
+///     try_or_log!(reload_level_from_env(&handle, "LOG_LEVEL"), "reload log level");
+/// }
+/// ```
+pub fn reload_level_from_env(
+    handle: &LogHandle,
+    var: &str,
+) -> Result<filter::LevelFilter, ConfigError> {
+    let val = env::var(var).map_err(|e| ConfigError::Invalid {
+        key: var.to_string(),
+        details: e.to_string(),
+    })?;
+    let level = parse_level(&val).ok_or_else(|| ConfigError::Invalid {
+        key: var.to_string(),
+        details: format!(
+            "invalid level {:?}, expected one of off, error, warn, info, debug, trace",
+            val
+        ),
+    })?;
+    handle
+        .modify(|targets| {
+            let mut reloaded = targets.iter().fold(Targets::new(), |t, (target, _)| {
+                t.with_target(target, level)
+            });
+            if targets.default_level().is_some() {
+                reloaded = reloaded.with_default(level);
+            }
+            *targets = reloaded;
+        })
+        .map_err(|e| ConfigError::Load(format!("reload log level failed: {}", e)))?;
+    info!("log level reloaded from {}: {}", var, level);
+    Ok(level)
+}
+
+/// Raise every target of the filter behind `handle` to at least DEBUG until the returned guard
+/// is dropped, for troubleshooting one operation without leaving verbose logging on.
+///
+/// ```rust,ignore
+/// {
+///     let _debug = debug_scope(&handle);
+///     sync_orders().await?;
+/// } // back to the previous levels
+/// ```
+pub fn debug_scope(handle: &LogHandle) -> LevelGuard {
+    level_scope(handle, filter::LevelFilter::DEBUG)
+}
+
+/// [`debug_scope`] with any `level`, targets already more verbose keep their level.
+pub fn level_scope(handle: &LogHandle, level: filter::LevelFilter) -> LevelGuard {
+    let previous = handle.clone_current();
+    if let Some(previous) = &previous {
+        let mut raised = previous
+            .iter()
+            .fold(Targets::new(), |t, (target, current)| {
+                t.with_target(target, current.max(level))
+            });
+        if let Some(default) = previous.default_level() {
+            raised = raised.with_default(default.max(level));
+        }
+        if let Err(e) = handle.reload(raised) {
+            warn!("raise log level failed: {}", e);
+        }
+    }
+    LevelGuard {
+        handle: handle.clone(),
+        previous,
+    }
+}
+
+/// Restores the filter behind the handle of [`debug_scope`] or [`level_scope`] on drop.
+#[must_use = "the level is restored when the guard is dropped"]
+pub struct LevelGuard {
+    handle: LogHandle,
+    previous: Option<Targets>,
+}
+
+impl Drop for LevelGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            // fails only when the subscriber is gone, nothing left to restore
+            let _ = self.handle.reload(previous);
+        }
+    }
+}
+
+#[allow(unused, unreachable_code)]
+pub fn change_debug(handle: &LogHandle, debug: &str) -> bool {
+    // TODO: change_debug
+    panic!("TODO: ");
+    let base_filter = filter::Targets::new().with_target("foo", filter::LevelFilter::DEBUG);
+    handle.modify(|filter| *filter = base_filter);
+    true
+}
+
+pub fn log_path(log_path: Option<&str>, env_log_path_key: Option<&str>) -> PathBuf {
+    if debug_mode() {
+        let dir = env::temp_dir();
+        debug!(
+            "log will be saved to temporary directory: {}",
+            dir.display()
+        );
+        return dir;
+    }
+
+    // log path from param is first if it have been set
+    if log_path.is_some() {
+        return PathBuf::from(log_path.unwp().trim());
+    }
+
+    // default log path
+    let log_path = DEFAULT_LOG_PATH;
+    if env_log_path_key.is_some() {
+        let env_log_path = env::var(env_log_path_key.unwp());
+        match env_log_path {
+            Ok(env_log_path) => return PathBuf::from(env_log_path),
+            Err(_) => warn!(
+                "{} is not set, use default log path: {}",
+                env_log_path_key.unwp(),
+                log_path
+            ),
+        }
+    };
+    PathBuf::from(log_path)
+}
+
+/// Env var turning the log files of [`init_logger`] and [`LoggerBuilder`] off with `off`,
+/// `false`, `no` or `0`, independently of [`debug_mode`], see [`LoggerBuilder::file`].
+pub const FILE_LOG_ENV: &str = "BUSYLIB_FILE_LOG";
+
+/// Default of [`log_path`], the logger writes to [`platform_log_path`] instead when it is not
+/// writable.
+pub const DEFAULT_LOG_PATH: &str = "/opt/logs/apps/";
+
+/// Platform-appropriate log directory for the current user, `$XDG_STATE_HOME/logs`
+/// (`~/.local/state/logs`) on Linux, `%LOCALAPPDATA%\logs` on Windows and
+/// `~/Library/Application Support/logs` on macOS, the temporary directory as last resort.
+pub fn platform_log_path() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(env::temp_dir)
+        .join("logs")
+}
+
+/// return `dir` if it is writable, else [`platform_log_path`]
+fn writable_or_fallback(dir: &Path) -> PathBuf {
+    if is_writable(dir) {
+        dir.to_path_buf()
+    } else {
+        platform_log_path()
+    }
+}
+
+/// Whether files can be created in `dir`, or in its nearest existing ancestor if `dir` does not
+/// exist yet, without creating anything but a short-lived probe file.
+fn is_writable(dir: &Path) -> bool {
+    let Some(existing) = dir.ancestors().find(|p| p.exists()) else {
+        return false;
+    };
+    let probe = existing.join(format!(".busylib_probe_{}", std::process::id()));
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod logger_test {
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+    use std::{env, fs};
+
+    use crate::errors::RemoveFilesError;
+    use chrono::{DateTime, TimeZone, Utc};
+    use log::{debug, info};
+    use tokio_cron_scheduler::JobScheduler;
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::logger::{
+        capture, current_level, current_levels, json_file_layer, log_path, parse_log_file,
+        platform_log_path, tail_file, tail_log_in, writable_or_fallback, BuiltLogger,
+        CaptureWriter, CleanupStats, LogCleaner, LogCleanerErrorHandler, LogHandle, LogTimer,
+        LoggingErrorHandler, NoopErrorHandler, TenantFileLayer,
+    };
+    use crate::prelude::EnhancedUnwrap;
+
+    #[derive(Clone)]
+    struct MyLoggerErrorHandler;
+
+    // define custom error handler and implement LogCleanerErrorHandler trait in application code
+    impl LogCleanerErrorHandler for MyLoggerErrorHandler {
+        fn handle_error(&self, error: RemoveFilesError) {
+            // put custom error handling logic here
+            dbg!("handling error: {:?}", error);
+        }
+    }
+
+    #[test]
+    fn test_delete_log_files() {
+        let cleaner = LogCleaner {
+            dir: "/opt/logs/apps/",
+            days: 30,
+            cron_expression: None,
+            error_handler: MyLoggerErrorHandler,
+            extra_dirs: Vec::new(),
+            clock: Utc::now,
+            recursive: false,
+            active_log: None,
+            compression: None,
+            run_on_start: false,
+        };
+        if let Err(e) = cleaner.cleanup_files_immediately() {
+            panic!("test_delete_log_files failed, error: {}", e);
+        }
+    }
+
+    fn seeded_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("busylib_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwp();
+        dir
+    }
+
+    fn seed_file(dir: &std::path::Path, name: &str, content: &str, days_ago: u64) {
+        let path = dir.join(name);
+        fs::write(&path, content).unwp();
+        let modified = SystemTime::now() - Duration::from_secs(days_ago * 24 * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwp()
+            .set_modified(modified)
+            .unwp();
+    }
+
+    #[test]
+    fn test_cleanup_stats() {
+        let dir = seeded_dir("cleanup_stats");
+        seed_file(&dir, "old.log", "0123456789", 40);
+        seed_file(&dir, "older.log", "01234", 60);
+        seed_file(&dir, "new.log", "0123456789", 1);
+
+        let cleaner = LogCleaner::new(dir.clone(), 30, None, MyLoggerErrorHandler).unwp();
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!(
+            stats,
+            CleanupStats {
+                scanned: 3,
+                deleted: 2,
+                bytes_freed: 15,
+                compressed: 0,
+                errors: 0,
+            }
+        );
+        assert!(dir.join("new.log").exists());
+        assert!(!dir.join("old.log").exists());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = seeded_dir("disk_usage");
+        seed_file(&dir, "old.log", "0123456789", 40);
+        seed_file(&dir, "new.log", "01234", 1);
+        fs::create_dir(dir.join("nested")).unwp();
+        seed_file(&dir.join("nested"), "nested.log", "012", 2);
+
+        let cleaner = LogCleaner::new(dir.clone(), 30, None, MyLoggerErrorHandler).unwp();
+        let usage = cleaner.disk_usage().unwp();
+        assert_eq!(usage.total_bytes, 15);
+        assert_eq!(usage.files, 2);
+        assert!(usage.oldest.unwp() < usage.newest.unwp());
+        assert!(dir.join("old.log").exists());
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .recursive(true)
+            .build()
+            .unwp();
+        let usage = cleaner.disk_usage().unwp();
+        assert_eq!(usage.total_bytes, 18);
+        assert_eq!(usage.files, 3);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_list_files() {
+        let dir = seeded_dir("list_files");
+        seed_file(&dir, "new.log", "01234", 1);
+        seed_file(&dir, "old.log", "0123456789", 40);
+        seed_file(&dir, "today.log", "0", 0);
+        fs::create_dir(dir.join("nested")).unwp();
+        seed_file(&dir.join("nested"), "nested.log", "012", 2);
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .recursive(true)
+            .build()
+            .unwp();
+        let files = cleaner.list_files().unwp();
+        let listed: Vec<_> = files
+            .iter()
+            .map(|f| {
+                (
+                    f.path.strip_prefix(&dir).unwp().to_path_buf(),
+                    f.size,
+                    f.age_days,
+                )
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                (std::path::PathBuf::from("old.log"), 10, 40),
+                (std::path::PathBuf::from("nested/nested.log"), 3, 2),
+                (std::path::PathBuf::from("new.log"), 5, 1),
+                (std::path::PathBuf::from("today.log"), 1, 0),
+            ]
+        );
+        assert!(files.windows(2).all(|w| w[0].modified <= w[1].modified));
+
+        // without recursion, and nothing is deleted
+        let cleaner = LogCleaner::builder(dir.clone()).days(5).build().unwp();
+        assert_eq!(cleaner.list_files().unwp().len(), 3);
+        assert!(dir.join("old.log").exists());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_cleanup_multiple_dirs() {
+        let app = seeded_dir("multi_app");
+        let gc = seeded_dir("multi_gc");
+        seed_file(&app, "app.log", "app", 10);
+        seed_file(&gc, "gc.log", "gc", 10);
+
+        let cleaner = LogCleaner::new(app.clone(), 30, None, MyLoggerErrorHandler)
+            .unwp()
+            .with_dir(&gc, 5);
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!(stats.scanned, 2);
+        assert_eq!(stats.deleted, 1);
+        assert!(app.join("app.log").exists());
+        assert!(!gc.join("gc.log").exists());
+
+        // an unreadable extra directory does not stop the others
+        seed_file(&gc, "gc.log", "gc", 10);
+        let missing = env::temp_dir().join("busylib_multi_missing_dir");
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let received = errors.clone();
+        let cleaner = LogCleaner::new(app.clone(), 30, None, move |e: RemoveFilesError| {
+            received
+                .lock()
+                .unwp()
+                .push(e.dir().map(|d| d.to_path_buf()))
+        })
+        .unwp()
+        .with_dir(&missing, 1)
+        .with_dir(&gc, 5);
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.deleted, 1);
+        assert!(!gc.join("gc.log").exists());
+        assert_eq!(*errors.lock().unwp(), [Some(missing.clone())]);
+
+        fs::remove_dir_all(&app).unwp();
+        fs::remove_dir_all(&gc).unwp();
+    }
+
+    #[test]
+    fn test_invalid_cron() {
+        let err = LogCleaner::new(
+            "/opt/logs/apps/",
+            30,
+            Some("not a cron".to_string()),
+            MyLoggerErrorHandler,
+        )
+        .err()
+        .unwp();
+        assert!(err
+            .to_string()
+            .contains("invalid cron expression `not a cron`"));
+
+        assert!(LogCleaner::new("/opt/logs/apps/", 30, None, MyLoggerErrorHandler).is_ok());
+    }
+
+    #[test]
+    fn test_cleaner_builder() {
+        let dir = seeded_dir("builder");
+        fs::create_dir_all(dir.join("nested")).unwp();
+        seed_file(&dir, "app.log", "app", 10);
+        seed_file(&dir.join("nested"), "gc.log", "gc", 10);
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .days(5)
+            .cron("0 0 3 * * * *")
+            .error_handler(MyLoggerErrorHandler)
+            .build()
+            .unwp();
+        assert_eq!(cleaner.cron_expression.as_deref(), Some("0 0 3 * * * *"));
+        assert_eq!(cleaner.cleanup_files_immediately().unwp().deleted, 1);
+        assert!(dir.join("nested").join("gc.log").exists());
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .days(5)
+            .recursive(true)
+            .build()
+            .unwp();
+        assert_eq!(cleaner.cleanup_files_immediately().unwp().deleted, 1);
+        assert!(!dir.join("nested").join("gc.log").exists());
+
+        assert!(LogCleaner::builder(dir.clone())
+            .cron("garbage")
+            .build()
+            .is_err());
+        assert!(LogCleaner::builder(dir.clone()).days(-1).build().is_err());
+        assert_eq!(LogCleaner::builder(dir.clone()).build().unwp().days, 30);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recursive_skips_symlinks() {
+        let dir = seeded_dir("symlinks");
+        let outside = seeded_dir("symlinks_outside");
+        seed_file(&dir, "old.log", "old", 40);
+        seed_file(&outside, "outside.log", "outside", 40);
+        std::os::unix::fs::symlink(&outside, dir.join("outside_dir")).unwp();
+        std::os::unix::fs::symlink(outside.join("outside.log"), dir.join("outside.log")).unwp();
+        // a loop back to the managed dir
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwp();
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .recursive(true)
+            .error_handler(MyLoggerErrorHandler)
+            .build()
+            .unwp();
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!(stats.deleted, 1);
+        assert_eq!(stats.errors, 0);
+        assert!(!dir.join("old.log").exists());
+        assert!(outside.join("outside.log").exists());
+        assert!(fs::symlink_metadata(dir.join("outside.log")).is_ok());
+        fs::remove_dir_all(&dir).unwp();
+        fs::remove_dir_all(&outside).unwp();
+    }
+
+    #[test]
+    fn test_provided_error_handlers() {
+        let dir = seeded_dir("provided_handlers");
+        seed_file(&dir, "app.log", "app", 10);
+
+        let cleaner: LogCleaner<_, LoggingErrorHandler> =
+            LogCleaner::builder(dir.clone()).days(5).build().unwp();
+        assert_eq!(cleaner.cleanup_files_immediately().unwp().deleted, 1);
+        let missing = env::temp_dir().join("busylib_provided_handlers_missing");
+        let err = LogCleaner::builder(missing.clone())
+            .build()
+            .unwp()
+            .cleanup_files_immediately()
+            .unwrap_err();
+        LoggingErrorHandler.handle_error(err);
+
+        let cleaner = LogCleaner::new(dir.clone(), 5, None, NoopErrorHandler).unwp();
+        assert_eq!(cleaner.cleanup_files_immediately().unwp().deleted, 0);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_closure_error_handler() {
+        let dir = seeded_dir("closure_handler");
+        std::os::unix::fs::symlink(dir.join("missing.log"), dir.join("dangling.log")).unwp();
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let counter = errors.clone();
+        let cleaner = LogCleaner::new(dir.clone(), 30, None, move |e: RemoveFilesError| {
+            assert!(e.dir().is_some());
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwp();
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!(stats.errors, 1);
+        assert_eq!(errors.load(Ordering::SeqCst), 1);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_skip_active_log() {
+        let dir = seeded_dir("skip_active_log");
+        // the current rotation is old too when the app has not logged for a while
+        seed_file(&dir, "app.log.2023-08-01", "0123456789", 60);
+        seed_file(&dir, "app.log.2023-08-02", "0123456789", 50);
+        seed_file(&dir, "other.log.2023-08-02", "0123456789", 50);
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .days(30)
+            .skip_active_log("app")
+            .build()
+            .unwp();
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!(stats.deleted, 2);
+        assert!(dir.join("app.log.2023-08-02").exists());
+        assert!(!dir.join("app.log.2023-08-01").exists());
+        assert!(!dir.join("other.log.2023-08-02").exists());
+
+        // an unrotated `{bin_name}.log` is the active one
+        seed_file(&dir, "app.log", "0123456789", 50);
+        cleaner.cleanup_files_immediately().unwp();
+        assert!(dir.join("app.log").exists());
+        assert!(!dir.join("app.log.2023-08-02").exists());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cleanup_continues_past_unreadable_entry() {
+        let dir = seeded_dir("unreadable_entry");
+        for i in 0..10 {
+            seed_file(&dir, &format!("old{}.log", i), "0123456789", 40);
+        }
+        // metadata of a dangling symlink can not be read
+        std::os::unix::fs::symlink(dir.join("missing.log"), dir.join("old5.log.link")).unwp();
+
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let received = errors.clone();
+        let cleaner = LogCleaner::new(dir.clone(), 30, None, move |e: RemoveFilesError| {
+            received.lock().unwp().push(e.to_string())
+        })
+        .unwp();
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!(
+            stats,
+            CleanupStats {
+                scanned: 11,
+                deleted: 10,
+                bytes_freed: 100,
+                compressed: 0,
+                errors: 1,
+            }
+        );
+        let errors = errors.lock().unwp();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("getting file modified time"));
+        assert_eq!(fs::read_dir(&dir).unwp().count(), 1);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    fn fixed_clock() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 9, 1, 0, 0, 0).single().unwp()
+    }
+
+    fn forty_days_later() -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::days(40)
+    }
+
+    #[test]
+    fn test_next_run() {
+        let next_run = |cron: Option<&str>| {
+            LogCleaner::new(
+                "/tmp",
+                30,
+                cron.map(|c| c.to_string()),
+                MyLoggerErrorHandler,
+            )
+            .unwp()
+            .with_clock(fixed_clock)
+            .next_run()
+        };
+        // fixed_clock is 2023-09-01 00:00:00, the run at that instant is not the next one
+        assert_eq!(
+            next_run(None),
+            Utc.with_ymd_and_hms(2023, 9, 2, 0, 0, 0).single()
+        );
+        assert_eq!(
+            next_run(Some("0 15 6,8,10 * * Fri *")),
+            Utc.with_ymd_and_hms(2023, 9, 1, 6, 15, 0).single()
+        );
+        assert_eq!(next_run(Some("0 0 0 1 1 * 2020")), None);
+    }
+
+    #[test]
+    fn test_cleanup_with_fake_clock() {
+        let dir = seeded_dir("fake_clock");
+        // both files are fresh on the real clock
+        seed_file(&dir, "a.log", "a", 0);
+        seed_file(&dir, "b.log", "b", 0);
+        let mtime: SystemTime = Utc
+            .with_ymd_and_hms(2023, 7, 1, 0, 0, 0)
+            .single()
+            .unwp()
+            .into();
+        fs::File::options()
+            .write(true)
+            .open(dir.join("a.log"))
+            .unwp()
+            .set_modified(mtime)
+            .unwp();
+
+        let cleaner = LogCleaner::new(dir.clone(), 30, None, MyLoggerErrorHandler)
+            .unwp()
+            .with_clock(fixed_clock);
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        // b.log is "in the future" relative to the fixed clock, so only a.log is removed
+        assert_eq!(stats.deleted, 1);
+        assert!(!dir.join("a.log").exists());
+        assert!(dir.join("b.log").exists());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[tokio::test]
+    async fn test_schedule_cleanup_log_files() {
+        let dir = seeded_dir("schedule_cleanup");
+        seed_file(&dir, "app.log", "app", 0);
+        let cleaner = LogCleaner {
+            dir: dir.clone(),
+            days: 30,
+            // execute once every second for testing
+            cron_expression: Some("* * * * * * *".to_string()),
+            error_handler: MyLoggerErrorHandler,
+            extra_dirs: Vec::new(),
+            clock: forty_days_later,
+            recursive: false,
+            active_log: None,
+            compression: None,
+            run_on_start: false,
+        };
+
+        if let Err(e) = cleaner.schedule_cleanup_log_files().await {
+            panic!("schedule_cleanup_log_files failed, error: {}", e)
+        }
+
+        for _ in 0..50 {
+            if !dir.join("app.log").exists() {
+                fs::remove_dir_all(&dir).unwp();
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("app.log should have been cleaned up by the scheduled job");
+    }
+
+    #[tokio::test]
+    async fn test_run_on_start() {
+        for run_on_start in [true, false] {
+            let dir = seeded_dir(&format!("run_on_start_{}", run_on_start));
+            seed_file(&dir, "old.log", "old", 40);
+            seed_file(&dir, "new.log", "new", 1);
+            let handle = LogCleaner::builder(dir.clone())
+                .days(30)
+                // not before the end of the test
+                .cron("0 0 0 1 1 * 2100")
+                .error_handler(MyLoggerErrorHandler)
+                .run_on_start(run_on_start)
+                .build()
+                .unwp()
+                .schedule_cleanup_log_files()
+                .await
+                .unwp();
+            assert_eq!(dir.join("old.log").exists(), !run_on_start);
+            assert!(dir.join("new.log").exists());
+            handle.shutdown().await.unwp();
+            fs::remove_dir_all(&dir).unwp();
+        }
+    }
+
+    static CLOCK_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_clock() -> DateTime<Utc> {
+        CLOCK_CALLS.fetch_add(1, Ordering::SeqCst);
+        Utc::now()
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_scheduled_cleanup() {
+        let dir = seeded_dir("shutdown_cleanup");
+        seed_file(&dir, "app.log", "app", 0);
+        let handle = LogCleaner::new(
+            dir.clone(),
+            30,
+            Some("* * * * * * *".to_string()),
+            MyLoggerErrorHandler,
+        )
+        .unwp()
+        .with_clock(counting_clock)
+        .schedule_cleanup_log_files()
+        .await
+        .unwp();
+
+        for _ in 0..50 {
+            if CLOCK_CALLS.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(CLOCK_CALLS.load(Ordering::SeqCst) > 0);
+
+        handle.shutdown().await.unwp();
+        // let a run that was already in flight finish
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let runs = CLOCK_CALLS.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert_eq!(CLOCK_CALLS.load(Ordering::SeqCst), runs);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[tokio::test]
+    async fn test_add_to_shared_scheduler() {
+        let app = seeded_dir("shared_app");
+        let gc = seeded_dir("shared_gc");
+        seed_file(&app, "app.log", "app", 0);
+        seed_file(&gc, "gc.log", "gc", 0);
+
+        let sched = JobScheduler::new().await.unwp();
+        for dir in [&app, &gc] {
+            LogCleaner::new(
+                dir.clone(),
+                30,
+                Some("* * * * * * *".to_string()),
+                MyLoggerErrorHandler,
+            )
+            .unwp()
+            .with_clock(forty_days_later)
+            .add_to_scheduler(&sched)
+            .await
+            .unwp();
+        }
+        sched.start().await.unwp();
+
+        for _ in 0..50 {
+            if !app.join("app.log").exists() && !gc.join("gc.log").exists() {
+                fs::remove_dir_all(&app).unwp();
+                fs::remove_dir_all(&gc).unwp();
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("both jobs should have run on the shared scheduler");
+    }
+
+    #[test]
+    fn test_get_log_path() {
+        let log_path_default = log_path(None, None);
+        assert_eq!(log_path_default.to_str().unwp(), "/opt/logs/apps/");
+
+        let log_path_from_param = log_path(Some("/a/b/c"), None);
+        assert_eq!(log_path_from_param.to_str().unwp(), "/a/b/c");
+
+        env::set_var("LOG_PATH", "/xx/xx");
+        let log_path_from_env = log_path(None, Some("LOG_PATH"));
+        assert_eq!(log_path_from_env.to_str().unwp(), "/xx/xx");
+    }
+
+    #[test]
+    fn test_parse_log_file() {
+        let dir = seeded_dir("parse_log_file");
+        let path = dir.join("app.log");
+        let file = fs::File::create(&path).unwp();
+        let subscriber = tracing_subscriber::registry()
+            .with(json_file_layer(Mutex::new(file), LogTimer::default()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(user = "foo", count = 3, "hello {}", "world");
+            tracing::warn!("second");
+        });
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwp()
+            .write_all(b"\nnot json\n")
+            .unwp();
+
+        let entries: Vec<_> = parse_log_file(&path).unwp().collect();
+        assert_eq!(entries.len(), 3);
+        let first = entries[0].as_ref().unwp();
+        assert_eq!(first.level, "INFO");
+        assert_eq!(first.message, "hello world");
+        assert_eq!(first.target, "busylib::logger::logger_test");
+        assert_eq!(first.fields["user"], "foo");
+        assert_eq!(first.fields["count"], 3);
+        assert!(chrono::DateTime::parse_from_rfc3339(&first.timestamp).is_ok());
+        assert_eq!(entries[1].as_ref().unwp().level, "WARN");
+        assert!(entries[2]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .starts_with("line 4:"));
+
+        assert!(parse_log_file(dir.join("missing.log")).is_err());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_tenant_file_layer() {
+        let dir = seeded_dir("tenant_files");
+        let subscriber =
+            tracing_subscriber::registry().with(TenantFileLayer::new(dir.clone(), "tenant_id"));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("no tenant");
+            for tenant in ["acme", "globex"] {
+                let _span = tracing::info_span!("request", tenant_id = tenant).entered();
+                let _inner = tracing::info_span!("db").entered();
+                tracing::info!(rows = 3, "query of {}", tenant);
+            }
+            let _span = tracing::info_span!("request", tenant_id = "../escape").entered();
+            tracing::warn!("sanitized");
+        });
+
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwp()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 3);
+        for (name, tenant) in names.iter().zip(["___escape", "acme", "globex"]) {
+            assert!(name.starts_with(&format!("tenant-{}.log.", tenant)));
+        }
+        let entries: Vec<_> = parse_log_file(dir.join(&names[1]))
+            .unwp()
+            .map(|e| e.unwp())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].message, "query of acme");
+        assert_eq!(entries[0].level, "INFO");
+        assert_eq!(entries[0].fields["rows"], 3);
+        assert!(fs::read_to_string(dir.join(&names[1]))
+            .unwp()
+            .contains(r#""tenant_id":"acme""#));
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_tenant_file_layer_max_tenants() {
+        let dir = seeded_dir("tenant_files_max");
+        let subscriber = tracing_subscriber::registry()
+            .with(TenantFileLayer::new(dir.clone(), "tenant_id").max_tenants(1));
+        tracing::subscriber::with_default(subscriber, || {
+            // the same file once sanitized
+            for tenant in ["acme", "globex", "acme", "a/b", "a_b"] {
+                let _span = tracing::info_span!("request", tenant_id = tenant).entered();
+                tracing::info!("request of {}", tenant);
+            }
+        });
+
+        let file = |tenant: &str| {
+            let name = fs::read_dir(&dir)
+                .unwp()
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .find(|name| name.starts_with(&format!("tenant-{}.log.", tenant)))
+                .unwp();
+            parse_log_file(dir.join(name))
+                .unwp()
+                .map(|e| e.unwp().message)
+                .collect::<Vec<_>>()
+        };
+        // reopened after being closed for globex
+        assert_eq!(file("acme"), ["request of acme", "request of acme"]);
+        assert_eq!(file("globex"), ["request of globex"]);
+        assert_eq!(file("a_b"), ["request of a/b", "request of a_b"]);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_tenant_file_layer_error_handler() {
+        let dir = seeded_dir("tenant_files_error");
+        seed_file(&dir, "file", "", 0);
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let received = errors.clone();
+        let layer = TenantFileLayer::new(dir.join("file"), "tenant_id").error_handler(
+            move |tenant: &str, e: std::io::Error| {
+                // logging from the handler does not come back to the layer
+                tracing::warn!("tenant log error: {}", e);
+                received.lock().unwp().push(tenant.to_string());
+            },
+        );
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            let _span = tracing::info_span!("request", tenant_id = "acme").entered();
+            tracing::info!("lost");
+        });
+        assert_eq!(*errors.lock().unwp(), ["acme"]);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_current_level() {
+        use tracing_subscriber::filter::{LevelFilter, Targets};
+        use tracing_subscriber::reload;
+
+        let (layer, handle): (_, LogHandle) = reload::Layer::new(
+            Targets::new()
+                .with_target("busylib", LevelFilter::INFO)
+                .with_target("hyper", LevelFilter::WARN),
+        );
+        assert_eq!(current_level(&handle), LevelFilter::INFO);
+        handle
+            .modify(|filter| *filter = filter.clone().with_target("busylib", LevelFilter::DEBUG))
+            .unwp();
+        assert_eq!(current_level(&handle), LevelFilter::DEBUG);
+        let mut levels = current_levels(&handle);
+        levels.sort();
+        assert_eq!(
+            levels,
+            vec![
+                ("busylib".to_string(), LevelFilter::DEBUG),
+                ("hyper".to_string(), LevelFilter::WARN),
+            ]
+        );
+
+        drop(layer);
+        assert_eq!(current_level(&handle), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn test_static_max_level() {
+        use tracing::level_filters::STATIC_MAX_LEVEL;
+        use tracing_subscriber::filter::LevelFilter;
+
+        use crate::logger::LoggerBuilder;
+
+        // `cargo test --release --features release-max-level-info` exercises the stripped build
+        if cfg!(all(
+            feature = "release-max-level-info",
+            not(debug_assertions)
+        )) {
+            assert_eq!(STATIC_MAX_LEVEL, LevelFilter::INFO);
+        }
+
+        let dir = seeded_dir("static_max_level");
+        let BuiltLogger {
+            subscriber: _subscriber,
+            guard: _guard,
+            handle,
+            ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .debug(true)
+            .build()
+            .unwp();
+        assert_eq!(
+            current_level(&handle),
+            LevelFilter::DEBUG.min(STATIC_MAX_LEVEL)
+        );
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_local_daily_appender() {
+        use std::sync::atomic::AtomicI64;
+
+        use time::OffsetDateTime;
+
+        use crate::logger::{log_offset, LocalDailyAppender};
+
+        static NOW: AtomicI64 = AtomicI64::new(0);
+        fn clock() -> OffsetDateTime {
+            OffsetDateTime::from_unix_timestamp(NOW.load(Ordering::SeqCst)).unwp()
+        }
+
+        let dir = seeded_dir("local_daily_appender");
+        // 2023-09-01T15:59:59Z is 23:59:59 on 2023-09-01 at +08:00
+        NOW.store(1693583999, Ordering::SeqCst);
+        let mut appender =
+            LocalDailyAppender::with_clock(&dir, "busylib.log", log_offset(), clock).unwp();
+        writeln!(appender, "before midnight").unwp();
+        // 2023-09-01T16:00:00Z is still 2023-09-01 in UTC but already 2023-09-02 locally
+        NOW.store(1693584000, Ordering::SeqCst);
+        writeln!(appender, "after midnight").unwp();
+        appender.flush().unwp();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwp();
+        assert_eq!(read("busylib.log.2023-09-01"), "before midnight\n");
+        assert_eq!(read("busylib.log.2023-09-02"), "after midnight\n");
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_size_rotating_appender() {
+        use crate::logger::{LoggerBuilder, SizeRotatingAppender};
+
+        let dir = seeded_dir("size_rotating_appender");
+        let mut appender = SizeRotatingAppender::new(&dir, "busylib.log", 100, 2).unwp();
+        for i in 0..10 {
+            // 30 bytes, 3 lines per file, in one write as writeln! splits them
+            let line = format!("line {:02} {}\n", i, "x".repeat(20));
+            appender.write_all(line.as_bytes()).unwp();
+        }
+        appender.flush().unwp();
+        let lines = |name: &str| -> Vec<String> {
+            fs::read_to_string(dir.join(name))
+                .unwp()
+                .lines()
+                .map(|l| l[..7].to_string())
+                .collect()
+        };
+        assert_eq!(lines("busylib.log"), ["line 09"]);
+        assert_eq!(lines("busylib.log.1"), ["line 06", "line 07", "line 08"]);
+        assert_eq!(lines("busylib.log.2"), ["line 03", "line 04", "line 05"]);
+        assert!(!dir.join("busylib.log.3").exists());
+
+        // an existing file counts towards the limit
+        drop(appender);
+        let mut appender = SizeRotatingAppender::new(&dir, "busylib.log", 100, 2).unwp();
+        for i in 10..13 {
+            let line = format!("line {:02} {}\n", i, "x".repeat(20));
+            appender.write_all(line.as_bytes()).unwp();
+        }
+        assert_eq!(lines("busylib.log"), ["line 12"]);
+        assert_eq!(lines("busylib.log.1"), ["line 09", "line 10", "line 11"]);
+        fs::remove_dir_all(&dir).unwp();
+
+        let dir = seeded_dir("size_rotating_logger");
+        let BuiltLogger {
+            subscriber, guard, ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .max_file_size(1024, 3)
+            .console_writer(std::io::sink)
+            .build()
+            .unwp();
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..100 {
+                tracing::info!(i, "rotate by size");
+            }
+        });
+        drop(guard);
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwp()
+            .map(|e| e.unwp().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "busylib.log",
+                "busylib.log.1",
+                "busylib.log.2",
+                "busylib.log.3"
+            ]
+        );
+        assert!(fs::metadata(dir.join("busylib.log.1")).unwp().len() <= 1024);
+        let last = tail_log_in(&dir, "busylib", 1).unwp().remove(0);
+        assert!(last.contains(r#""i":99"#), "{}", last);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_global_fields() {
+        use crate::logger::{LogFormat, LoggerBuilder};
+
+        for format in [LogFormat::Json, LogFormat::Logfmt] {
+            let dir = seeded_dir(&format!("global_fields_{:?}", format));
+            let console = Arc::new(Mutex::new(Vec::new()));
+            let received = console.clone();
+            let BuiltLogger {
+                subscriber, guard, ..
+            } = LoggerBuilder::new("busylib")
+                .log_directory(&dir)
+                .format(format)
+                .field("service", "foo")
+                .field("version", "1.2.3")
+                .field("env", "prod west")
+                .console_writer(move || CaptureWriter(received.clone()))
+                .build()
+                .unwp();
+            tracing::subscriber::with_default(subscriber, || {
+                let _span = tracing::info_span!("request", id = 7, path = "/a b").entered();
+                tracing::info!(user = "bar", "unrelated event");
+            });
+            drop(guard);
+
+            let line = tail_log_in(&dir, "busylib", 1).unwp().remove(0);
+            let console = String::from_utf8(console.lock().unwp().clone()).unwp();
+            assert!(console.contains("unrelated event"));
+            assert!(
+                console
+                    .trim_end()
+                    .ends_with(r#"service=foo version=1.2.3 env="prod west""#),
+                "{}",
+                console
+            );
+            match format {
+                LogFormat::Json => {
+                    let json: serde_json::Value = serde_json::from_str(&line).unwp();
+                    assert_eq!(json["service"], "foo");
+                    assert_eq!(json["version"], "1.2.3");
+                    assert_eq!(json["env"], "prod west");
+                    assert_eq!(json["fields"]["message"], "unrelated event");
+                    let entry: crate::logger::LogEntry = line.parse().unwp();
+                    assert_eq!(entry.message, "unrelated event");
+                }
+                LogFormat::Logfmt => {
+                    assert!(line.contains(r#"id=7 path="/a b" user=bar"#), "{}", line);
+                    assert!(line.ends_with(r#"service=foo version=1.2.3 env="prod west""#));
+                }
+            }
+            fs::remove_dir_all(&dir).unwp();
+        }
+    }
+
+    #[test]
+    fn test_logger_backpressure() {
+        use crate::logger::LoggerBuilder;
+
+        const LINES: usize = 20_000;
+        for lossy in [true, false] {
+            let dir = seeded_dir(&format!("backpressure_{}", lossy));
+            let BuiltLogger {
+                subscriber,
+                guard,
+                dropped_lines,
+                ..
+            } = LoggerBuilder::new("busylib")
+                .log_directory(&dir)
+                .buffered_lines_limit(1)
+                .lossy(lossy)
+                .console_writer(std::io::sink)
+                .build()
+                .unwp();
+            tracing::subscriber::with_default(subscriber, || {
+                for i in 0..LINES {
+                    tracing::info!(i, "flood");
+                }
+            });
+            drop(guard);
+
+            let written = fs::read_dir(&dir)
+                .unwp()
+                .map(|entry| {
+                    fs::read_to_string(entry.unwp().path())
+                        .unwp()
+                        .lines()
+                        .count()
+                })
+                .sum::<usize>();
+            let dropped = dropped_lines.unwp().dropped_lines();
+            if lossy {
+                assert!(dropped > 0);
+                assert_eq!(written + dropped, LINES);
+            } else {
+                assert_eq!(dropped, 0);
+                assert_eq!(written, LINES);
+            }
+            fs::remove_dir_all(&dir).unwp();
+        }
+    }
+
+    #[test]
+    fn test_error_file() {
+        use crate::logger::LoggerBuilder;
+
+        let dir = seeded_dir("error_file");
+        let BuiltLogger {
+            subscriber,
+            guard,
+            directory,
+            ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .error_file(true)
+            .console_writer(std::io::sink)
+            .build()
+            .unwp();
+        let directory = directory.unwp();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("routine info");
+            tracing::warn!("disk almost full");
+            tracing::error!("payment failed");
+            tracing::error!(target: "hyper", "not logged at all");
+        });
+        drop(guard);
+
+        let main = tail_log_in(&dir, "busylib", 10).unwp();
+        assert_eq!(main.len(), 3);
+        let errors = tail_log_in(&dir, "busylib.error", 10).unwp();
+        assert_eq!(errors.len(), 2);
+        let messages: Vec<_> = errors
+            .iter()
+            .map(|line| line.parse::<crate::logger::LogEntry>().unwp().message)
+            .collect();
+        assert_eq!(messages, ["disk almost full", "payment failed"]);
+        assert!(main.iter().any(|line| line.contains("routine info")));
+        assert!(main.iter().any(|line| line.contains("payment failed")));
+
+        // both files move with the directory
+        let moved = seeded_dir("error_file_moved");
+        directory.set_log_directory(&moved).unwp();
+        assert_eq!(fs::read_dir(&moved).unwp().count(), 2);
+        fs::remove_dir_all(&dir).unwp();
+        fs::remove_dir_all(&moved).unwp();
+    }
+
+    #[test]
+    fn test_reload_filters_files() {
+        use crate::logger::LoggerBuilder;
+
+        let dir = seeded_dir("reload_files");
+        let BuiltLogger {
+            subscriber,
+            guard,
+            handle,
+            ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .error_file(true)
+            .console_writer(std::io::sink)
+            .build()
+            .unwp();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(target: "reloaded", "before reload");
+            handle
+                .modify(|f| *f = f.clone().with_target("reloaded", tracing::Level::DEBUG))
+                .unwp();
+            tracing::debug!(target: "reloaded", "after reload");
+            tracing::warn!(target: "reloaded", "warn after reload");
+        });
+        drop(guard);
+
+        let messages = |name| {
+            tail_log_in(&dir, name, 10)
+                .unwp()
+                .iter()
+                .map(|line| line.parse::<crate::logger::LogEntry>().unwp().message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages("busylib"), ["after reload", "warn after reload"]);
+        assert_eq!(messages("busylib.error"), ["warn after reload"]);
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_audit_targets() {
+        use crate::logger::LoggerBuilder;
+
+        let dir = seeded_dir("audit_targets");
+        let BuiltLogger {
+            subscriber,
+            guard: _guard,
+            ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .audit_targets(&["audit"])
+            .console_writer(std::io::sink)
+            .build()
+            .unwp();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("routine info");
+            tracing::debug!(target: "audit::user", user = "foo", "user deleted");
+            // on disk as soon as the call returns, without waiting for the writer thread
+            let audit = tail_log_in(&dir, "busylib.audit", 10).unwp();
+            assert_eq!(audit.len(), 1);
+            let entry = audit[0].parse::<crate::logger::LogEntry>().unwp();
+            assert_eq!(entry.message, "user deleted");
+            assert_eq!(entry.target, "audit::user");
+        });
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    const FILE_LOG_TEST_DIR: &str = "BUSYLIB_FILE_LOG_TEST_DIR";
+
+    /// the half of [`test_file_log_env_off`] logging with the env var set, run in a subprocess
+    /// as the env var would turn the files of the other tests off
+    #[test]
+    fn test_file_log_env_off_child() {
+        use crate::logger::LoggerBuilder;
+
+        let Ok(dir) = env::var(FILE_LOG_TEST_DIR) else {
+            return;
+        };
+        let BuiltLogger {
+            subscriber,
+            guard,
+            dropped_lines,
+            directory,
+            ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .error_file(true)
+            .console_writer(std::io::sink)
+            .build()
+            .unwp();
+        assert!(guard.is_none());
+        assert!(dropped_lines.is_none());
+        assert!(directory.is_none());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::error!("console only");
+        });
+    }
+
+    #[test]
+    fn test_file_log_env_off() {
+        use crate::logger::FILE_LOG_ENV;
+
+        let dir = env::temp_dir().join(format!("busylib_file_log_off_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let output = std::process::Command::new(env::current_exe().unwp())
+            .args([
+                "--exact",
+                "logger::logger_test::test_file_log_env_off_child",
+            ])
+            .env(FILE_LOG_ENV, "off")
+            .env(FILE_LOG_TEST_DIR, &dir)
+            .output()
+            .unwp();
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+        assert!(!dir.exists());
+    }
+
+    const INIT_ONCE_CHILD: &str = "BUSYLIB_INIT_ONCE_CHILD";
+
+    /// the half of [`test_init_logger_once_other_subscriber`] installing the subscribers, run in
+    /// a subprocess as they are global
+    #[test]
+    fn test_init_logger_once_other_subscriber_child() {
+        use tracing_subscriber::util::SubscriberInitExt;
+
+        let Ok(dir) = env::var(INIT_ONCE_CHILD) else {
+            return;
+        };
+        tracing_subscriber::registry().init();
+        let (guard, handle) =
+            super::init_logger_once("busylib", &[], false, Some(std::path::PathBuf::from(dir)));
+        assert!(guard.is_none());
+        assert!(handle.is_none());
+        assert!(super::init_logger_once("busylib", &[], false, None)
+            .1
+            .is_none());
+    }
+
+    #[test]
+    fn test_init_logger_once_other_subscriber() {
+        let dir = seeded_dir("init_once_other");
+        let output = std::process::Command::new(env::current_exe().unwp())
+            .args([
+                "--exact",
+                "logger::logger_test::test_init_logger_once_other_subscriber_child",
+                "--nocapture",
+            ])
+            .env(INIT_ONCE_CHILD, &dir)
+            .output()
+            .unwp();
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("init logger failed"));
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_set_log_directory() {
+        use crate::logger::LoggerBuilder;
+
+        let dir_a = seeded_dir("log_directory_a");
+        let dir_b = env::temp_dir().join(format!("busylib_log_directory_b_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir_b);
+        let BuiltLogger {
+            subscriber,
+            guard,
+            directory,
+            ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir_a)
+            .console_writer(std::io::sink)
+            .build()
+            .unwp();
+        let directory = directory.unwp();
+        assert_eq!(directory.log_directory(), dir_a);
+        let read_dir = |dir: &std::path::Path| -> String {
+            fs::read_dir(dir)
+                .unwp()
+                .map(|entry| fs::read_to_string(entry.unwp().path()).unwp())
+                .collect()
+        };
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("before the swap");
+            // wait for the writer thread, the lines still queued would land in B
+            let start = std::time::Instant::now();
+            while !read_dir(&dir_a).contains("before the swap") {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            directory.set_log_directory(&dir_b).unwp();
+            tracing::info!("after the swap");
+        });
+        drop(guard);
+
+        assert_eq!(directory.log_directory(), dir_b);
+        let a = read_dir(&dir_a);
+        let b = read_dir(&dir_b);
+        assert!(a.contains("before the swap") && !a.contains("after the swap"));
+        assert!(b.contains("after the swap") && !b.contains("before the swap"));
+
+        // the current directory is kept on error
+        let file = dir_b.join("not_a_directory");
+        fs::write(&file, "").unwp();
+        assert!(directory.set_log_directory(&file).is_err());
+        assert_eq!(directory.log_directory(), dir_b);
+        fs::remove_dir_all(&dir_a).unwp();
+        fs::remove_dir_all(&dir_b).unwp();
+    }
+
+    #[test]
+    fn test_logger_filter() {
+        use tracing_subscriber::filter::{LevelFilter, Targets};
+
+        use crate::logger::LoggerBuilder;
+
+        let dir = seeded_dir("logger_filter");
+        let console = Arc::new(Mutex::new(Vec::new()));
+        let received = console.clone();
+        let BuiltLogger {
+            subscriber,
+            guard,
+            handle,
+            ..
+        } = LoggerBuilder::new("busylib")
+            .debug(true)
+            .filter(
+                Targets::new()
+                    .with_target("busylib", LevelFilter::WARN)
+                    .with_target("my_app", LevelFilter::TRACE),
+            )
+            .log_directory(&dir)
+            .console_writer(move || CaptureWriter(received.clone()))
+            .build()
+            .unwp();
+        let mut levels = current_levels(&handle);
+        levels.sort();
+        assert_eq!(
+            levels,
+            vec![
+                ("busylib".to_string(), LevelFilter::WARN),
+                ("my_app".to_string(), LevelFilter::TRACE),
+            ]
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("busylib info");
+            tracing::warn!("busylib warn");
+            tracing::trace!(target: "my_app", "my_app trace");
+        });
+        drop(guard);
+
+        let console = String::from_utf8(console.lock().unwp().clone()).unwp();
+        let file = tail_log_in(&dir, "busylib", 10).unwp().join("\n");
+        for output in [console, file] {
+            assert!(!output.contains("busylib info"));
+            assert!(output.contains("busylib warn"));
+            assert!(output.contains("my_app trace"));
+        }
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_parse_level() {
+        use tracing_subscriber::filter::LevelFilter;
+
+        use crate::logger::parse_level;
+
+        for (input, expected) in [
+            ("off", Some(LevelFilter::OFF)),
+            ("error", Some(LevelFilter::ERROR)),
+            ("warn", Some(LevelFilter::WARN)),
+            ("info", Some(LevelFilter::INFO)),
+            ("debug", Some(LevelFilter::DEBUG)),
+            ("trace", Some(LevelFilter::TRACE)),
+            ("OFF", Some(LevelFilter::OFF)),
+            ("Warn", Some(LevelFilter::WARN)),
+            ("DeBuG", Some(LevelFilter::DEBUG)),
+            (" info\n", Some(LevelFilter::INFO)),
+            ("", None),
+            ("warning", None),
+            ("verbose", None),
+            ("3", None),
+            ("info,hyper=warn", None),
+        ] {
+            assert_eq!(parse_level(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_reload_level_from_env() {
+        use tracing_subscriber::filter::{LevelFilter, Targets};
+        use tracing_subscriber::reload;
+
+        use crate::logger::reload_level_from_env;
+
+        let var = "BUSYLIB_RELOAD_LOG_LEVEL";
+        let (layer, handle): (_, LogHandle) = reload::Layer::new(
+            Targets::new()
+                .with_target("busylib", LevelFilter::INFO)
+                .with_target("hyper", LevelFilter::WARN),
+        );
+        env::set_var(var, "debug");
+        assert_eq!(
+            reload_level_from_env(&handle, var).unwp(),
+            LevelFilter::DEBUG
+        );
+        let mut levels = current_levels(&handle);
+        levels.sort();
+        assert_eq!(
+            levels,
+            vec![
+                ("busylib".to_string(), LevelFilter::DEBUG),
+                ("hyper".to_string(), LevelFilter::DEBUG),
+            ]
+        );
+
+        // an invalid or missing value keeps the current level
+        env::set_var(var, "loud");
+        assert!(reload_level_from_env(&handle, var).is_err());
+        env::remove_var(var);
+        assert!(reload_level_from_env(&handle, var).is_err());
+        assert_eq!(current_level(&handle), LevelFilter::DEBUG);
+
+        env::set_var(var, "WARN");
+        assert_eq!(
+            reload_level_from_env(&handle, var).unwp(),
+            LevelFilter::WARN
+        );
+        assert_eq!(current_level(&handle), LevelFilter::WARN);
+
+        drop(layer);
+        assert!(reload_level_from_env(&handle, var).is_err());
+    }
+
+    #[test]
+    fn test_debug_scope() {
+        use tracing_subscriber::filter::{LevelFilter, Targets};
+        use tracing_subscriber::reload;
+
+        use crate::logger::{debug_scope, level_scope};
+
+        let (layer, handle): (_, LogHandle) = reload::Layer::new(
+            Targets::new()
+                .with_target("busylib", LevelFilter::INFO)
+                .with_target("hyper", LevelFilter::TRACE)
+                .with_default(LevelFilter::WARN),
+        );
+        let sorted = |handle: &LogHandle| {
+            let mut levels = current_levels(handle);
+            levels.sort();
+            levels
+        };
+        let before = sorted(&handle);
+        {
+            let _debug = debug_scope(&handle);
+            assert_eq!(
+                sorted(&handle),
+                vec![
+                    (String::new(), LevelFilter::DEBUG),
+                    ("busylib".to_string(), LevelFilter::DEBUG),
+                    ("hyper".to_string(), LevelFilter::TRACE),
+                ]
+            );
+            {
+                let _trace = level_scope(&handle, LevelFilter::TRACE);
+                assert_eq!(current_level(&handle), LevelFilter::TRACE);
+                assert!(sorted(&handle)
+                    .iter()
+                    .all(|(_, l)| *l == LevelFilter::TRACE));
+            }
+            assert_eq!(
+                sorted(&handle)[1],
+                ("busylib".to_string(), LevelFilter::DEBUG)
+            );
+        }
+        assert_eq!(sorted(&handle), before);
+
+        // the guard outliving the subscriber does nothing
+        let guard = debug_scope(&handle);
+        drop(layer);
+        drop(guard);
+    }
+
+    #[test]
+    fn test_compression() {
+        use std::io::Read;
+
+        use crate::logger::{Compression, CompressionAlgo};
+
+        let dir = seeded_dir("compression");
+        let content = "{\"message\":\"hello\"}\n".repeat(100);
+        seed_file(&dir, "busylib.log.2023-09-01", &content, 3);
+        seed_file(&dir, "busylib.log.2023-09-02", &content, 0);
+        seed_file(&dir, "busylib.log.2023-08-01.gz", "gz", 3);
+        seed_file(&dir, "busylib.log.2023-07-01", &content, 40);
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .compression(Compression {
+                algo: CompressionAlgo::Gzip,
+                level: 9,
+                ..Default::default()
+            })
+            .build()
+            .unwp();
+        let stats = cleaner.cleanup_files_immediately().unwp();
+        assert_eq!((stats.deleted, stats.compressed), (1, 1));
+
+        let compressed = dir.join("busylib.log.2023-09-01.gz");
+        assert!(!dir.join("busylib.log.2023-09-01").exists());
+        assert!(fs::metadata(&compressed).unwp().len() < content.len() as u64);
+        // the age is kept so the archive expires like the original
+        let age = SystemTime::now()
+            .duration_since(fs::metadata(&compressed).unwp().modified().unwp())
+            .unwp();
+        assert!(age > Duration::from_secs(2 * 24 * 60 * 60));
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(&compressed).unwp())
+            .read_to_string(&mut decompressed)
+            .unwp();
+        assert_eq!(decompressed, content);
+        // recent and already compressed files are left alone
+        assert_eq!(
+            fs::read_to_string(dir.join("busylib.log.2023-09-02")).unwp(),
+            content
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("busylib.log.2023-08-01.gz")).unwp(),
+            "gz"
+        );
+
+        assert!(LogCleaner::builder(dir.clone())
+            .compression(Compression {
+                algo: CompressionAlgo::Gzip,
+                level: 10,
+                ..Default::default()
+            })
+            .build()
+            .is_err());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_compression_min_size() {
+        use crate::logger::Compression;
+
+        let dir = seeded_dir("compression_min_size");
+        let content = "{\"message\":\"hello\"}\n".repeat(100);
+        seed_file(&dir, "busylib.log.2023-09-01", &content, 3);
+        seed_file(&dir, "busylib.log.2023-09-02", "{}\n", 3);
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .compression(Compression {
+                min_size_bytes: 1024,
+                ..Default::default()
+            })
+            .build()
+            .unwp();
+        assert_eq!(cleaner.cleanup_files_immediately().unwp().compressed, 1);
+        assert!(dir.join("busylib.log.2023-09-01.gz").exists());
+        assert!(!dir.join("busylib.log.2023-09-01").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("busylib.log.2023-09-02")).unwp(),
+            "{}\n"
+        );
+        assert!(!dir.join("busylib.log.2023-09-02.gz").exists());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_compression() {
+        use crate::logger::{Compression, CompressionAlgo};
+
+        let dir = seeded_dir("zstd_compression");
+        let content = "{\"message\":\"hello\"}\n".repeat(100);
+        seed_file(&dir, "busylib.log.2023-09-01", &content, 3);
+
+        let cleaner = LogCleaner::builder(dir.clone())
+            .compression(Compression {
+                algo: CompressionAlgo::Zstd,
+                level: 19,
+                ..Default::default()
+            })
+            .build()
+            .unwp();
+        assert_eq!(cleaner.cleanup_files_immediately().unwp().compressed, 1);
+        let compressed = fs::read(dir.join("busylib.log.2023-09-01.zst")).unwp();
+        assert_eq!(zstd::decode_all(&compressed[..]).unwp(), content.as_bytes());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_redact() {
+        use crate::logger::{LoggerBuilder, DEFAULT_REDACT_PATTERNS};
+
+        let dir = seeded_dir("redact");
+        let console = Arc::new(Mutex::new(Vec::new()));
+        let received = console.clone();
+        let BuiltLogger {
+            subscriber, guard, ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .redact(DEFAULT_REDACT_PATTERNS.iter().copied())
+            .redact([r"sk_live_[a-z0-9]+"])
+            .console_writer(move || CaptureWriter(received.clone()))
+            .build()
+            .unwp();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(
+                body = "{\"key\":\"sk_live_abc123\"}",
+                "call with Authorization: Bearer eyJhbGciOi.abc-123 for foo@example.com, card 4111 1111 1111 1111"
+            );
+        });
+        drop(guard);
+
+        let line = &tail_log_in(&dir, "busylib", 1).unwp()[0];
+        let console = String::from_utf8(console.lock().unwp().clone()).unwp();
+        for output in [line.as_str(), console.as_str()] {
+            assert!(output.contains("Authorization: *** for ***, card ***"));
+            assert!(!output.contains("eyJhbGciOi"));
+            assert!(!output.contains("sk_live_abc123"));
+        }
+        // the JSON line is still valid
+        let entry: crate::logger::LogEntry = line.parse().unwp();
+        assert_eq!(
+            entry.message,
+            "call with Authorization: *** for ***, card ***"
+        );
+
+        assert!(LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .redact(["("])
+            .build()
+            .is_err());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_tail_log() {
+        let dir = seeded_dir("tail_log");
+        let lines: Vec<_> = (0..2000).map(|i| format!("line {}", i)).collect();
+        seed_file(&dir, "app.log.2023-09-02", &(lines.join("\n") + "\n"), 0);
+        seed_file(&dir, "app.log.2023-09-01", "old\n", 1);
+        // left by a compression in progress, sorts after the plain file
+        seed_file(&dir, "app.log.2023-09-02.gz", "\x1f\u{8b}", 0);
+        seed_file(&dir, "short.log.2023-09-02", "first\nsecond", 0);
+
+        assert_eq!(
+            tail_log_in(&dir, "app", 3).unwp(),
+            vec!["line 1997", "line 1998", "line 1999"]
+        );
+        // more than one block away from the end
+        assert_eq!(tail_log_in(&dir, "app", 1500).unwp(), lines[500..].to_vec());
+        assert!(tail_log_in(&dir, "app", 0).unwp().is_empty());
+        // smaller than the window, without a trailing newline
+        assert_eq!(
+            tail_file(dir.join("short.log.2023-09-02"), 10).unwp(),
+            vec!["first", "second"]
+        );
+        assert!(tail_log_in(&dir, "missing", 3).is_err());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_capture() {
+        let logs = capture();
+        tracing::info!(user = "foo", "captured {}", 1);
+        // every level is captured
+        tracing::trace!("from trace");
+        assert!(logs.contains("captured 1"));
+        let entries = logs.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].level, "INFO");
+        assert_eq!(entries[0].fields["user"], "foo");
+        assert_eq!(entries[1].message, "from trace");
+        assert_eq!(logs.lines().len(), 2);
+
+        drop(logs);
+        let logs = capture();
+        assert!(logs.lines().is_empty());
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        use crate::logger::TimestampFormat;
+
+        let dir = seeded_dir("timestamp_format");
+        let timestamp = |format: TimestampFormat| {
+            let path = dir.join("app.log");
+            let file = fs::File::create(&path).unwp();
+            let layer = json_file_layer(Mutex::new(file), format.timer().unwp());
+            tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+                tracing::info!("hello");
+            });
+            let entry = parse_log_file(&path).unwp().next().unwp().unwp();
+            entry.timestamp
+        };
+
+        let rfc3339 = timestamp(TimestampFormat::default());
+        assert!(chrono::DateTime::parse_from_rfc3339(&rfc3339).is_ok());
+        let rfc2822 = timestamp(TimestampFormat::Rfc2822);
+        assert!(chrono::DateTime::parse_from_rfc2822(&rfc2822).is_ok());
+        assert!(rfc2822.ends_with("+0800"));
+        let millis: i64 = timestamp(TimestampFormat::UnixMillis).parse().unwp();
+        assert!((Utc::now().timestamp_millis() - millis).abs() < 10_000);
+        let custom = timestamp(TimestampFormat::Custom(
+            "[year]/[month]/[day] [hour]:[minute]".to_string(),
+        ));
+        assert!(chrono::NaiveDateTime::parse_from_str(&custom, "%Y/%m/%d %H:%M").is_ok());
+
+        assert!(TimestampFormat::Custom("[nope]".to_string())
+            .timer()
+            .is_err());
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_console_writer() {
+        use crate::logger::LoggerBuilder;
+
+        let dir = seeded_dir("console_writer");
+        let console = Arc::new(Mutex::new(Vec::new()));
+        let received = console.clone();
+        let BuiltLogger {
+            subscriber, guard, ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .stderr(true)
+            .console_writer(move || CaptureWriter(received.clone()))
+            .build()
+            .unwp();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("to the console");
+        });
+        drop(guard);
+
+        let console = String::from_utf8(console.lock().unwp().clone()).unwp();
+        assert!(console.contains("to the console"));
+        // the log file is written as usual
+        assert!(tail_log_in(&dir, "busylib", 1).unwp()[0].contains("to the console"));
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_flush_on_shutdown() {
+        use crate::logger::{flush_after, LoggerBuilder};
+
+        let dir = seeded_dir("flush_on_shutdown");
+        let BuiltLogger {
+            subscriber, guard, ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .build()
+            .unwp();
+        let default = tracing::subscriber::set_default(subscriber);
+        tracing::info!("last words");
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown = tokio::spawn(flush_after(
+            async {
+                rx.await.unwp();
+                // logged while draining
+                tracing::info!("drained");
+                42
+            },
+            guard,
+        ));
+        tokio::task::yield_now().await;
+        assert!(!shutdown.is_finished());
+        tx.send(()).unwrap();
+        assert_eq!(shutdown.await.unwp(), 42);
+        drop(default);
+
+        // the guard is dropped once the shutdown completes, the buffered lines are on disk
+        let lines = tail_log_in(&dir, "busylib", 10).unwp();
+        assert!(lines.iter().any(|line| line.contains("last words")));
+        assert!(lines.iter().any(|line| line.contains("drained")));
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_logfmt() {
+        use crate::logger::{LogFormat, LoggerBuilder};
+
+        let dir = seeded_dir("logfmt");
+        let console = Arc::new(Mutex::new(Vec::new()));
+        let received = console.clone();
+        let BuiltLogger {
+            subscriber, guard, ..
+        } = LoggerBuilder::new("busylib")
+            .log_directory(&dir)
+            .format(LogFormat::Logfmt)
+            .console_writer(move || CaptureWriter(received.clone()))
+            .build()
+            .unwp();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(user = "foo bar", count = 3, "say \"hi\"");
+        });
+        drop(guard);
+
+        let console = String::from_utf8(console.lock().unwp().clone()).unwp();
+        let line = tail_log_in(&dir, "busylib", 1).unwp().remove(0);
+        // same line on the console and in the file, up to the timestamp
+        assert_eq!(
+            console.trim_end().split_once(' ').unwp().1,
+            line.split_once(' ').unwp().1
+        );
+        assert!(line.starts_with("ts=20"));
+        assert!(line.ends_with(
+            " level=warn target=busylib::logger::logger_test msg=\"say \\\"hi\\\"\" user=\"foo bar\" count=3"
+        ));
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    #[test]
+    fn test_log_path_fallback() {
+        let dir = seeded_dir("log_path_fallback");
+        assert_eq!(writable_or_fallback(&dir), dir);
+        assert_eq!(writable_or_fallback(&dir.join("a/b")), dir.join("a/b"));
+
+        // a regular file can not hold log files
+        seed_file(&dir, "file", "", 0);
+        assert_eq!(
+            writable_or_fallback(&dir.join("file").join("logs")),
+            platform_log_path()
+        );
+
+        fs::remove_dir_all(&dir).unwp();
+    }
+
+    const PLATFORM_LOG_PATH_CHILD: &str = "BUSYLIB_PLATFORM_LOG_PATH_CHILD";
+
+    /// the half of [`test_platform_log_path`] reading `XDG_STATE_HOME`, run in a subprocess as
+    /// setting it would move the fallback of the other tests
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_platform_log_path_child() {
+        if env::var(PLATFORM_LOG_PATH_CHILD).is_err() {
+            return;
+        }
+        let state = std::path::PathBuf::from(env::var("XDG_STATE_HOME").unwp());
+        assert_eq!(platform_log_path(), state.join("logs"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_platform_log_path() {
+        let state = env::temp_dir().join(format!("busylib_state_{}", std::process::id()));
+        let output = std::process::Command::new(env::current_exe().unwp())
+            .args([
+                "--exact",
+                "logger::logger_test::test_platform_log_path_child",
+            ])
+            .env(PLATFORM_LOG_PATH_CHILD, "1")
+            .env("XDG_STATE_HOME", &state)
+            .output()
+            .unwp();
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+    }
+
+    #[test]
+    fn test_init_logger() {
+        let dir = seeded_dir("init_logger");
+        seed_file(&dir, "file", "", 0);
+        let err =
+            super::try_init_logger("busylib", &["busylib"], false, Some(dir.join("file/logs")))
+                .unwrap_err();
+        assert!(err.to_string().starts_with("create log directory"));
+
+        // the nested directory does not exist yet
+        let log_path = log_path(Some(dir.join("a/b").to_str().unwp()), None);
+        let (guard, handle) =
+            super::init_logger_once("busylib", &["busylib"], false, Some(log_path));
+        assert!(guard.is_some());
+        // later calls do not panic and share the handle of the first one
+        let (second_guard, second_handle) =
+            super::init_logger_once("other", &[], true, Some(dir.join("c")));
+        assert!(second_guard.is_none());
+        assert_eq!(
+            current_level(&second_handle.unwp()),
+            current_level(&handle.unwp())
+        );
+        assert!(!dir.join("c").exists());
+        debug!("test_init_logger - debug");
+        info!("test_init_logger - info, message: {}", "xxxadf");
+        drop(guard);
+
+        let log_file = fs::read_dir(dir.join("a/b"))
+            .unwp()
+            .flatten()
+            .find(|e| e.file_name().to_string_lossy().starts_with("busylib.log"))
+            .unwp();
+        let content = fs::read_to_string(log_file.path()).unwp();
+        assert!(content.contains("test_init_logger - info, message: xxxadf"));
+        assert!(!content.contains("test_init_logger - debug"));
+
+        // the cleanup never touches the file being written
+        fs::File::options()
+            .write(true)
+            .open(log_file.path())
+            .unwp()
+            .set_modified(SystemTime::now() - Duration::from_secs(40 * 24 * 60 * 60))
+            .unwp();
+        seed_file(&dir.join("a/b"), "busylib.log.2023-08-01", "old", 40);
+        let cleaner = LogCleaner::new(dir.join("a/b"), 30, None, NoopErrorHandler).unwp();
+        assert_eq!(cleaner.cleanup_files_immediately().unwp().deleted, 1);
+        assert!(log_file.path().exists());
+        assert!(
+            super::try_init_logger("busylib", &["busylib"], false, Some(dir.join("a/b"))).is_err()
+        );
+        fs::remove_dir_all(&dir).unwp();
     }
 }
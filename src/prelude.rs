@@ -1,6 +1,29 @@
-use std::{backtrace::Backtrace, fmt::Display};
+//! Commonly used items, `use busylib::prelude::*;` to bring them all in scope.
 
-use log::error;
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    error::Error,
+    fmt::Display,
+    panic::{self, AssertUnwindSafe},
+};
+
+use tracing::error;
+
+pub use crate::errors::BusyError;
+
+#[cfg(feature = "crypto")]
+pub use crate::crypto::{
+    decrypt_by_key, decrypt_by_key_with_default, decrypt_by_key_with_error, encrypt_by_key,
+};
+#[cfg(feature = "http")]
+pub use crate::errors::HttpError;
+#[cfg(feature = "logger")]
+pub use crate::errors::RemoveFilesError;
+#[cfg(feature = "crypto")]
+pub use crate::errors::{DecryptError, DecryptErrorKind};
+#[cfg(feature = "http")]
+pub use crate::http::{default_reqwest_client, ReqwestClient, ReqwestError};
 
 trait DisplayBackTrace {
     fn to_simple_string(&self) -> String;
@@ -32,6 +55,56 @@ pub trait EnhancedExpect<T, E: Display> {
     fn ex(self, msg: &str) -> T;
 }
 
+pub trait EnhancedContext<T> {
+    /// Wrap the error (or `None`) into a [`BusyError`] with `msg`, the non-panicking
+    /// counterpart of [`EnhancedExpect::ex`] to use with `?`.
+    fn context<C: Display>(self, msg: C) -> Result<T, BusyError>;
+
+    /// [`EnhancedContext::context`] with the message built only on error.
+    fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T, BusyError>;
+}
+
+pub trait EnhancedLog {
+    /// Log the error with `context` and a backtrace like [`try_or_log!`] and return `self`
+    /// unchanged, to keep the `Result` flowing: `do_thing().log_err("during X")?`.
+    fn log_err<C: Display>(self, context: C) -> Self;
+}
+
+impl<T, E: Display> EnhancedLog for Result<T, E> {
+    fn log_err<C: Display>(self, context: C) -> Self {
+        if let Err(e) = &self {
+            log_error(e, context);
+        }
+        self
+    }
+}
+
+impl<T, E: Error + Send + Sync + 'static> EnhancedContext<T> for Result<T, E> {
+    fn context<C: Display>(self, msg: C) -> Result<T, BusyError> {
+        self.with_context(|| msg)
+    }
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T, BusyError> {
+        self.map_err(|e| BusyError {
+            context: f().to_string(),
+            source: Some(Box::new(e)),
+        })
+    }
+}
+
+impl<T> EnhancedContext<T> for Option<T> {
+    fn context<C: Display>(self, msg: C) -> Result<T, BusyError> {
+        self.with_context(|| msg)
+    }
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T, BusyError> {
+        self.ok_or_else(|| BusyError {
+            context: f().to_string(),
+            source: None,
+        })
+    }
+}
+
 impl<T, E: Display> EnhancedUnwrap<T> for Result<T, E> {
     #[inline]
     fn unwp(self) -> T {
@@ -92,6 +165,125 @@ pub fn some_ctx<T>(option: Option<T>, msg: &str) -> T {
     }
 }
 
+/// Like `?` but logs the error with `context` and a backtrace first, for handlers that should
+/// report a failure to the caller instead of panicking with [`EnhancedUnwrap::unwp`].
+///
+/// ```rust,ignore
+/// fn load(path: &str) -> Result<Config, Box<dyn Error>> {
+///     let content = try_or_log!(fs::read_to_string(path), "reading config");
+///     Ok(try_or_log!(serde_json::from_str(&content), format!("parsing {}", path)))
+/// }
+/// ```
+#[macro_export]
+macro_rules! try_or_log {
+    ($expr:expr, $context:expr $(,)?) => {
+        match $expr {
+            Ok(value) => value,
+            Err(e) => {
+                $crate::prelude::log_error(&e, $context);
+                return Err(::std::convert::From::from(e));
+            }
+        }
+    };
+    ($expr:expr $(,)?) => {
+        $crate::try_or_log!($expr, "")
+    };
+}
+
+pub use crate::try_or_log;
+
+/// Log `err` with `context` and a backtrace as separate fields, used by [`try_or_log!`].
+#[doc(hidden)]
+pub fn log_error<E: Display, C: Display>(err: &E, context: C) {
+    let back_trace = Backtrace::force_capture().to_simple_string();
+    error!(
+        error = %err,
+        context = %context,
+        backtrace = %back_trace,
+        "operation failed"
+    );
+}
+
+/// Run `f` and turn a panic into a logged `Err`, the recovery boundary of worker threads and
+/// tasks that should report a failure instead of dying silently. The [`BusyError::context`] of
+/// the error is the panic message. The backtrace of the panic is logged too when
+/// [`install_panic_hook`] is installed, it is gone once the panic is caught. `f` is assumed to
+/// be unwind safe, state it shares may be left half updated by the panic.
+///
+/// ```rust,ignore
+/// thread::spawn(|| loop {
+///     if let Err(e) = run_logged(|| process(queue.pop())) {
+///         metrics.incr("worker.panics");
+///     }
+/// });
+/// ```
+pub fn run_logged<T, F: FnOnce() -> T>(f: F) -> Result<T, BusyError> {
+    PANIC_BACKTRACE.with(|back_trace| back_trace.take());
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let msg = panic_message(payload.as_ref());
+        let back_trace = PANIC_BACKTRACE.with(|back_trace| back_trace.take());
+        error!(
+            error = %msg,
+            backtrace = back_trace.as_deref(),
+            "panic caught"
+        );
+        BusyError {
+            context: msg,
+            source: None,
+        }
+    })
+}
+
+thread_local! {
+    /// the backtrace of the last panic of the thread, captured by [`install_panic_hook`] for
+    /// [`run_logged`]
+    static PANIC_BACKTRACE: std::cell::Cell<Option<String>> = const { std::cell::Cell::new(None) };
+}
+
+/// Replace the panic hook so every panic, not only the ones of [`EnhancedUnwrap::unwp`], is
+/// logged through `tracing` as an ERROR with the panic message, location and trimmed backtrace
+/// as separate fields, consistent with the rest of the logs. Call it once at startup after
+/// installing the logger, when the subscriber does not log ERROR for `busylib`, like the one of
+/// `init_logger("my_app", &[], ..)`, or there is none, the panic is printed to stderr instead so
+/// it is never lost.
+///
+/// ```rust,ignore
+/// let (_guard, _handle) = init_logger("my_app", &[], false, None);
+/// install_panic_hook();
+/// ```
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let msg = panic_message(info.payload());
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+        let back_trace = Backtrace::force_capture().to_simple_string();
+        error!(
+            error = %msg,
+            location = %location,
+            backtrace = %back_trace,
+            "panicked"
+        );
+        // the target of the event above
+        if !tracing::enabled!(tracing::Level::ERROR) {
+            eprintln!("panicked at {}: {}\n{}", location, msg, back_trace);
+        }
+        PANIC_BACKTRACE.with(|panic_back_trace| panic_back_trace.set(Some(back_trace)));
+    }));
+}
+
+/// return the message of a `panic!` payload
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None => "panic with a non-string payload".to_string(),
+        },
+    }
+}
+
 #[inline]
 fn log_and_panic<E: Display>(err: Option<E>, msg: &str) -> ! {
     let err_msg = match err {
@@ -99,12 +291,239 @@ fn log_and_panic<E: Display>(err: Option<E>, msg: &str) -> ! {
         None => "".to_string(),
     };
 
-    let info = format!(
+    let back_trace = Backtrace::force_capture().to_simple_string();
+    // separate fields so they become separate keys in the JSON logs
+    error!(
+        error = %err_msg,
+        context = %msg,
+        backtrace = %back_trace,
+        "this should never happen"
+    );
+    panic!(
         "this should never happen: {}, context: {}, back_trace: {}",
-        err_msg,
-        msg,
-        Backtrace::force_capture().to_simple_string()
+        err_msg, msg, back_trace
     );
-    error!("{}", info);
-    panic!("{}", info);
+}
+
+#[cfg(test)]
+mod test {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::Layer;
+
+    use crate::prelude::{install_panic_hook, run_logged, EnhancedContext};
+    #[cfg(feature = "logger")]
+    use crate::prelude::{EnhancedExpect, EnhancedLog};
+
+    #[cfg(feature = "logger")]
+    fn parse_port(s: &str) -> Result<u16, Box<dyn std::error::Error>> {
+        let port: u16 = crate::try_or_log!(s.parse(), format!("parsing port {:?}", s));
+        Ok(port + 1)
+    }
+
+    #[cfg(feature = "logger")]
+    #[test]
+    fn test_try_or_log() {
+        let logs = crate::logger::capture();
+        assert_eq!(parse_port("8080").unwrap(), 8081);
+        assert!(logs.lines().is_empty());
+
+        let err = parse_port("http").unwrap_err();
+        assert_eq!(err.to_string(), "invalid digit found in string");
+
+        let entries = logs.entries();
+        assert_eq!(entries.len(), 1);
+        let fields = &entries[0].fields;
+        assert_eq!(entries[0].message, "operation failed");
+        assert_eq!(fields["error"], "invalid digit found in string");
+        assert_eq!(fields["context"], "parsing port \"http\"");
+        assert!(fields.contains_key("backtrace"));
+    }
+
+    #[cfg(feature = "logger")]
+    #[test]
+    fn test_log_and_panic_fields() {
+        let logs = crate::logger::capture();
+        let result = std::panic::catch_unwind(|| Err::<(), _>("boom").ex("loading config"));
+        let panic = result.unwrap_err();
+        let panic_msg = panic.downcast_ref::<String>().unwrap();
+        assert!(panic_msg.starts_with("this should never happen: boom, context: loading config"));
+
+        let entries = logs.entries();
+        assert_eq!(entries.len(), 1);
+        let fields = &entries[0].fields;
+        assert_eq!(entries[0].message, "this should never happen");
+        assert_eq!(fields["error"], "boom");
+        assert_eq!(fields["context"], "loading config");
+        assert!(fields.contains_key("backtrace"));
+    }
+
+    #[cfg(feature = "logger")]
+    #[test]
+    fn test_log_err() {
+        let logs = crate::logger::capture();
+        let ok: Result<Vec<u16>, String> = Ok(vec![80, 443]);
+        assert_eq!(ok.log_err("parsing ports"), Ok(vec![80, 443]));
+        assert!(logs.lines().is_empty());
+
+        let err: Result<Vec<u16>, String> = Err("boom".to_string());
+        assert_eq!(
+            err.log_err(format!("parsing {}", "ports")),
+            Err("boom".to_string())
+        );
+
+        let entries = logs.entries();
+        assert_eq!(entries.len(), 1);
+        let fields = &entries[0].fields;
+        assert_eq!(entries[0].message, "operation failed");
+        assert_eq!(fields["error"], "boom");
+        assert_eq!(fields["context"], "parsing ports");
+        assert!(fields.contains_key("backtrace"));
+    }
+
+    const PANIC_HOOK_CHILD: &str = "BUSYLIB_PANIC_HOOK_CHILD";
+
+    /// the panicking half of [`test_panic_hook`], run in a subprocess as the hook is global
+    #[test]
+    fn test_panic_hook_child() {
+        let Ok(child) = std::env::var(PANIC_HOOK_CHILD) else {
+            return;
+        };
+        let app_only =
+            tracing_subscriber::filter::Targets::new().with_target("my_app", tracing::Level::INFO);
+        tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(std::io::stdout)
+                    .with_filter(match child.as_str() {
+                        "app_only" => app_only,
+                        _ => app_only.with_default(tracing::Level::TRACE),
+                    }),
+            )
+            .init();
+        install_panic_hook();
+        assert!(run_logged(|| panic!("boom in run_logged")).is_err());
+        panic!("boom in {}", "child");
+    }
+
+    #[test]
+    fn test_panic_hook() {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "prelude::test::test_panic_hook_child",
+                "--nocapture",
+            ])
+            .env(PANIC_HOOK_CHILD, "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let line = stdout
+            .lines()
+            .find(|line| line.contains("panic caught"))
+            .unwrap_or_else(|| panic!("no run_logged log line in {:?}", stdout));
+        assert!(line.contains("error=boom in run_logged"), "{}", line);
+        assert!(line.contains("backtrace="), "{}", line);
+        let line = stdout
+            .lines()
+            .find(|line| line.contains("panicked") && line.contains("boom in child"))
+            .unwrap_or_else(|| panic!("no panic log line in {:?}", stdout));
+        assert!(line.contains("ERROR"), "{}", line);
+        assert!(line.contains("error=boom in child"), "{}", line);
+        assert!(line.contains("location=src/prelude.rs:"), "{}", line);
+        assert!(line.contains("backtrace="), "{}", line);
+        // the default hook did not run
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("thread '"), "{}", stderr);
+
+        // busylib filtered out
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "prelude::test::test_panic_hook_child",
+                "--nocapture",
+            ])
+            .env(PANIC_HOOK_CHILD, "app_only")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("panicked at src/prelude.rs:") && stderr.contains("boom in child"),
+            "{}",
+            stderr
+        );
+    }
+
+    #[cfg(feature = "logger")]
+    #[test]
+    fn test_run_logged() {
+        let logs = crate::logger::capture();
+        assert_eq!(run_logged(|| 42).unwrap(), 42);
+        assert!(logs.lines().is_empty());
+
+        let port = "http";
+        let err = run_logged(|| -> u16 { panic!("invalid port {:?}", port) }).unwrap_err();
+        assert_eq!(err.context(), "invalid port \"http\"");
+        let err = run_logged(|| std::panic::panic_any(42)).unwrap_err();
+        assert_eq!(err.context(), "panic with a non-string payload");
+
+        let entries = logs.entries();
+        assert_eq!(entries.len(), 2);
+        let fields = &entries[0].fields;
+        assert_eq!(entries[0].message, "panic caught");
+        assert_eq!(fields["error"], "invalid port \"http\"");
+        assert_eq!(
+            entries[1].fields["error"],
+            "panic with a non-string payload"
+        );
+        // only captured by the panic hook
+        assert!(!fields.contains_key("backtrace"));
+    }
+
+    fn read_port(path: &str) -> Result<u16, crate::errors::BusyError> {
+        let content = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+        let port = content.trim().parse::<u16>().context("parsing port")?;
+        Ok(port)
+    }
+
+    #[test]
+    fn test_context() {
+        use std::error::Error;
+
+        let path = std::env::temp_dir().join(format!("busylib_context_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let err = read_port(path).unwrap_err();
+        assert_eq!(err.context(), format!("reading {}", path));
+        let source = err.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        assert!(err.to_string().starts_with(&format!("reading {}: ", path)));
+
+        std::fs::write(path, "http").unwrap();
+        let err = read_port(path).unwrap_err();
+        assert_eq!(err.context(), "parsing port");
+        assert!(err
+            .source()
+            .unwrap()
+            .downcast_ref::<std::num::ParseIntError>()
+            .is_some());
+        assert_eq!(
+            err.to_string(),
+            "parsing port: invalid digit found in string"
+        );
+
+        std::fs::write(path, "8080\n").unwrap();
+        assert_eq!(read_port(path).unwrap(), 8080);
+        std::fs::remove_file(path).unwrap();
+
+        let err = None::<u16>.context("missing port").unwrap_err();
+        assert_eq!(err.to_string(), "missing port");
+        assert!(err.source().is_none());
+    }
 }
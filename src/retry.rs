@@ -0,0 +1,192 @@
+//! Retry any fallible async operation with exponential backoff.
+
+use std::future::Future;
+use std::time::Duration;
+
+use tracing::debug;
+
+/// How often and how fast [`retry`] repeats a failed operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// retries after the first attempt, `0` runs the operation once
+    pub max_retries: u32,
+    /// wait before the first retry
+    pub initial_backoff: Duration,
+    /// upper bound of the wait between two attempts
+    pub max_backoff: Duration,
+    /// factor applied to the wait after each retry
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    /// 3 retries waiting 100ms, 200ms and 400ms, capped at 10s.
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// return the wait before retry number `retry`, starting at 0
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(retry as i32);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Run `operation` until it succeeds or `policy.max_retries` is exhausted, retrying every error.
+///
+/// ```rust,ignore
+/// let body = retry(&RetryPolicy::default(), || async {
+///     client.get(url).send().await?.text().await
+/// })
+/// .await?;
+/// ```
+pub async fn retry<T, E, Fut, Op>(policy: &RetryPolicy, operation: Op) -> Result<T, E>
+where
+    Op: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_if(policy, |_: &E| true, operation).await
+}
+
+/// [`retry`] only the errors for which `retryable` returns `true`, any other error is
+/// returned immediately.
+pub async fn retry_if<T, E, Fut, Op, C>(
+    policy: &RetryPolicy,
+    retryable: C,
+    operation: Op,
+) -> Result<T, E>
+where
+    Op: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    C: Fn(&E) -> bool,
+{
+    retry_notify(policy, retryable, |_: &E, _, _| {}, operation).await
+}
+
+/// [`retry_if`] calling `notify` with the error, the retry number starting at 1 and the backoff
+/// before each retry, to log or count them.
+///
+/// ```rust,ignore
+/// let body = retry_notify(
+///     &RetryPolicy::default(),
+///     |e: &Error| e.is_transient(),
+///     |e, retry, backoff| warn!(retry, ?backoff, error = %e, "fetch failed, retrying"),
+///     || fetch(url),
+/// )
+/// .await?;
+/// ```
+pub async fn retry_notify<T, E, Fut, Op, C, N>(
+    policy: &RetryPolicy,
+    retryable: C,
+    mut notify: N,
+    mut operation: Op,
+) -> Result<T, E>
+where
+    Op: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    C: Fn(&E) -> bool,
+    N: FnMut(&E, u32, Duration),
+{
+    let mut retries = 0;
+    loop {
+        match operation().await {
+            Ok(v) => return Ok(v),
+            Err(e) if retries < policy.max_retries && retryable(&e) => {
+                let backoff = policy.backoff(retries);
+                retries += 1;
+                debug!(retry = retries, ?backoff, "operation failed, retrying");
+                notify(&e, retries, backoff);
+                tokio::time::sleep(backoff).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use super::{retry, retry_if, retry_notify, RetryPolicy};
+
+    fn fast_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            multiplier: 2.0,
+        }
+    }
+
+    async fn fail_times(calls: &AtomicU32, failures: u32) -> Result<u32, String> {
+        let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+        if call <= failures {
+            Err(format!("failure {}", call))
+        } else {
+            Ok(call)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let calls = AtomicU32::new(0);
+        let result = retry(&fast_policy(3), || fail_times(&calls, 2)).await;
+        assert_eq!(result, Ok(3));
+
+        let calls = AtomicU32::new(0);
+        let result = retry(&fast_policy(3), || fail_times(&calls, 10)).await;
+        assert_eq!(result, Err("failure 4".to_string()));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_retry_if() {
+        let calls = AtomicU32::new(0);
+        let result = retry_if(
+            &fast_policy(3),
+            |e: &String| e != "failure 2",
+            || fail_times(&calls, 5),
+        )
+        .await;
+        assert_eq!(result, Err("failure 2".to_string()));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_notify() {
+        let calls = AtomicU32::new(0);
+        let mut notified = Vec::new();
+        let result = retry_notify(
+            &fast_policy(3),
+            |_: &String| true,
+            |e, retry, backoff| notified.push((e.clone(), retry, backoff)),
+            || fail_times(&calls, 2),
+        )
+        .await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(
+            notified,
+            vec![
+                ("failure 1".to_string(), 1, Duration::from_millis(1)),
+                ("failure 2".to_string(), 2, Duration::from_millis(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,135 @@
+//! Run async closures on cron schedules for periodic work like cache refreshes or heartbeats,
+//! on the same `tokio_cron_scheduler` engine `logger::LogCleaner` schedules its cleanups with.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio_cron_scheduler::{Job, JobScheduler};
+use uuid::Uuid;
+
+use crate::errors::ScheduleError;
+
+/// A started cron scheduler, every job added with [`Scheduler::every`] runs on the tokio
+/// runtime until cancelled or the scheduler is shut down. Clones share the same jobs.
+///
+/// ```rust,ignore
+/// let scheduler = Scheduler::new().await?;
+/// // every 30 seconds
+/// let job = scheduler.every("*/30 * * * * *", || async { refresh_cache().await }).await?;
+/// // later
+/// job.cancel().await?;
+/// ```
+#[derive(Clone)]
+pub struct Scheduler {
+    sched: JobScheduler,
+}
+
+impl Scheduler {
+    /// return a started scheduler without jobs
+    pub async fn new() -> Result<Self, ScheduleError> {
+        let sched = JobScheduler::new().await?;
+        sched.start().await?;
+        Ok(Scheduler { sched })
+    }
+
+    /// Run `task` on the cron schedule `cron_expr`: `sec min hour day-of-month month day-of-week`
+    /// with an optional trailing year, like `0 15 6 * * *` every day at 06:15:00 or
+    /// `0 */5 * * * *` every five minutes. A run that takes longer than the interval overlaps
+    /// the next one. If `cron_expr` is invalid return Err.
+    pub async fn every<F, Fut>(&self, cron_expr: &str, task: F) -> Result<JobHandle, ScheduleError>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task = Arc::new(task);
+        let job = Job::new_async(cron_expr, move |_, _| {
+            let task = task.clone();
+            Box::pin(async move { task().await })
+        })
+        .map_err(|e| ScheduleError {
+            details: format!("invalid cron expression {:?}: {}", cron_expr, e),
+        })?;
+        let id = self.sched.add(job).await?;
+        Ok(JobHandle {
+            id,
+            sched: self.sched.clone(),
+        })
+    }
+
+    /// Stop the scheduler, no job runs after this returns.
+    pub async fn shutdown(mut self) -> Result<(), ScheduleError> {
+        self.sched.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Returned by [`Scheduler::every`] to cancel the job, dropping it keeps the job running.
+#[derive(Clone)]
+pub struct JobHandle {
+    id: Uuid,
+    sched: JobScheduler,
+}
+
+impl JobHandle {
+    /// return the id of the job in its scheduler
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Remove the job from its scheduler, a run in progress is not interrupted.
+    pub async fn cancel(&self) -> Result<(), ScheduleError> {
+        Ok(self.sched.remove(&self.id).await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::Scheduler;
+
+    #[tokio::test]
+    async fn test_every_and_cancel() {
+        let scheduler = Scheduler::new().await.unwrap();
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let job = scheduler
+            .every("* * * * * *", move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .await
+            .unwrap();
+
+        for _ in 0..50 {
+            if runs.load(Ordering::SeqCst) >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+
+        job.cancel().await.unwrap();
+        // a run may already be in flight when cancelling
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let cancelled_at = runs.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), cancelled_at);
+        scheduler.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_cron() {
+        let scheduler = Scheduler::new().await.unwrap();
+        let err = scheduler
+            .every("every minute", || async {})
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("every minute"), "{}", err);
+    }
+}
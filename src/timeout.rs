@@ -0,0 +1,46 @@
+//! Bound the run time of futures with a crate error instead of `tokio::time::error::Elapsed`.
+
+use std::future::Future;
+use std::time::Duration;
+
+use crate::errors::TimeoutError;
+
+/// Wait at most `duration` for `fut`, the error converts into [`crate::errors::HttpError`]
+/// so it can be `?`-ed next to http calls.
+///
+/// ```rust,ignore
+/// let response = with_timeout(Duration::from_secs(3), client.get(url).send()).await??;
+/// ```
+pub async fn with_timeout<F: Future>(
+    duration: Duration,
+    fut: F,
+) -> Result<F::Output, TimeoutError> {
+    tokio::time::timeout(duration, fut)
+        .await
+        .map_err(|_| TimeoutError { duration })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::errors::HttpError;
+
+    use super::with_timeout;
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let v = with_timeout(Duration::from_secs(1), async { 42 }).await;
+        assert_eq!(v.unwrap(), 42);
+
+        let err = with_timeout(
+            Duration::from_millis(10),
+            tokio::time::sleep(Duration::from_secs(5)),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.duration(), Duration::from_millis(10));
+        assert_eq!(err.to_string(), "operation timed out after 10ms");
+        assert!(matches!(HttpError::from(err), HttpError::Timeout(_)));
+    }
+}